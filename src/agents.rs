@@ -0,0 +1,224 @@
+use anyhow::Result;
+use std::fmt;
+
+use crate::settings::{AgentSettings, Settings};
+
+/// Agent started when neither `--agent` nor the `agent` setting is given.
+pub const DEFAULT_AGENT: &str = "claude";
+
+/// An agent CLI that can run in the sandbox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agent {
+    pub name: String,
+    pub display_name: String,
+    pub binary: String,
+    pub install: Option<String>,
+    pub config_dirs: Vec<String>,
+    pub skip_permission_flag: Option<String>,
+    pub continue_args: Option<String>,
+    pub headless_args: Option<String>,
+}
+
+impl Agent {
+    /// Executable started in the sandbox.
+    pub fn command(&self) -> &str {
+        &self.binary
+    }
+
+    /// Arguments that make the agent resume its previous conversation, placed
+    /// right after the binary so they may be a flag or a subcommand.
+    pub fn continue_args(&self) -> Result<&str> {
+        self.continue_args.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} cannot resume a previous conversation; run without --continue or set agents.{}.continue_args in settings",
+                self, self.name
+            )
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn builtin(
+        name: &str,
+        display_name: &str,
+        binary: &str,
+        install: &str,
+        config_dirs: &[&str],
+        skip_permission_flag: Option<&str>,
+        continue_args: Option<&str>,
+        headless_args: &str,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            display_name: display_name.to_string(),
+            binary: binary.to_string(),
+            install: Some(install.to_string()),
+            config_dirs: config_dirs.iter().map(|d| d.to_string()).collect(),
+            skip_permission_flag: skip_permission_flag.map(str::to_string),
+            continue_args: continue_args.map(str::to_string),
+            headless_args: Some(headless_args.to_string()),
+        }
+    }
+
+    fn apply(&mut self, entry: &AgentSettings) {
+        let flag = |value: &Option<String>, current: &mut Option<String>| {
+            if let Some(value) = value {
+                *current = Some(value.clone()).filter(|v| !v.trim().is_empty());
+            }
+        };
+        if let Some(display_name) = &entry.display_name {
+            self.display_name = display_name.clone();
+        }
+        if let Some(binary) = &entry.binary {
+            self.binary = binary.clone();
+        }
+        flag(&entry.install, &mut self.install);
+        if let Some(config_dirs) = &entry.config_dirs {
+            self.config_dirs = config_dirs.clone();
+        }
+        flag(&entry.skip_permission_flag, &mut self.skip_permission_flag);
+        flag(&entry.continue_args, &mut self.continue_args);
+        flag(&entry.headless_args, &mut self.headless_args);
+    }
+}
+
+impl fmt::Display for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.display_name)
+    }
+}
+
+/// The agents that can be selected with `--agent`: the built-in ones plus
+/// those declared under `agents` in settings.
+#[derive(Debug, Clone)]
+pub struct AgentRegistry {
+    agents: Vec<Agent>,
+}
+
+impl AgentRegistry {
+    pub fn builtin() -> Self {
+        Self {
+            agents: vec![
+                Agent::builtin(
+                    "claude",
+                    "Claude",
+                    "claude",
+                    "npm install -g @anthropic-ai/claude-code",
+                    &[],
+                    Some("--dangerously-skip-permissions"),
+                    Some("--continue"),
+                    "-p {prompt}",
+                ),
+                Agent::builtin(
+                    "gemini",
+                    "Gemini",
+                    "gemini",
+                    "npm install -g @google/gemini-cli",
+                    &["gemini"],
+                    Some("--yolo"),
+                    None,
+                    "-p {prompt}",
+                ),
+                Agent::builtin(
+                    "codex",
+                    "Codex",
+                    "codex",
+                    "npm install -g @openai/codex",
+                    &[],
+                    None,
+                    Some("resume --last"),
+                    "exec {prompt}",
+                ),
+                Agent::builtin(
+                    "qwen",
+                    "Qwen",
+                    "qwen",
+                    "npm install -g @qwen-code/qwen-code@latest",
+                    &["qwen"],
+                    Some("--yolo"),
+                    None,
+                    "-p {prompt}",
+                ),
+                Agent::builtin(
+                    "cursor",
+                    "Cursor",
+                    "cursor-agent",
+                    "curl https://cursor.com/install -fsS | bash",
+                    &["cursor"],
+                    None,
+                    Some("resume"),
+                    "-p {prompt}",
+                ),
+            ],
+        }
+    }
+
+    /// The built-in agents with the `agents` setting applied. The
+    /// `skip_permission_flags` map still applies to agents whose entry does
+    /// not set `skip_permission_flag`.
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let mut registry = Self::builtin();
+        for (name, entry) in settings.agents.iter() {
+            if name.trim().is_empty() {
+                anyhow::bail!("agents: agent names may not be empty");
+            }
+            match registry.agents.iter_mut().find(|a| a.name == *name) {
+                Some(agent) => agent.apply(entry),
+                None => {
+                    let mut agent = Agent {
+                        name: name.clone(),
+                        display_name: name.clone(),
+                        binary: name.clone(),
+                        install: None,
+                        config_dirs: Vec::new(),
+                        skip_permission_flag: None,
+                        continue_args: None,
+                        headless_args: None,
+                    };
+                    agent.apply(entry);
+                    registry.agents.push(agent);
+                }
+            }
+        }
+        for agent in registry.agents.iter_mut() {
+            let configured = settings
+                .agents
+                .get(&agent.name)
+                .is_some_and(|entry| entry.skip_permission_flag.is_some());
+            if !configured {
+                if let Some(flag) = settings.skip_permission_flag(&agent.binary) {
+                    agent.skip_permission_flag = Some(flag.to_string());
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    /// Look up an agent by name, or by binary for containers whose labels
+    /// predate the registry.
+    pub fn get(&self, name: &str) -> Option<&Agent> {
+        self.agents
+            .iter()
+            .find(|a| a.name.eq_ignore_ascii_case(name))
+            .or_else(|| self.agents.iter().find(|a| a.binary == name))
+    }
+
+    /// The agent called `name`, or the default agent when `name` is `None`.
+    pub fn resolve(&self, name: Option<&str>) -> Result<Agent> {
+        let name = name.unwrap_or(DEFAULT_AGENT);
+        self.get(name).cloned().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown agent '{}'; available agents: {}",
+                name,
+                self.names().join(", ")
+            )
+        })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.agents.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Agent> {
+        self.agents.iter()
+    }
+}
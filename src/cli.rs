@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{Map, Value};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -22,10 +23,11 @@ pub struct Cli {
 
     #[arg(
         long = "add_dir",
-        value_name = "DIR",
-        help = "Additional directory to mount read-only inside the container"
+        global = true,
+        value_name = "DIR[:CONTAINER_PATH][:ro|rw]",
+        help = "Additional directory to mount inside the container (read-only unless :rw); repeatable"
     )]
-    pub add_dir: Option<PathBuf>,
+    pub add_dir: Vec<String>,
 
     #[arg(
         long = "worktree",
@@ -42,16 +44,94 @@ pub struct Cli {
 
     #[arg(
         long,
+        global = true,
+        help = "Mount the project read-only and keep the agent's changes in the container until `codesandbox apply`"
+    )]
+    pub isolated: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "AGENT",
+        help = "Agent to start in the container (claude, gemini, codex, qwen, cursor or one from the `agents` setting); defaults to the configured agent or claude"
+    )]
+    pub agent: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "RUNTIME",
+        value_parser = ["docker", "podman", "nerdctl"],
+        help = "Container runtime to use (docker, podman, nerdctl); overrides settings.json"
+    )]
+    pub runtime: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "CPUS",
+        help = "Limit the number of CPUs the sandbox may use (e.g. 2 or 1.5)"
+    )]
+    pub cpus: Option<f64>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "SIZE",
+        help = "Limit the sandbox memory (e.g. 4g or 512m)"
+    )]
+    pub memory: Option<String>,
+
+    #[arg(
+        long = "pids-limit",
+        global = true,
+        value_name = "N",
+        help = "Limit the number of processes in the sandbox"
+    )]
+    pub pids_limit: Option<u64>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "SIZE",
+        help = "Limit the sandbox's writable storage (e.g. 20G); needs runtime support"
+    )]
+    pub storage: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "POLICY",
+        value_parser = ["full", "allowlist", "none"],
+        help = "Network access for the sandbox: full, allowlist (via the egress proxy) or none"
+    )]
+    pub network: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
         value_enum,
-        default_value_t = Agent::Claude,
-        help = "Agent to start in the container (claude, gemini, codex, qwen, cursor)",
+        default_value_t = OutputFormat::Table,
+        help = "Output format for ls, ps and the container lifecycle commands"
     )]
-    pub agent: Agent,
+    pub format: OutputFormat,
+
+    #[arg(long, global = true, help = "Shorthand for --format json")]
+    pub json: bool,
 
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text; `ls` and `ps` prompt for a container to attach to
+    #[default]
+    Table,
+    /// One JSON document on stdout and no prompts; progress goes to stderr
+    Json,
+}
+
 #[derive(Subcommand, Clone)]
 pub enum Commands {
     #[command(about = "List containers for this directory and optionally attach to one")]
@@ -64,52 +144,309 @@ pub enum Commands {
     Serve {
         #[arg(short = 'd', long = "daemon", help = "Run server in the background")]
         daemon: bool,
+        #[command(flatten)]
+        listen: ServerArgs,
+    },
+    #[command(
+        about = "Create a sandbox, run the agent on a prompt without interaction and report the result"
+    )]
+    Run {
+        #[arg(
+            long,
+            value_name = "TEXT",
+            conflicts_with = "prompt_file",
+            required_unless_present = "prompt_file",
+            help = "Prompt to give the agent"
+        )]
+        prompt: Option<String>,
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Read the prompt from FILE (`-` for stdin)"
+        )]
+        prompt_file: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Where to write the agent's output (defaults to runs/<container>.log next to settings.json)"
+        )]
+        transcript: Option<PathBuf>,
+        #[arg(long, help = "Remove the container once the agent has exited")]
+        rm: bool,
+    },
+    #[command(about = "Attach to a container, starting it if needed")]
+    Attach {
+        #[arg(
+            value_name = "CONTAINER",
+            help = "Container name or its number in `ls`"
+        )]
+        container: String,
+    },
+    #[command(about = "Run a command in a running container")]
+    Exec {
+        #[arg(
+            value_name = "CONTAINER",
+            help = "Container name or its number in `ls`"
+        )]
+        container: String,
+        #[arg(
+            value_name = "COMMAND",
+            required = true,
+            last = true,
+            help = "Command to run, after `--`"
+        )]
+        command: Vec<String>,
+    },
+    #[command(about = "Start a stopped container without attaching")]
+    Start {
+        #[arg(
+            value_name = "CONTAINER",
+            help = "Container name or its number in `ls`"
+        )]
+        container: String,
+    },
+    #[command(about = "Stop a container, or the API server when no container is given")]
+    Stop {
+        #[arg(
+            value_name = "CONTAINER",
+            help = "Container name or its number in `ls`"
+        )]
+        container: Option<String>,
+    },
+    #[command(about = "Remove a container")]
+    Rm {
+        #[arg(
+            value_name = "CONTAINER",
+            help = "Container name or its number in `ls`"
+        )]
+        container: String,
     },
-    #[command(about = "Stop the running Code Sandbox API server")]
-    Stop,
+    #[command(about = "Show the output of a container")]
+    Logs {
+        #[arg(
+            value_name = "CONTAINER",
+            help = "Container name or its number in `ls`"
+        )]
+        container: String,
+        #[arg(short = 'f', long, help = "Keep streaming new output")]
+        follow: bool,
+        #[arg(long, value_name = "N", help = "Only show the last N lines")]
+        tail: Option<String>,
+    },
+    #[command(about = "Print a shell completion script")]
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+    #[command(name = "__containers", hide = true)]
+    ContainerNames,
     #[command(about = "Restart the Code Sandbox API server")]
     Restart {
         #[arg(short = 'd', long = "daemon", help = "Run server in the background")]
         daemon: bool,
+        #[command(flatten)]
+        listen: ServerArgs,
+    },
+    #[command(about = "Inspect Code Sandbox configuration")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    #[command(about = "Run the egress proxy used by sandboxes with the allowlist network policy")]
+    Proxy {
+        #[arg(
+            long,
+            help = "Port to listen on (defaults to network.proxy_port or 6790)"
+        )]
+        port: Option<u16>,
+    },
+    #[command(about = "Show the changes made in the isolated container for this directory")]
+    Diff {
+        #[arg(long, help = "Only list the changed paths")]
+        name_only: bool,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Container to compare (defaults to the newest isolated one)"
+        )]
+        container: Option<String>,
+    },
+    #[command(
+        about = "Copy changes from the isolated container for this directory back to the host"
+    )]
+    Apply {
+        #[arg(
+            value_name = "PATH",
+            help = "Only apply changes at or below these project paths"
+        )]
+        paths: Vec<PathBuf>,
+        #[arg(long, help = "List what would be applied without changing any files")]
+        dry_run: bool,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Container to copy from (defaults to the newest isolated one)"
+        )]
+        container: Option<String>,
+    },
+    #[command(about = "Manage the encrypted secrets store")]
+    Secrets {
+        #[command(subcommand)]
+        command: SecretsCommand,
+    },
+    #[command(about = "Manage the sandbox images")]
+    Image {
+        #[command(subcommand)]
+        command: ImageCommand,
     },
 }
 
-#[derive(ValueEnum, Clone, Debug)]
-pub enum Agent {
-    Claude,
-    Gemini,
-    Codex,
-    Qwen,
-    Cursor,
+/// Where `serve` and `restart` listen; overrides the `server` setting.
+#[derive(Args, Clone, Debug, Default, PartialEq)]
+pub struct ServerArgs {
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        help = "Address to listen on (defaults to server.bind or 127.0.0.1:6789)"
+    )]
+    pub bind: Option<String>,
+    #[arg(
+        long,
+        help = "Serve HTTPS, with a self-signed certificate unless --tls-cert and --tls-key are given"
+    )]
+    pub tls: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_key",
+        help = "PEM certificate chain for HTTPS"
+    )]
+    pub tls_cert: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        help = "PEM private key for HTTPS"
+    )]
+    pub tls_key: Option<PathBuf>,
 }
 
-impl Agent {
-    pub fn command(&self) -> &'static str {
-        match self {
-            Agent::Claude => "claude",
-            Agent::Gemini => "gemini",
-            Agent::Codex => "codex",
-            Agent::Qwen => "qwen",
-            Agent::Cursor => "cursor-agent",
-        }
-    }
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
 }
 
-impl std::fmt::Display for Agent {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = match self {
-            Agent::Claude => "Claude",
-            Agent::Gemini => "Gemini",
-            Agent::Codex => "Codex",
-            Agent::Qwen => "Qwen",
-            Agent::Cursor => "Cursor",
-        };
-        write!(f, "{}", name)
-    }
+#[derive(Subcommand, Clone)]
+pub enum ConfigCommand {
+    #[command(
+        about = "Print the effective settings for this directory and where each value came from"
+    )]
+    Show,
+    #[command(
+        about = "Check settings.json and .codesandbox.toml for syntax errors and unknown keys"
+    )]
+    Validate,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum SecretsCommand {
+    #[command(about = "Store a secret, reading its value from stdin")]
+    Set {
+        #[arg(value_name = "KEY")]
+        key: String,
+    },
+    #[command(about = "Remove a secret from the store")]
+    Rm {
+        #[arg(value_name = "KEY")]
+        key: String,
+    },
+    #[command(about = "List the keys in the store (values are never shown)")]
+    List,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ImageCommand {
+    #[command(about = "Rebuild the image for this directory without using the build cache")]
+    Rebuild,
+    #[command(about = "Remove sandbox images that no container uses")]
+    Prune,
 }
 
 impl Cli {
     pub fn parse_args() -> Self {
         Self::parse()
     }
+
+    pub fn output_format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            self.format
+        }
+    }
+
+    /// Values given on the command line that take precedence over the project
+    /// and global settings, keyed like `settings.json`.
+    pub fn settings_overrides(&self) -> Map<String, Value> {
+        let mut overrides = Map::new();
+        if let Some(agent) = &self.agent {
+            overrides.insert("agent".to_string(), Value::from(agent.as_str()));
+        }
+        if self.web {
+            overrides.insert("web".to_string(), Value::Bool(true));
+        }
+        if self.isolated {
+            overrides.insert("isolated".to_string(), Value::Bool(true));
+        }
+        if let Some(runtime) = &self.runtime {
+            overrides.insert("runtime".to_string(), Value::from(runtime.as_str()));
+        }
+        let mut limits = Map::new();
+        if let Some(cpus) = self.cpus {
+            limits.insert("cpus".to_string(), Value::from(cpus));
+        }
+        if let Some(memory) = &self.memory {
+            limits.insert("memory".to_string(), Value::from(memory.as_str()));
+        }
+        if let Some(pids_limit) = self.pids_limit {
+            limits.insert("pids_limit".to_string(), Value::from(pids_limit));
+        }
+        if let Some(storage) = &self.storage {
+            limits.insert("storage".to_string(), Value::from(storage.as_str()));
+        }
+        if !limits.is_empty() {
+            overrides.insert("limits".to_string(), Value::Object(limits));
+        }
+        if let Some(policy) = &self.network {
+            let mut network = Map::new();
+            network.insert("policy".to_string(), Value::from(policy.as_str()));
+            overrides.insert("network".to_string(), Value::Object(network));
+        }
+        if let Some(Commands::Serve { listen, .. } | Commands::Restart { listen, .. }) =
+            &self.command
+        {
+            let mut server = Map::new();
+            if let Some(bind) = &listen.bind {
+                server.insert("bind".to_string(), Value::from(bind.as_str()));
+            }
+            if listen.tls || listen.tls_cert.is_some() {
+                server.insert("tls".to_string(), Value::Bool(true));
+            }
+            if let (Some(cert), Some(key)) = (&listen.tls_cert, &listen.tls_key) {
+                // Absolute, so a server started in the background finds them
+                let absolute = |path: &PathBuf| {
+                    let path = std::env::current_dir().unwrap_or_default().join(path);
+                    Value::from(path.display().to_string())
+                };
+                server.insert("tls_cert".to_string(), absolute(cert));
+                server.insert("tls_key".to_string(), absolute(key));
+            }
+            if !server.is_empty() {
+                overrides.insert("server".to_string(), Value::Object(server));
+            }
+        }
+        overrides
+    }
 }
@@ -0,0 +1,70 @@
+use clap::CommandFactory;
+
+use crate::cli::{Cli, CompletionShell};
+
+/// Subcommands whose first argument is a container name.
+const CONTAINER_COMMANDS: &[&str] = &["attach", "exec", "start", "stop", "rm", "logs"];
+
+/// Hidden subcommand printing the names of all sandbox containers, one per
+/// line, for the completion scripts to call.
+pub const CONTAINER_NAMES_COMMAND: &str = "__containers";
+
+fn subcommands() -> String {
+    Cli::command()
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set())
+        .map(|c| c.get_name().to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Completion script for `shell`. Subcommands are completed statically;
+/// container names are looked up when completing.
+pub fn completion_script(shell: CompletionShell) -> String {
+    let subcommands = subcommands();
+    match shell {
+        CompletionShell::Bash => format!(
+            r#"_codesandbox() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{subcommands}" -- "$cur"))
+        return
+    fi
+    case "${{COMP_WORDS[1]}}" in
+        {patterns})
+            if [ "$COMP_CWORD" -eq 2 ]; then
+                COMPREPLY=($(compgen -W "$(codesandbox {names} 2>/dev/null)" -- "$cur"))
+            fi
+            ;;
+    esac
+}}
+complete -o default -F _codesandbox codesandbox
+"#,
+            patterns = CONTAINER_COMMANDS.join("|"),
+            names = CONTAINER_NAMES_COMMAND,
+        ),
+        CompletionShell::Zsh => format!(
+            r#"#compdef codesandbox
+_codesandbox() {{
+    if (( CURRENT == 2 )); then
+        compadd -- {subcommands}
+    elif (( CURRENT == 3 )) && [[ "$words[2]" == ({patterns}) ]]; then
+        compadd -- ${{(f)"$(codesandbox {names} 2>/dev/null)"}}
+    else
+        _files
+    fi
+}}
+compdef _codesandbox codesandbox
+"#,
+            patterns = CONTAINER_COMMANDS.join("|"),
+            names = CONTAINER_NAMES_COMMAND,
+        ),
+        CompletionShell::Fish => format!(
+            r#"complete -c codesandbox -f -n __fish_use_subcommand -a "{subcommands}"
+complete -c codesandbox -f -n "__fish_seen_subcommand_from {commands}" -a "(codesandbox {names} 2>/dev/null)"
+"#,
+            commands = CONTAINER_COMMANDS.join(" "),
+            names = CONTAINER_NAMES_COMMAND,
+        ),
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use crate::agents::Agent;
+use crate::engine::runtime;
+use crate::settings::Settings;
+
+use super::isolation::workspace_diff;
+use super::runtime::build_agent_command;
+
+/// Shell variable holding the prompt when the template refers to `{prompt}`.
+const PROMPT_VAR: &str = "CODESANDBOX_PROMPT";
+
+/// Outcome of `run_headless`.
+#[derive(Debug)]
+pub struct HeadlessRun {
+    pub exit_code: i32,
+    /// Changes the agent made to the project, as a unified diff.
+    pub diff: String,
+}
+
+/// Command that runs `agent` once with the non-interactive arguments in
+/// `headless_args` and exits. The prompt is read from stdin: when the template
+/// contains `{prompt}` it is substituted there, otherwise the agent reads it.
+pub fn build_headless_command(
+    current_dir: &Path,
+    agent: &Agent,
+    headless_args: &str,
+) -> Result<String> {
+    let command = build_agent_command(current_dir, agent, false)?;
+    if headless_args.contains("{prompt}") {
+        let args = headless_args.replace("{prompt}", &format!("\"${}\"", PROMPT_VAR));
+        Ok(format!(
+            "{}=\"$(cat)\" && {} {} 2>&1",
+            PROMPT_VAR, command, args
+        ))
+    } else {
+        Ok(format!("{} {} 2>&1", command, headless_args))
+    }
+}
+
+/// Run the agent in `container_name` on `prompt` and wait for it to exit.
+/// Its output is streamed to stdout and written to `transcript`. The returned
+/// diff covers the changes the agent made: to the container workspace in
+/// isolated mode, otherwise to the host checkout since the run started.
+pub fn run_headless(
+    container_name: &str,
+    current_dir: &Path,
+    agent: &Agent,
+    settings: &Settings,
+    prompt: &str,
+    transcript: &Path,
+) -> Result<HeadlessRun> {
+    let headless_args = agent.headless_args.as_deref().with_context(|| {
+        format!(
+            "{} cannot run headless; set agents.{}.headless_args in settings",
+            agent, agent.name
+        )
+    })?;
+    let command = build_headless_command(current_dir, agent, headless_args)?;
+    let isolated = settings.isolated.unwrap_or(false);
+    // Changes already in the checkout are not the agent's
+    let before = if isolated {
+        None
+    } else {
+        snapshot_tree(current_dir)?
+    };
+
+    if let Some(dir) = transcript.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let mut log = File::create(transcript)
+        .with_context(|| format!("Failed to create {}", transcript.display()))?;
+
+    println!("Running {} in {}...", agent, container_name);
+    let mut child = runtime()
+        .command()
+        .args(["exec", "-i", container_name, "bash", "-c", &command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to start the agent")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(prompt.as_bytes())?;
+    }
+    if let Some(mut output) = child.stdout.take() {
+        let mut stdout = io::stdout();
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = output.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            log.write_all(&buf[..n])?;
+            stdout.write_all(&buf[..n])?;
+            stdout.flush().ok();
+        }
+    }
+    let status = child.wait().context("Failed to wait for the agent")?;
+
+    let diff = if isolated {
+        workspace_diff(container_name, current_dir)?
+    } else if let Some(before) = before {
+        git_diff(current_dir, &before)?
+    } else {
+        String::new()
+    };
+    Ok(HeadlessRun {
+        exit_code: status.code().unwrap_or(1),
+        diff,
+    })
+}
+
+/// Record the working tree of the git checkout at `dir`, untracked files
+/// included, as a tree object without touching the index. `None` when `dir`
+/// is not a git repository.
+pub fn snapshot_tree(dir: &Path) -> Result<Option<String>> {
+    let git_path = git(dir, &["rev-parse", "--git-path", "index"], None)?;
+    if !git_path.status.success() {
+        return Ok(None);
+    }
+    // Start from a copy of the real index so unchanged files are not rehashed
+    let index = dir.join(String::from_utf8_lossy(&git_path.stdout).trim());
+    let scratch = tempfile::tempdir().context("Failed to create a scratch index")?;
+    let scratch_index = scratch.path().join("index");
+    if index.exists() {
+        fs::copy(&index, &scratch_index)
+            .with_context(|| format!("Failed to copy {}", index.display()))?;
+    }
+    let snapshot = |args: &[&str]| -> Result<Output> {
+        let output = git(dir, args, Some(&scratch_index))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to snapshot {}: {}",
+                dir.display(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(output)
+    };
+    snapshot(&["add", "-A"])?;
+    let tree = snapshot(&["write-tree"])?;
+    Ok(Some(String::from_utf8_lossy(&tree.stdout).trim().to_string()))
+}
+
+/// Changes to the git checkout at `dir` since `before` was taken with
+/// `snapshot_tree`, as a unified diff.
+pub fn git_diff(dir: &Path, before: &str) -> Result<String> {
+    let Some(after) = snapshot_tree(dir)? else {
+        return Ok(String::new());
+    };
+    let output = git(dir, &["diff", before, &after], None)?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to diff {}: {}",
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn git(dir: &Path, args: &[&str], index: Option<&Path>) -> Result<Output> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).args(args);
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    command.output().context("Failed to run git")
+}
@@ -0,0 +1,408 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::agents::AgentRegistry;
+use crate::engine::runtime;
+use crate::settings::{settings_file_path, Settings};
+
+pub const IMAGE_REPOSITORY: &str = "codesandbox-image";
+pub const DEFAULT_BASE_IMAGE: &str = "ubuntu:22.04";
+/// Image label holding the hash of the Dockerfile the image was built from.
+pub const LABEL_DOCKERFILE_HASH: &str = "codesandbox.dockerfile-hash";
+/// Registry asked for the latest release of the npm-installed agents.
+const NPM_REGISTRY: &str = "https://registry.npmjs.org";
+
+/// The image a sandbox runs in. Its tag is derived from the Dockerfile, so
+/// projects with different base images or fragments get different images.
+#[derive(Debug, Clone)]
+pub struct SandboxImage {
+    pub user: String,
+    pub dockerfile: String,
+    pub hash: String,
+    pub tag: String,
+}
+
+impl SandboxImage {
+    /// The image for `settings`. Agents installed from npm without a version
+    /// are pinned to `agent_versions` (package to version), so a new agent
+    /// release changes the Dockerfile and therefore the tag.
+    pub fn for_settings(
+        settings: &Settings,
+        user: &str,
+        agent_versions: &BTreeMap<String, String>,
+    ) -> Result<Self> {
+        let base_image = settings
+            .base_image
+            .as_deref()
+            .filter(|image| !image.trim().is_empty())
+            .unwrap_or(DEFAULT_BASE_IMAGE);
+        let fragment = match settings.dockerfile_fragment.as_deref() {
+            Some(path) => Some(read_fragment(Path::new(path))?),
+            None => None,
+        };
+        let installs: Vec<String> = AgentRegistry::from_settings(settings)?
+            .iter()
+            .filter_map(|agent| agent.install.as_deref())
+            .map(|install| pin_install(install, agent_versions))
+            .collect();
+        let dockerfile =
+            create_dockerfile_content(user, base_image, fragment.as_deref(), &installs);
+        let hash = content_hash(&dockerfile);
+        let tag = format!("{}:{}", IMAGE_REPOSITORY, hash);
+        Ok(Self {
+            user: user.to_string(),
+            dockerfile,
+            hash,
+            tag,
+        })
+    }
+}
+
+/// User created inside the image; matches the host user so file ownership lines up.
+pub fn sandbox_user() -> String {
+    env::var("USER").unwrap_or_else(|_| "ubuntu".to_string())
+}
+
+/// Where the agent versions images are pinned to are kept, next to `settings.json`.
+pub fn agent_versions_path() -> PathBuf {
+    settings_file_path().with_file_name("agent-versions.json")
+}
+
+/// The package of an `npm install -g <package>` command that names no
+/// version (or `@latest`). Other install commands are used as written.
+fn floating_npm_package(install: &str) -> Option<&str> {
+    let words: Vec<&str> = install.split_whitespace().collect();
+    let ["npm", "install", "-g", spec] = words.as_slice() else {
+        return None;
+    };
+    let package = spec.strip_suffix("@latest").unwrap_or(spec);
+    // Any `@` after the one opening a scope separates a version
+    let name = package.get(1..).filter(|name| !name.is_empty())?;
+    (!name.contains('@')).then_some(package)
+}
+
+fn pin_install(install: &str, agent_versions: &BTreeMap<String, String>) -> String {
+    match floating_npm_package(install)
+        .and_then(|package| Some((package, agent_versions.get(package)?)))
+    {
+        Some((package, version)) => format!("npm install -g {}@{}", package, version),
+        None => install.to_string(),
+    }
+}
+
+/// The agent versions recorded in `agent-versions.json`, after looking up the
+/// latest release of every floating npm agent package without one, or of all
+/// of them when `refresh` is set. A package that cannot be looked up keeps
+/// its recorded version, or is installed unpinned when it has none.
+pub async fn pin_agent_versions(
+    settings: &Settings,
+    refresh: bool,
+) -> Result<BTreeMap<String, String>> {
+    let path = agent_versions_path();
+    let mut versions: BTreeMap<String, String> = match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse {}", path.display()))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let registry = AgentRegistry::from_settings(settings)?;
+    let mut packages: Vec<&str> = registry
+        .iter()
+        .filter_map(|agent| agent.install.as_deref().and_then(floating_npm_package))
+        .filter(|package| refresh || !versions.contains_key(*package))
+        .collect();
+    packages.sort();
+    packages.dedup();
+    if packages.is_empty() {
+        return Ok(versions);
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let mut changed = false;
+    for package in packages {
+        match latest_npm_version(&client, package).await {
+            Ok(version) => {
+                changed |= versions.insert(package.to_string(), version.clone()) != Some(version);
+            }
+            Err(e) => match versions.get(package) {
+                Some(version) => println!(
+                    "Warning: looking up the latest {} release failed ({:#}); keeping {}",
+                    package, e, version
+                ),
+                None => println!(
+                    "Warning: looking up the latest {} release failed ({:#}); installing it unpinned",
+                    package, e
+                ),
+            },
+        }
+    }
+    if changed {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(&versions)? + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(versions)
+}
+
+async fn latest_npm_version(client: &reqwest::Client, package: &str) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Release {
+        version: String,
+    }
+    let url = format!("{}/{}/latest", NPM_REGISTRY, package.replace('/', "%2F"));
+    let release: Release = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(release.version)
+}
+
+fn read_fragment(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .with_context(|| format!("Failed to read Dockerfile fragment {}", path.display()))
+}
+
+fn content_hash(content: &str) -> String {
+    let digest = Sha256::digest(content.as_bytes());
+    digest
+        .iter()
+        .take(6)
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// True when an image with this tag exists and was built from the same Dockerfile.
+fn image_is_current(image: &SandboxImage) -> bool {
+    let format = format!("{{{{index .Config.Labels \"{}\"}}}}", LABEL_DOCKERFILE_HASH);
+    runtime()
+        .command()
+        .args(["image", "inspect", "-f", &format, &image.tag])
+        .output()
+        .map(|output| {
+            output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == image.hash
+        })
+        .unwrap_or(false)
+}
+
+/// Build the image unless an up-to-date one is already present.
+pub fn ensure_image(image: &SandboxImage) -> Result<()> {
+    if image_is_current(image) {
+        println!("Using cached image {}", image.tag);
+        return Ok(());
+    }
+    build_image(image, false)
+}
+
+/// Build the image, streaming the build output to the terminal. `no_cache`
+/// also re-runs cached layers, which picks up changes behind unpinned installs.
+pub fn build_image(image: &SandboxImage, no_cache: bool) -> Result<()> {
+    let build_dir = tempfile::tempdir().context("Failed to create build directory")?;
+    let dockerfile_path = build_dir.path().join("Dockerfile");
+    fs::write(&dockerfile_path, &image.dockerfile).context("Failed to write Dockerfile")?;
+
+    println!(
+        "Building container image {} with {}...",
+        image.tag,
+        runtime().name()
+    );
+    let label = format!("{}={}", LABEL_DOCKERFILE_HASH, image.hash);
+    let mut build = runtime().command();
+    build.args(["build", "-t", &image.tag, "--label", &label]);
+    if no_cache {
+        build.arg("--no-cache");
+    }
+    let status = build
+        .arg("-f")
+        .arg(&dockerfile_path)
+        .arg(build_dir.path())
+        .status()
+        .context("Failed to build container image")?;
+
+    if !status.success() {
+        anyhow::bail!("Image build failed ({})", status);
+    }
+
+    Ok(())
+}
+
+/// Re-pin the agents to their latest releases, rebuild the image for
+/// `settings` from scratch and return its tag.
+pub async fn rebuild_image(settings: &Settings) -> Result<String> {
+    let agent_versions = pin_agent_versions(settings, true).await?;
+    let image = SandboxImage::for_settings(settings, &sandbox_user(), &agent_versions)?;
+    build_image(&image, true)?;
+    Ok(image.tag)
+}
+
+/// Outcome of `prune_images`.
+#[derive(Debug, Default)]
+pub struct PrunedImages {
+    pub removed: Vec<String>,
+    /// Tags a container (running or stopped) still needs.
+    pub kept: Vec<String>,
+    /// Tags the runtime refused to remove, with its error.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Remove every sandbox image that no container uses. A failed removal is
+/// recorded and the remaining images are still pruned.
+pub fn prune_images() -> Result<PrunedImages> {
+    let images = list_output(
+        &[
+            "images",
+            IMAGE_REPOSITORY,
+            "--format",
+            "{{.Repository}}:{{.Tag}}",
+        ],
+        "images",
+    )?;
+    let in_use = list_output(&["ps", "-a", "--format", "{{.Image}}"], "containers")?;
+
+    let mut pruned = PrunedImages::default();
+    for tag in images.lines() {
+        let tag = tag.trim();
+        if tag.is_empty() || tag.ends_with(":<none>") {
+            continue;
+        }
+        if in_use.lines().any(|image| image.trim() == tag) {
+            pruned.kept.push(tag.to_string());
+            continue;
+        }
+        let output = runtime()
+            .command()
+            .args(["rmi", tag])
+            .output()
+            .context("Failed to remove image")?;
+        if output.status.success() {
+            pruned.removed.push(tag.to_string());
+        } else {
+            pruned.failed.push((
+                tag.to_string(),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+    }
+    Ok(pruned)
+}
+
+/// Stdout of a runtime listing command; `what` names the listing in errors.
+fn list_output(args: &[&str], what: &str) -> Result<String> {
+    let output = runtime()
+        .command()
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to list {}", what))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to list {}: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The generated Dockerfile. `base_image` must be Debian or Ubuntu based since
+/// the toolchain layers use apt; `fragment` is appended before the final `CMD`.
+/// `agent_installs` are the install commands of the registered agents.
+fn create_dockerfile_content(
+    user: &str,
+    base_image: &str,
+    fragment: Option<&str>,
+    agent_installs: &[String],
+) -> String {
+    let fragment = match fragment {
+        Some(fragment) => format!(
+            "\n# User-supplied Dockerfile fragment\n{}\n",
+            fragment.trim_end()
+        ),
+        None => String::new(),
+    };
+    let agent_installs: String = agent_installs
+        .iter()
+        .map(|install| format!("RUN {}\n", install))
+        .collect();
+    format!(
+        r#"FROM {base_image}
+
+# Avoid interactive prompts during package installation
+ENV DEBIAN_FRONTEND=noninteractive
+
+# Update and install required packages
+RUN apt-get update && apt-get install -y \
+    curl \
+    wget \
+    git \
+    build-essential \
+    python3 \
+    python3-pip \
+    sudo \
+    ca-certificates \
+    gnupg \
+    lsb-release \
+    tmux \
+    && rm -rf /var/lib/apt/lists/*
+
+# Install Node.js v22
+RUN curl -fsSL https://deb.nodesource.com/setup_22.x | bash - && \
+    apt-get install -y nodejs
+
+# Install Go
+RUN wget https://go.dev/dl/go1.24.5.linux-amd64.tar.gz && \
+    tar -C /usr/local -xzf go1.24.5.linux-amd64.tar.gz && \
+    rm go1.24.5.linux-amd64.tar.gz
+
+# Install Rust and Cargo
+RUN curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y && \
+    /root/.cargo/bin/rustup component add rustfmt clippy
+
+# Create user with sudo privileges
+RUN useradd -m -s /bin/bash {user} && \
+    echo "{user} ALL=(ALL) NOPASSWD:ALL" >> /etc/sudoers && \
+    echo 'Defaults env_keep += "HTTP_PROXY HTTPS_PROXY NO_PROXY http_proxy https_proxy no_proxy"' >> /etc/sudoers
+USER root
+# Install the agent CLIs
+{agent_installs}
+# Switch to user
+USER {user}
+WORKDIR /home/{user}
+
+# Set up PATH environment for the user session
+ENV PATH="/home/{user}/.local/bin:/usr/local/go/bin:/home/{user}/.cargo/bin:$PATH"
+
+# Install Rust for the user and ensure cargo is available
+RUN curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y && \
+    ~/.cargo/bin/rustup component add rustfmt clippy
+
+# Install uv for Python tooling
+RUN curl -LsSf https://astral.sh/uv/install.sh | sh
+
+# Add Go, Rust, Cargo, and uv to PATH
+RUN echo 'export PATH="/usr/local/go/bin:$HOME/.cargo/bin:$HOME/.local/bin:$PATH"' >> ~/.bashrc
+{fragment}
+# Set working directory to home
+WORKDIR /home/{user}
+
+# Keep container running
+CMD ["/bin/bash"]
+"#
+    )
+}
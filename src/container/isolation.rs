@@ -0,0 +1,353 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::engine::runtime;
+
+use super::manage::{inspect_container, list_containers};
+
+/// Where the host project is mounted read-only inside an isolated sandbox.
+pub const HOST_MOUNT: &str = "/codesandbox/host";
+
+/// Paths left out of the populated workspace; `node_modules` is synced into
+/// volumes of its own.
+const NOT_POPULATED: &[&str] = &["node_modules"];
+
+/// Paths left out of `diff`/`apply`. The workspace keeps its `.git` so the
+/// agent can use git, but repository internals are never copied back.
+const NOT_COMPARED: &[&str] = &[".git", "node_modules"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A path that differs between the sandbox workspace and the host project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Relative to the project root.
+    pub path: PathBuf,
+}
+
+impl Change {
+    /// Whether this change is `filter` or lies below it.
+    pub fn matches(&self, filter: &Path) -> bool {
+        let filter = filter.strip_prefix("./").unwrap_or(filter);
+        filter.as_os_str().is_empty() || filter == Path::new(".") || self.path.starts_with(filter)
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let marker = match self.kind {
+            ChangeKind::Added => "A",
+            ChangeKind::Modified => "M",
+            ChangeKind::Deleted => "D",
+        };
+        write!(f, "{} {}", marker, self.path.display())
+    }
+}
+
+fn exclude_args(excluded: &[&str]) -> String {
+    excluded
+        .iter()
+        .map(|e| format!("--exclude='{}'", e))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+/// Copy the read-only host project into the writable workspace volume at
+/// `project_dir`, leaving out `NOT_POPULATED`.
+pub fn populate_workspace(container_name: &str, project_dir: &Path) -> Result<()> {
+    let dir = quote(project_dir);
+    let command = format!(
+        "sudo mkdir -p {dir} && sudo tar -C {HOST_MOUNT} {} -cf - . | sudo tar -C {dir} -xpf - && sudo chown -R $(id -u):$(id -g) {dir}",
+        exclude_args(NOT_POPULATED)
+    );
+    let status = runtime()
+        .command()
+        .args(["exec", container_name, "bash", "-lc", &command])
+        .status()
+        .context("Failed to populate the isolated workspace")?;
+    if !status.success() {
+        anyhow::bail!("Copying the project into the isolated workspace failed");
+    }
+    Ok(())
+}
+
+/// Newest isolated sandbox created for `project_dir`.
+pub fn find_isolated_container(project_dir: &Path) -> Result<String> {
+    for name in list_containers(project_dir)? {
+        if inspect_container(&name)?.is_some_and(|info| info.isolated) {
+            return Ok(name);
+        }
+    }
+    anyhow::bail!(
+        "No isolated container found for {}; start one with --isolated",
+        project_dir.display()
+    )
+}
+
+fn run_diff(container_name: &str, project_dir: &Path, flags: &str) -> Result<String> {
+    let command = format!(
+        "diff {flags} {} {HOST_MOUNT} {}",
+        exclude_args(NOT_COMPARED),
+        quote(project_dir)
+    );
+    let output = runtime()
+        .command()
+        .args(["exec", container_name, "bash", "-c", &command])
+        .output()
+        .context("Failed to compare the workspace with the host project")?;
+    // diff exits with 1 when the trees differ
+    if output.status.code().is_some_and(|code| code > 1) || output.status.code().is_none() {
+        anyhow::bail!(
+            "Failed to compare the workspace with the host project: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// NUL-separated listing of the tree at `root` in `container_name`, as read
+/// by `parse_changes`.
+fn list_tree(container_name: &str, root: &Path) -> Result<Vec<u8>> {
+    let prune = NOT_COMPARED
+        .iter()
+        .map(|e| format!("-name '{}'", e))
+        .collect::<Vec<_>>()
+        .join(" -o ");
+    let find = format!("find . -mindepth 1 \\( {prune} \\) -prune -o");
+    let command = format!(
+        "cd {} && {find} -printf '%y\\0%m\\0%l\\0%P\\0' && printf '\\0' && {find} -type f -exec sha256sum -z {{}} +",
+        quote(root)
+    );
+    let output = runtime()
+        .command()
+        .args(["exec", container_name, "bash", "-c", &command])
+        .output()
+        .context("Failed to list the workspace")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to list {} in the sandbox: {}",
+            root.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Paths the agent added, modified or deleted in the workspace of `container_name`.
+pub fn workspace_changes(container_name: &str, project_dir: &Path) -> Result<Vec<Change>> {
+    let host = list_tree(container_name, Path::new(HOST_MOUNT))?;
+    let workspace = list_tree(container_name, project_dir)?;
+    parse_changes(&host, &workspace)
+}
+
+/// Unified diff of the workspace against the host project, with `a/` and `b/`
+/// path prefixes like `git diff`.
+pub fn workspace_diff(container_name: &str, project_dir: &Path) -> Result<String> {
+    let output = run_diff(container_name, project_dir, "-ruN")?;
+    let host = format!("{HOST_MOUNT}/");
+    let workspace = format!("{}/", project_dir.display());
+    Ok(output
+        .lines()
+        .map(|line| {
+            if let Some(rest) = line.strip_prefix("--- ") {
+                format!("--- a/{}", rest.strip_prefix(&host).unwrap_or(rest))
+            } else if let Some(rest) = line.strip_prefix("+++ ") {
+                format!("+++ b/{}", rest.strip_prefix(&workspace).unwrap_or(rest))
+            } else {
+                line.to_string()
+            }
+        })
+        .map(|line| line + "\n")
+        .collect())
+}
+
+/// One entry of a tree listing: its `find -printf %y` type, its permission
+/// bits and what tells two versions apart (the content hash of a file, the
+/// target of a link).
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    kind: Vec<u8>,
+    mode: Vec<u8>,
+    detail: Vec<u8>,
+}
+
+/// Read a listing written by `list_tree`: `type\0mode\0target\0path\0` for
+/// every entry, an empty field, then `sha256sum -z` output for the regular
+/// files.
+fn parse_listing(listing: &[u8]) -> Result<BTreeMap<PathBuf, Entry>> {
+    let path = |bytes: &[u8]| PathBuf::from(OsStr::from_bytes(bytes));
+    let mut entries = BTreeMap::new();
+    let mut fields = listing.split(|b| *b == 0);
+    loop {
+        match fields.next() {
+            Some([]) => break,
+            Some(kind) => {
+                let (Some(mode), Some(detail), Some(name)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    anyhow::bail!("Truncated workspace listing");
+                };
+                entries.insert(
+                    path(name),
+                    Entry {
+                        kind: kind.to_vec(),
+                        mode: mode.to_vec(),
+                        detail: detail.to_vec(),
+                    },
+                );
+            }
+            None => anyhow::bail!("Truncated workspace listing"),
+        }
+    }
+    for record in fields.filter(|record| !record.is_empty()) {
+        // "<64 hex digits>  ./<path>"
+        let name = record
+            .get(64..)
+            .and_then(|rest| rest.strip_prefix(b"  ./"))
+            .with_context(|| {
+                format!(
+                    "Unexpected checksum line {:?}",
+                    String::from_utf8_lossy(record)
+                )
+            })?;
+        entries
+            .entry(path(name))
+            .or_insert_with(|| Entry {
+                kind: b"f".to_vec(),
+                mode: Vec::new(),
+                detail: Vec::new(),
+            })
+            .detail = record[..64].to_vec();
+    }
+    Ok(entries)
+}
+
+/// Compare the listings of the host project and of the workspace. A path that
+/// was added, deleted or changed type is reported once, without its contents;
+/// a file whose content or permissions changed is modified.
+pub fn parse_changes(host: &[u8], workspace: &[u8]) -> Result<Vec<Change>> {
+    let host = parse_listing(host)?;
+    let workspace = parse_listing(workspace)?;
+    let mut paths: Vec<&PathBuf> = host.keys().chain(workspace.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut changes: Vec<Change> = Vec::new();
+    let mut replaced: Vec<&Path> = Vec::new();
+    for path in paths {
+        if replaced.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        let kind = match (host.get(path), workspace.get(path)) {
+            (Some(_), None) => ChangeKind::Deleted,
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(old), Some(new)) if old.kind != new.kind => ChangeKind::Modified,
+            // Directory modes are left alone; a changed one would copy the
+            // whole tree back
+            (Some(old), Some(new))
+                if old.detail != new.detail || (old.kind == b"f" && old.mode != new.mode) =>
+            {
+                changes.push(Change {
+                    kind: ChangeKind::Modified,
+                    path: path.clone(),
+                });
+                continue;
+            }
+            _ => continue,
+        };
+        replaced.push(path);
+        changes.push(Change {
+            kind,
+            path: path.clone(),
+        });
+    }
+    Ok(changes)
+}
+
+/// `path` below `project_dir`, refusing paths that could lead outside of it:
+/// `..` components, or an existing ancestor that resolves elsewhere, such as a
+/// symlink to another directory.
+fn host_path_in_project(project_dir: &Path, path: &Path) -> Result<PathBuf> {
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        anyhow::bail!(
+            "Refusing to apply {}: not a plain relative path",
+            path.display()
+        );
+    }
+    let root = fs::canonicalize(project_dir)
+        .with_context(|| format!("Failed to resolve {}", project_dir.display()))?;
+    let host_path = project_dir.join(path);
+    let mut ancestor = host_path.parent();
+    while let Some(dir) = ancestor {
+        if let Ok(resolved) = fs::canonicalize(dir) {
+            if !resolved.starts_with(&root) {
+                anyhow::bail!(
+                    "Refusing to apply {}: {} leads outside the project",
+                    path.display(),
+                    dir.display()
+                );
+            }
+            break;
+        }
+        ancestor = dir.parent();
+    }
+    Ok(host_path)
+}
+
+/// Copy `change` from the workspace of `container_name` back to the host project.
+pub fn apply_change(container_name: &str, project_dir: &Path, change: &Change) -> Result<()> {
+    let host_path = host_path_in_project(project_dir, &change.path)?;
+    let remove_host_path = || -> Result<()> {
+        if host_path.is_dir() && !host_path.is_symlink() {
+            fs::remove_dir_all(&host_path)
+        } else {
+            fs::remove_file(&host_path)
+        }
+        .with_context(|| format!("Failed to remove {}", host_path.display()))
+    };
+    match change.kind {
+        ChangeKind::Deleted => remove_host_path(),
+        ChangeKind::Added | ChangeKind::Modified => {
+            // docker cp merges directories and follows a symlink at the
+            // destination; copy onto nothing so the file's mode comes along too
+            if host_path.symlink_metadata().is_ok() {
+                remove_host_path()?;
+            }
+            if let Some(parent) = host_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            let src = format!("{}:{}", container_name, host_path.display());
+            let output = runtime()
+                .command()
+                .args(["cp", &src, &host_path.display().to_string()])
+                .output()
+                .context("Failed to copy from container")?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "Failed to copy {}: {}",
+                    change.path.display(),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Ok(())
+        }
+    }
+}
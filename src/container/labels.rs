@@ -0,0 +1,169 @@
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::agents::Agent;
+use crate::proxy::{Allowlist, LABEL_ALLOWED_HOSTS};
+use crate::settings::{MountSpec, NetworkPolicy, ResourceLimits, Settings};
+
+use super::naming::current_branch;
+
+pub const LABEL_PROJECT: &str = "codesandbox.project";
+pub const LABEL_AGENT: &str = "codesandbox.agent";
+pub const LABEL_BRANCH: &str = "codesandbox.branch";
+pub const LABEL_WORKTREE: &str = "codesandbox.worktree";
+pub const LABEL_CREATED: &str = "codesandbox.created";
+pub const LABEL_VERSION: &str = "codesandbox.version";
+pub const LABEL_CPUS: &str = "codesandbox.limits.cpus";
+pub const LABEL_MEMORY: &str = "codesandbox.limits.memory";
+pub const LABEL_PIDS_LIMIT: &str = "codesandbox.limits.pids";
+pub const LABEL_STORAGE: &str = "codesandbox.limits.storage";
+pub const LABEL_NETWORK: &str = "codesandbox.network";
+pub const LABEL_ISOLATED: &str = "codesandbox.isolated";
+pub const LABEL_MOUNTS: &str = "codesandbox.mounts";
+
+/// Metadata recorded on a sandbox container through its labels.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ContainerInfo {
+    pub name: String,
+    pub project_path: Option<String>,
+    pub agent: Option<String>,
+    pub branch: Option<String>,
+    pub worktree: bool,
+    pub isolated: bool,
+    pub created: Option<String>,
+    pub version: Option<String>,
+    pub status: Option<String>,
+    pub limits: ResourceLimits,
+    /// Extra mounts as `path[:container_path]:ro|rw`.
+    pub mounts: Vec<String>,
+}
+
+impl ContainerInfo {
+    pub fn project_name(&self) -> String {
+        self.project_path
+            .as_deref()
+            .and_then(|p| Path::new(p).file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Build from the JSON document printed by `<runtime> inspect <name>`.
+    pub fn from_inspect(name: &str, inspect_json: &str) -> Option<Self> {
+        let value: Value = serde_json::from_str(inspect_json).ok()?;
+        let object = match &value {
+            Value::Array(items) => items.first()?,
+            other => other,
+        };
+        let labels: HashMap<String, String> = object
+            .pointer("/Config/Labels")
+            .and_then(|l| serde_json::from_value(l.clone()).ok())
+            .unwrap_or_default();
+        labels.get(LABEL_PROJECT)?;
+
+        let label = |key: &str| labels.get(key).filter(|v| !v.is_empty()).cloned();
+        Some(Self {
+            name: name.to_string(),
+            project_path: label(LABEL_PROJECT),
+            agent: label(LABEL_AGENT),
+            branch: label(LABEL_BRANCH),
+            worktree: label(LABEL_WORKTREE).as_deref() == Some("true"),
+            isolated: label(LABEL_ISOLATED).as_deref() == Some("true"),
+            created: label(LABEL_CREATED).or_else(|| {
+                object
+                    .get("Created")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            }),
+            version: label(LABEL_VERSION),
+            status: object
+                .pointer("/State/Status")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            limits: ResourceLimits {
+                cpus: label(LABEL_CPUS).and_then(|v| v.parse().ok()),
+                memory: label(LABEL_MEMORY),
+                pids_limit: label(LABEL_PIDS_LIMIT).and_then(|v| v.parse().ok()),
+                storage: label(LABEL_STORAGE),
+            },
+            mounts: label(LABEL_MOUNTS)
+                .map(|m| m.split(';').map(str::to_string).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Absolute, canonical project path used as the value of the project label.
+pub fn project_label_value(dir: &Path) -> String {
+    fs::canonicalize(dir)
+        .unwrap_or_else(|_| dir.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// `--filter` argument selecting containers created for `dir`.
+pub fn project_filter(dir: &Path) -> String {
+    format!("label={LABEL_PROJECT}={}", project_label_value(dir))
+}
+
+pub fn container_labels(
+    current_dir: &Path,
+    agent: &Agent,
+    settings: &Settings,
+    mounts: &[MountSpec],
+) -> Vec<(&'static str, String)> {
+    let limits = &settings.limits;
+    // Worktrees created by `--worktree` live under `.codesandbox-worktrees`
+    let worktree = current_dir
+        .components()
+        .any(|c| c.as_os_str() == ".codesandbox-worktrees");
+    let mut labels = vec![
+        (LABEL_PROJECT, project_label_value(current_dir)),
+        (LABEL_AGENT, agent.name.clone()),
+        (
+            LABEL_BRANCH,
+            current_branch(current_dir).unwrap_or_default(),
+        ),
+        (LABEL_WORKTREE, worktree.to_string()),
+        (
+            LABEL_ISOLATED,
+            settings.isolated.unwrap_or(false).to_string(),
+        ),
+        (LABEL_CREATED, Utc::now().to_rfc3339()),
+        (LABEL_VERSION, env!("CARGO_PKG_VERSION").to_string()),
+    ];
+    // Recorded so that `ls`, `ps` and the API can show the configured limits
+    labels.extend(limits.cpus.map(|cpus| (LABEL_CPUS, cpus.to_string())));
+    labels.extend(limits.memory.clone().map(|memory| (LABEL_MEMORY, memory)));
+    labels.extend(
+        limits
+            .pids_limit
+            .map(|pids| (LABEL_PIDS_LIMIT, pids.to_string())),
+    );
+    labels.extend(
+        limits
+            .storage
+            .clone()
+            .map(|storage| (LABEL_STORAGE, storage)),
+    );
+    if !mounts.is_empty() {
+        let mounts: Vec<String> = mounts.iter().map(ToString::to_string).collect();
+        labels.push((LABEL_MOUNTS, mounts.join(";")));
+    }
+    let network = &settings.network;
+    let policy = match network.policy {
+        NetworkPolicy::Full => "full",
+        NetworkPolicy::Allowlist => "allowlist",
+        NetworkPolicy::None => "none",
+    };
+    labels.push((LABEL_NETWORK, policy.to_string()));
+    // The egress proxy reads the allowlist back from this label
+    if network.policy == NetworkPolicy::Allowlist {
+        let allowlist = Allowlist::with_defaults(&network.allow);
+        labels.push((LABEL_ALLOWED_HOSTS, allowlist.to_label()));
+    }
+    labels
+}
@@ -1,22 +1,22 @@
 use anyhow::{Context, Result};
 use chrono::Utc;
 use std::path::Path;
-use std::process::Command;
 
-use super::naming::sanitize;
+use crate::engine::runtime;
 
-pub fn cleanup_containers(current_dir: &Path) -> Result<()> {
-    let dir_name = current_dir
-        .file_name()
-        .and_then(|s| s.to_str())
-        .map(sanitize)
-        .unwrap_or_else(|| "unknown".to_string());
-    let dir_marker = format!("-{dir_name}-");
+use super::labels::{project_filter, ContainerInfo, LABEL_PROJECT};
 
-    let list_output = Command::new("docker")
-        .args(["ps", "-a", "--format", "{{.Names}}"]) 
+fn list_container_names(filter: &str, all: bool) -> Result<Vec<String>> {
+    let mut args = vec!["ps"];
+    if all {
+        args.push("-a");
+    }
+    args.extend(["--filter", filter, "--format", "{{.Names}}"]);
+    let list_output = runtime()
+        .command()
+        .args(&args)
         .output()
-        .context("Failed to list Docker containers")?;
+        .context("Failed to list containers")?;
 
     if !list_output.status.success() {
         anyhow::bail!(
@@ -26,113 +26,183 @@ pub fn cleanup_containers(current_dir: &Path) -> Result<()> {
     }
 
     let names = String::from_utf8_lossy(&list_output.stdout);
-    for name in names
+    Ok(names
         .lines()
-        .filter(|n| n.starts_with("csb-") && n.contains(&dir_marker))
-    {
-        println!("Removing container {name}");
-        let rm_output = Command::new("docker")
-            .args(["rm", "-f", name])
-            .output()
-            .context("Failed to remove container")?;
-
-        if !rm_output.status.success() {
-            anyhow::bail!(
-                "Failed to remove container {}: {}",
-                name,
-                String::from_utf8_lossy(&rm_output.stderr)
-            );
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Remove every container created for `current_dir`, returning their names.
+pub fn cleanup_containers(current_dir: &Path) -> Result<Vec<String>> {
+    let names = list_container_names(&project_filter(current_dir), true)?;
+    for name in names.iter() {
+        remove_container(name)?;
+    }
+
+    Ok(names)
+}
+
+/// Find a container by name, or by its number in the `ls` listing for
+/// `current_dir`. Names take precedence over numbers.
+pub fn resolve_container(target: &str, current_dir: &Path) -> Result<ContainerInfo> {
+    if let Some(info) = inspect_container(target)? {
+        return Ok(info);
+    }
+    if let Ok(num) = target.parse::<usize>() {
+        let containers = list_containers(current_dir)?;
+        if num >= 1 && num <= containers.len() {
+            if let Some(info) = inspect_container(&containers[num - 1])? {
+                return Ok(info);
+            }
         }
+        anyhow::bail!(
+            "No container number {} for directory {} (see `codesandbox ls`)",
+            num,
+            current_dir.display()
+        );
     }
+    anyhow::bail!("No Code Sandbox container named '{}'", target)
+}
 
+pub fn stop_container(container_name: &str) -> Result<()> {
+    println!("Stopping container {container_name}");
+    let output = runtime()
+        .command()
+        .args(["stop", container_name])
+        .output()
+        .context("Failed to stop container")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to stop container {}: {}",
+            container_name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
     Ok(())
 }
 
-pub fn list_containers(current_dir: &Path) -> Result<Vec<String>> {
-    let dir_name = current_dir
-        .file_name()
-        .and_then(|s| s.to_str())
-        .map(sanitize)
-        .unwrap_or_else(|| "unknown".to_string());
-    let dir_marker = format!("-{dir_name}-");
-
-    let list_output = Command::new("docker")
-        .args(["ps", "-a", "--format", "{{.Names}}"]) 
+pub fn remove_container(container_name: &str) -> Result<()> {
+    println!("Removing container {container_name}");
+    let rm_output = runtime()
+        .command()
+        .args(["rm", "-f", "-v", container_name])
         .output()
-        .context("Failed to list Docker containers")?;
+        .context("Failed to remove container")?;
 
-    if !list_output.status.success() {
+    if !rm_output.status.success() {
         anyhow::bail!(
-            "Failed to list containers: {}",
-            String::from_utf8_lossy(&list_output.stderr)
+            "Failed to remove container {}: {}",
+            container_name,
+            String::from_utf8_lossy(&rm_output.stderr)
         );
     }
+    Ok(())
+}
 
-    let names = String::from_utf8_lossy(&list_output.stdout);
-    let containers = names
-        .lines()
-        .filter(|n| n.starts_with("csb-") && n.contains(&dir_marker))
-        .map(|s| s.to_string())
-        .collect();
-    Ok(containers)
+/// Stream the output of a container to the terminal.
+pub fn container_logs(container_name: &str, follow: bool, tail: Option<&str>) -> Result<()> {
+    let mut args = vec!["logs"];
+    if follow {
+        args.push("--follow");
+    }
+    if let Some(tail) = tail {
+        args.extend(["--tail", tail]);
+    }
+    args.push(container_name);
+    let status = runtime()
+        .command()
+        .args(&args)
+        .status()
+        .context("Failed to read container logs")?;
+    if !status.success() {
+        anyhow::bail!("Failed to read logs of container {}", container_name);
+    }
+    Ok(())
 }
 
-pub fn list_all_containers() -> Result<Vec<(String, String, Option<String>)>> {
-    let list_output = Command::new("docker")
-        .args(["ps", "--format", "{{.Names}}"]) 
+/// The output of a container so far, or its last `tail` lines.
+pub fn read_container_logs(container_name: &str, tail: Option<&str>) -> Result<String> {
+    let mut args = vec!["logs"];
+    if let Some(tail) = tail {
+        args.extend(["--tail", tail]);
+    }
+    args.push(container_name);
+    let output = runtime()
+        .command()
+        .args(&args)
         .output()
-        .context("Failed to list Docker containers")?;
-
-    if !list_output.status.success() {
+        .context("Failed to read container logs")?;
+    if !output.status.success() {
         anyhow::bail!(
-            "Failed to list containers: {}",
-            String::from_utf8_lossy(&list_output.stderr)
+            "Failed to read logs of container {}: {}",
+            container_name,
+            String::from_utf8_lossy(&output.stderr)
         );
     }
+    // The runtime replays the container's stderr on its own stderr
+    let mut logs = String::from_utf8_lossy(&output.stdout).into_owned();
+    logs.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(logs)
+}
 
-    let names = String::from_utf8_lossy(&list_output.stdout);
+/// Run `command` in a running container from `workdir`, returning its exit
+/// code. A terminal is allocated when both stdin and stdout are terminals.
+pub fn exec_in_container(container_name: &str, workdir: &str, command: &[String]) -> Result<i32> {
+    let allocate_tty = atty::is(atty::Stream::Stdout) && atty::is(atty::Stream::Stdin);
+    let mut args = vec!["exec", if allocate_tty { "-it" } else { "-i" }];
+    args.extend(["-w", workdir, container_name]);
+    args.extend(command.iter().map(String::as_str));
+    let status = runtime()
+        .command()
+        .args(&args)
+        .status()
+        .context("Failed to run command in container")?;
+    Ok(status.code().unwrap_or(1))
+}
+
+pub fn list_containers(current_dir: &Path) -> Result<Vec<String>> {
+    list_container_names(&project_filter(current_dir), true)
+}
+
+pub fn list_all_containers() -> Result<Vec<ContainerInfo>> {
     let mut containers = Vec::new();
-    for name in names.lines().filter(|n| n.starts_with("csb-")) {
-        let project = extract_project_name(name);
-        let path = get_container_directory(name).ok().flatten();
-        containers.push((project, name.to_string(), path));
+    for name in list_container_names(&format!("label={LABEL_PROJECT}"), false)? {
+        if let Some(info) = inspect_container(&name)? {
+            containers.push(info);
+        }
     }
     Ok(containers)
 }
 
-fn extract_project_name(name: &str) -> String {
-    let parts: Vec<&str> = name.split('-').collect();
-    if parts.len() >= 3 {
-        parts[2].to_string()
-    } else {
-        "unknown".to_string()
+/// Every sandbox container, running or stopped, found by its labels.
+pub fn list_sandbox_containers() -> Result<Vec<ContainerInfo>> {
+    let mut containers = Vec::new();
+    for name in list_container_names(&format!("label={LABEL_PROJECT}"), true)? {
+        if let Some(info) = inspect_container(&name)? {
+            containers.push(info);
+        }
     }
+    Ok(containers)
 }
 
-fn get_container_directory(name: &str) -> Result<Option<String>> {
-    // First try to get the main project mount (where source equals destination and is read-write)
-    let output = Command::new("docker")
-        .args([
-            "inspect",
-            "-f",
-            "{{range .Mounts}}{{if and .RW (eq .Source .Destination)}}{{.Source}}{{\"\\n\"}}{{end}}{{end}}",
-            name,
-        ])
+/// Read the codesandbox labels of a container, or `None` if it does not
+/// exist or was not created by codesandbox.
+pub fn inspect_container(name: &str) -> Result<Option<ContainerInfo>> {
+    let output = runtime()
+        .command()
+        .args(["inspect", name])
         .output()
         .context("Failed to inspect container")?;
     if !output.status.success() {
         return Ok(None);
     }
-    let paths = String::from_utf8_lossy(&output.stdout);
-
-    // Filter out config directories and get the first valid project path
-    for line in paths.lines() {
-        let path = line.trim();
-        if !path.is_empty() && !path.contains("/.claude") && !path.contains("/.serena") {
-            return Ok(Some(path.to_string()));
-        }
-    }
-    Ok(None)
+    Ok(ContainerInfo::from_inspect(
+        name,
+        &String::from_utf8_lossy(&output.stdout),
+    ))
 }
 
 pub fn auto_remove_old_containers(minutes: u64) -> Result<()> {
@@ -142,22 +212,10 @@ pub fn auto_remove_old_containers(minutes: u64) -> Result<()> {
 
     let cutoff = Utc::now() - chrono::Duration::minutes(minutes as i64);
 
-    let list_output = Command::new("docker")
-        .args(["ps", "-a", "--format", "{{.Names}}"]) 
-        .output()
-        .context("Failed to list Docker containers")?;
-
-    if !list_output.status.success() {
-        anyhow::bail!(
-            "Failed to list containers: {}",
-            String::from_utf8_lossy(&list_output.stderr)
-        );
-    }
-
-    let names = String::from_utf8_lossy(&list_output.stdout);
-    for name in names.lines().filter(|n| n.starts_with("csb-")) {
-        let inspect_output = Command::new("docker")
-            .args(["inspect", "-f", "{{.Created}}", name])
+    for name in list_container_names(&format!("label={LABEL_PROJECT}"), true)? {
+        let inspect_output = runtime()
+            .command()
+            .args(["inspect", "-f", "{{.Created}}", &name])
             .output()
             .context("Failed to inspect container")?;
         if !inspect_output.status.success() {
@@ -174,8 +232,9 @@ pub fn auto_remove_old_containers(minutes: u64) -> Result<()> {
             continue;
         }
 
-        let logs_output = Command::new("docker")
-            .args(["logs", name])
+        let logs_output = runtime()
+            .command()
+            .args(["logs", &name])
             .output()
             .context("Failed to check container logs")?;
         if !logs_output.status.success() {
@@ -183,8 +242,9 @@ pub fn auto_remove_old_containers(minutes: u64) -> Result<()> {
         }
         if logs_output.stdout.is_empty() && logs_output.stderr.is_empty() {
             println!("Auto removing unused container {name}");
-            let rm_output = Command::new("docker")
-                .args(["rm", "-f", name])
+            let rm_output = runtime()
+                .command()
+                .args(["rm", "-f", "-v", &name])
                 .output()
                 .context("Failed to remove container")?;
             if !rm_output.status.success() {
@@ -199,23 +259,13 @@ pub fn auto_remove_old_containers(minutes: u64) -> Result<()> {
     Ok(())
 }
 
-pub fn check_docker_availability() -> Result<()> {
-    let output = Command::new("docker")
-        .arg("--version")
-        .output()
-        .context(
-            "Failed to check Docker availability. Make sure Docker is installed and running.",
-        )?;
-
-    if !output.status.success() {
-        anyhow::bail!("Docker is not available or not running");
-    }
-
-    Ok(())
+pub fn check_runtime_availability() -> Result<()> {
+    runtime().check_availability()
 }
 
 pub fn is_container_running(container_name: &str) -> Result<bool> {
-    let output = Command::new("docker")
+    let output = runtime()
+        .command()
         .args(&["inspect", "-f", "{{.State.Running}}", container_name])
         .output()
         .context("Failed to check container status")?;
@@ -230,7 +280,8 @@ pub fn is_container_running(container_name: &str) -> Result<bool> {
 }
 
 pub fn container_exists(container_name: &str) -> Result<bool> {
-    let output = Command::new("docker")
+    let output = runtime()
+        .command()
         .args(&["inspect", container_name])
         .output()
         .context("Failed to check if container exists")?;
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories never searched for files to mask: `.git` is internal and
+/// `node_modules` is replaced by a container volume anyway.
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules"];
+
+/// A file or directory hidden from the sandbox, relative to the project root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskedPath {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// The `env_files` setting compiled as gitignore-style patterns: `.env`
+/// matches at any depth, `config/secrets.yml` only below the root, `**`
+/// spans directories and `secrets/` matches directories only.
+pub struct MaskPatterns {
+    matcher: Gitignore,
+}
+
+impl MaskPatterns {
+    pub fn new(root: &Path, patterns: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid env_files pattern '{}'", pattern))?;
+        }
+        Ok(Self {
+            matcher: builder.build()?,
+        })
+    }
+
+    /// Whether `relative` is masked, directly or through a masked parent.
+    pub fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        self.matcher
+            .matched_path_or_any_parents(relative, is_dir)
+            .is_ignore()
+    }
+
+    /// Every masked path below `root`, sorted. Masked directories are listed
+    /// once and not searched further.
+    pub fn find(&self, root: &Path) -> Result<Vec<MaskedPath>> {
+        let mut found = Vec::new();
+        if !self.matcher.is_empty() {
+            self.walk(root, root, &mut found)?;
+        }
+        Ok(found)
+    }
+
+    fn walk(&self, root: &Path, dir: &Path, found: &mut Vec<MaskedPath>) -> Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?
+            .flatten()
+            .collect();
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let is_dir = file_type.is_dir();
+            if is_dir && SKIPPED_DIRS.iter().any(|d| entry.file_name() == *d) {
+                continue;
+            }
+            let path = entry.path();
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            if self.matcher.matched(&relative, is_dir).is_ignore() {
+                found.push(MaskedPath {
+                    path: relative,
+                    is_dir,
+                });
+            } else if is_dir {
+                self.walk(root, &path, found)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,30 @@
+mod headless;
+mod image;
+mod isolation;
+mod labels;
 mod manage;
+mod masking;
 mod naming;
 mod runtime;
 
+#[allow(unused_imports)]
+pub use headless::{build_headless_command, git_diff, run_headless, snapshot_tree, HeadlessRun};
+pub use image::{prune_images, rebuild_image};
+#[allow(unused_imports)]
+pub use isolation::{
+    apply_change, find_isolated_container, parse_changes, workspace_changes, workspace_diff,
+    Change, ChangeKind,
+};
+#[allow(unused_imports)]
+pub use labels::ContainerInfo;
 pub use manage::{
-    auto_remove_old_containers, check_docker_availability, cleanup_containers, list_all_containers,
-    list_containers,
+    auto_remove_old_containers, check_runtime_availability, cleanup_containers, container_logs,
+    exec_in_container, inspect_container, is_container_running, list_all_containers,
+    list_containers, list_sandbox_containers, read_container_logs, remove_container,
+    resolve_container, stop_container,
 };
+#[allow(unused_imports)]
+pub use masking::{MaskPatterns, MaskedPath};
 pub use naming::generate_container_name;
 #[allow(unused_imports)]
 pub use runtime::{build_agent_command, create_container, resume_container};
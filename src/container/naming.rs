@@ -2,7 +2,7 @@ use chrono::Local;
 use std::path::Path;
 use std::process::Command;
 
-use crate::cli::Agent;
+use crate::agents::Agent;
 
 pub(crate) fn sanitize(name: &str) -> String {
     name.to_lowercase()
@@ -20,17 +20,21 @@ pub fn generate_container_name(current_dir: &Path, agent: &Agent) -> String {
 
     let agent_name = sanitize(agent.command());
 
-    let branch_output = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "HEAD"])
-        .current_dir(current_dir)
-        .output();
-    let branch_name = branch_output
-        .ok()
-        .filter(|o| o.status.success())
-        .map(|o| sanitize(String::from_utf8_lossy(&o.stdout).trim()))
+    let branch_name = current_branch(current_dir)
+        .map(|b| sanitize(&b))
         .unwrap_or_else(|| "unknown".to_string());
 
     let timestamp = Local::now().format("%y%m%d%H%M").to_string();
 
     format!("csb-{agent_name}-{dir_name}-{branch_name}-{timestamp}")
 }
+
+pub(crate) fn current_branch(dir: &Path) -> Option<String> {
+    Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+}
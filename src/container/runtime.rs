@@ -1,21 +1,32 @@
 use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
 use std::path::Path;
 use std::process::Command;
-use tempfile::NamedTempFile;
+use tempfile::TempDir;
 
-use crate::cli::Agent;
+use crate::agents::Agent;
 use crate::config::{get_claude_config_dir, get_claude_json_paths};
+use crate::engine::runtime;
 use crate::language::{
-    detect_project_languages, ensure_language_tools, sync_node_modules_from_host, ProjectLanguage,
+    ensure_language_tools, node_package_dirs, scan_project_languages, sync_node_modules_from_host,
+    DetectedLanguage, ProjectLanguage, ScanOptions,
 };
-use crate::settings::load_settings;
-
+use crate::proxy::{ensure_proxy_running, DEFAULT_PROXY_PORT, EGRESS_NETWORK};
+use crate::secrets::{apply_secret_args, resolve_secrets, write_secret_files, ResolvedSecret};
+use crate::settings::{MountSpec, NetworkPolicy, SecretTarget, Settings};
+use crate::toolchain::ensure_pinned_toolchains;
+
+use super::image::{ensure_image, pin_agent_versions, sandbox_user, SandboxImage};
+use super::isolation::{populate_workspace, HOST_MOUNT};
+use super::labels::container_labels;
 use super::manage::{container_exists, is_container_running};
+use super::masking::MaskPatterns;
 
 fn mount_agent_config(
     docker_run: &mut Command,
-    agent_names: &[&str],
+    agent_names: &[String],
     current_dir: &Path,
     current_user: &str,
 ) {
@@ -70,62 +81,71 @@ fn mount_language_configs(
     }
 }
 
-fn build_docker_image(current_user: &str) -> Result<()> {
-    let dockerfile_content = create_dockerfile_content(current_user);
-    let temp_dir = std::env::temp_dir();
-    let dockerfile_path = temp_dir.join("Dockerfile.codesandbox");
-    std::fs::write(&dockerfile_path, dockerfile_content).context("Failed to write Dockerfile")?;
-
-    println!("Building Docker image...");
-    let build_output = Command::new("docker")
-        .args([
-            "build",
-            "-t",
-            "codesandbox-image",
-            "-f",
-            dockerfile_path.to_str().unwrap(),
-            ".",
-        ])
-        .current_dir(&temp_dir)
-        .output()
-        .context("Failed to build Docker image")?;
-
-    if !build_output.status.success() {
-        anyhow::bail!(
-            "Docker build failed: {}",
-            String::from_utf8_lossy(&build_output.stderr)
-        );
-    }
-
-    Ok(())
-}
-
+#[allow(clippy::too_many_arguments)]
 fn build_run_command(
     container_name: &str,
     current_dir: &Path,
-    additional_dir: Option<&Path>,
+    extra_mounts: &[MountSpec],
     agent: &Agent,
-    current_user: &str,
-    languages: &[ProjectLanguage],
-) -> Result<(Command, Vec<NamedTempFile>)> {
-    let mut docker_run = Command::new("docker");
-    docker_run.args([
-        "run",
-        "-d",
-        "-it",
-        "--name",
-        container_name,
-        "-v",
-        &format!("{}:{}", current_dir.display(), current_dir.display()),
-    ]);
+    settings: &Settings,
+    image: &SandboxImage,
+    detected: &[DetectedLanguage],
+    secrets: &[ResolvedSecret],
+) -> Result<(Command, Option<TempDir>)> {
+    let current_user = image.user.as_str();
+    let mut docker_run = runtime().command();
+    docker_run.arg("run");
+    docker_run.args(runtime().run_args());
+    docker_run.args(["-d", "-it", "--name", container_name]);
+    for (key, value) in container_labels(current_dir, agent, settings, extra_mounts) {
+        docker_run.args(["--label", &format!("{key}={value}")]);
+    }
+    docker_run.args(settings.limits.run_args());
+    match settings.network.policy {
+        NetworkPolicy::Full => {}
+        NetworkPolicy::None => {
+            docker_run.args(["--network", "none"]);
+            println!("Network access disabled");
+        }
+        NetworkPolicy::Allowlist => {
+            let port = settings.network.proxy_port.unwrap_or(DEFAULT_PROXY_PORT);
+            let proxy_url = ensure_proxy_running(port)?;
+            docker_run.args(["--network", EGRESS_NETWORK]);
+            for var in ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"] {
+                docker_run.args(["-e", &format!("{var}={proxy_url}")]);
+            }
+            for var in ["NO_PROXY", "no_proxy"] {
+                docker_run.args(["-e", &format!("{var}=localhost,127.0.0.1")]);
+            }
+            println!("Network restricted to allowlisted hosts via {}", proxy_url);
+        }
+    }
+    apply_secret_args(&mut docker_run, secrets);
+    let isolated = settings.isolated.unwrap_or(false);
+    if isolated {
+        // The project is only readable; edits go to a volume at the project
+        // path and reach the host through `codesandbox apply`
+        docker_run.args([
+            "-v",
+            &format!("{}:{}:ro", current_dir.display(), HOST_MOUNT),
+        ]);
+        docker_run.args(["-v", &current_dir.display().to_string()]);
+        println!(
+            "Mounting {} read-only; changes stay in the container until applied",
+            current_dir.display()
+        );
+    } else {
+        docker_run.args([
+            "-v",
+            &format!("{}:{}", current_dir.display(), current_dir.display()),
+        ]);
+    }
 
-    // For Node.js projects, avoid mounting host node_modules by overlaying
+    // For Node.js packages, avoid mounting host node_modules by overlaying
     // an anonymous volume at the container's node_modules path. This prevents
     // install scripts from affecting the host machine.
-    let project_has_node =
-        current_dir.join("package.json").exists() || current_dir.join("node_modules").exists();
-    if project_has_node {
-        let node_modules_path = current_dir.join("node_modules");
+    for package_dir in node_package_dirs(current_dir, detected) {
+        let node_modules_path = package_dir.join("node_modules");
         docker_run.args(["-v", &format!("{}", node_modules_path.display())]);
         println!(
             "Isolating node_modules with container volume: {}",
@@ -133,24 +153,48 @@ fn build_run_command(
         );
     }
 
-    let settings = load_settings().unwrap_or_default();
-    let mut env_file_overlays: Vec<NamedTempFile> = Vec::new();
-    for file in settings.env_files.iter() {
-        let target = current_dir.join(file);
-        if target.is_file() {
-            let tmp = NamedTempFile::new().context("Failed to create temp file for env masking")?;
+    let masked = MaskPatterns::new(current_dir, &settings.env_files)?.find(current_dir)?;
+    let mask_sources = if masked.is_empty() {
+        None
+    } else {
+        // One empty file and one empty directory, bound read-only over every match
+        let dir = TempDir::new().context("Failed to create temp dir for env masking")?;
+        fs::write(dir.path().join("file"), "").context("Failed to create env mask")?;
+        fs::create_dir(dir.path().join("dir")).context("Failed to create env mask")?;
+        Some(dir)
+    };
+    if let Some(sources) = &mask_sources {
+        // In isolated mode the workspace is copied from the host mount,
+        // so masking it there keeps the secrets out of the copy as well
+        let base = if isolated {
+            Path::new(HOST_MOUNT)
+        } else {
+            current_dir
+        };
+        for path in masked.iter() {
+            let source = sources
+                .path()
+                .join(if path.is_dir { "dir" } else { "file" });
             docker_run.args([
                 "-v",
-                &format!("{}:{}:ro", tmp.path().display(), target.display()),
+                &format!(
+                    "{}:{}:ro",
+                    source.display(),
+                    base.join(&path.path).display()
+                ),
             ]);
-            println!("Excluding {} from container mount", target.display());
-            env_file_overlays.push(tmp);
         }
+        let dirs = masked.iter().filter(|p| p.is_dir).count();
+        println!(
+            "Excluding {} file(s) and {} director(ies) matching env_files from container mount",
+            masked.len() - dirs,
+            dirs
+        );
     }
 
-    if let Some(dir) = additional_dir {
-        docker_run.args(["-v", &format!("{}:{}:ro", dir.display(), dir.display())]);
-        println!("Mounting additional directory read-only: {}", dir.display());
+    for mount in extra_mounts {
+        docker_run.args(["-v", &mount.volume_arg()]);
+        println!("Mounting additional directory: {}", mount);
     }
 
     if let Some(claude_config_dir) = get_claude_config_dir() {
@@ -210,74 +254,129 @@ fn build_run_command(
         }
     }
 
-    match agent {
-        Agent::Gemini => {
-            mount_agent_config(&mut docker_run, &["gemini"], current_dir, current_user);
-        }
-        Agent::Qwen => {
-            mount_agent_config(&mut docker_run, &["qwen"], current_dir, current_user);
-        }
-        Agent::Cursor => {
-            mount_agent_config(&mut docker_run, &["cursor"], current_dir, current_user);
+    mount_agent_config(
+        &mut docker_run,
+        &agent.config_dirs,
+        current_dir,
+        current_user,
+    );
+
+    for found in detected {
+        let paths: Vec<String> = found
+            .paths
+            .iter()
+            .map(|p| {
+                if p.as_os_str().is_empty() {
+                    ".".to_string()
+                } else {
+                    p.display().to_string()
+                }
+            })
+            .collect();
+        if paths.is_empty() {
+            println!(
+                "Installing {} (configured toolchain)",
+                found.language.name()
+            );
+        } else {
+            println!(
+                "Detected {} in: {}",
+                found.language.name(),
+                paths.join(", ")
+            );
         }
-        _ => {}
     }
+    let languages: Vec<ProjectLanguage> = detected.iter().map(|d| d.language).collect();
+    mount_language_configs(&mut docker_run, &languages, current_user);
 
-    if !languages.is_empty() {
-        println!(
-            "Detected languages: {:?}",
-            languages.iter().map(|l| l.name()).collect::<Vec<_>>()
-        );
-        mount_language_configs(&mut docker_run, languages, current_user);
-    }
+    docker_run.args([image.tag.as_str(), "/bin/bash"]);
 
-    docker_run.args(["codesandbox-image", "/bin/bash"]);
+    Ok((docker_run, mask_sources))
+}
 
-    Ok((docker_run, env_file_overlays))
+/// Mounts from `--add_dir` (already resolved) followed by the configured
+/// `mounts`. Configured directories that do not exist are skipped.
+fn collect_mounts(additional: &[MountSpec], settings: &Settings) -> Result<Vec<MountSpec>> {
+    let mut mounts = additional.to_vec();
+    for spec in settings.mounts.iter() {
+        let mount = MountSpec::parse(spec)?;
+        if !mount.host.is_dir() {
+            println!(
+                "Skipping configured mount {}: not a directory",
+                mount.host.display()
+            );
+            continue;
+        }
+        mounts.push(mount.canonicalize()?);
+    }
+    Ok(mounts)
 }
 
 pub async fn create_container(
     container_name: &str,
     current_dir: &Path,
-    additional_dir: Option<&Path>,
+    additional_mounts: &[MountSpec],
     agent: &Agent,
-    skip_permission_flag: Option<&str>,
+    settings: &Settings,
     shell: bool,
     attach: bool,
 ) -> Result<()> {
-    let current_user = env::var("USER").unwrap_or_else(|_| "ubuntu".to_string());
-    build_docker_image(&current_user)?;
-    let languages = detect_project_languages(current_dir);
-    let (mut docker_run, _env_file_overlays) = build_run_command(
+    let agent_versions = pin_agent_versions(settings, false).await?;
+    let image = SandboxImage::for_settings(settings, &sandbox_user(), &agent_versions)?;
+    ensure_image(&image)?;
+    let scan = ScanOptions {
+        max_depth: settings
+            .language_scan_depth
+            .unwrap_or(ScanOptions::default().max_depth),
+        exclude: settings.language_scan_exclude.clone(),
+    };
+    let mut detected = scan_project_languages(current_dir, &scan)?;
+    for name in settings.toolchains.iter() {
+        match ProjectLanguage::from_name(name) {
+            Some(language) if !detected.iter().any(|d| d.language == language) => {
+                detected.push(DetectedLanguage {
+                    language,
+                    paths: Vec::new(),
+                })
+            }
+            Some(_) => {}
+            None => println!("Ignoring unknown toolchain in settings: {}", name),
+        }
+    }
+    let languages: Vec<ProjectLanguage> = detected.iter().map(|d| d.language).collect();
+    let mounts = collect_mounts(additional_mounts, settings)?;
+    let secrets = resolve_secrets(&settings.secrets)?;
+    let (mut docker_run, _mask_sources) = build_run_command(
         container_name,
         current_dir,
-        additional_dir,
+        &mounts,
         agent,
-        &current_user,
-        &languages,
+        settings,
+        &image,
+        &detected,
+        &secrets,
     )?;
-    let run_output = docker_run
-        .output()
-        .context("Failed to run Docker container")?;
+    let run_output = docker_run.output().context("Failed to run container")?;
     if !run_output.status.success() {
         anyhow::bail!(
             "Failed to create container: {}",
             String::from_utf8_lossy(&run_output.stderr)
         );
     }
-    ensure_language_tools(container_name, &languages)?;
-    // For Node.js projects, copy host node_modules into the isolated volume in container
-    sync_node_modules_from_host(container_name, current_dir, &languages)?;
+    write_secret_files(container_name, &secrets)?;
+    if settings.isolated.unwrap_or(false) {
+        populate_workspace(container_name, current_dir)?;
+    }
+    if settings.network.policy == NetworkPolicy::None {
+        println!("Skipping toolchain installation: the sandbox has no network access");
+    } else {
+        ensure_language_tools(container_name, &languages)?;
+        ensure_pinned_toolchains(container_name, current_dir, &detected)?;
+    }
+    // For Node.js packages, copy host node_modules into the isolated volumes in container
+    sync_node_modules_from_host(container_name, current_dir, &detected)?;
     if attach {
-        attach_to_container(
-            container_name,
-            current_dir,
-            agent,
-            false,
-            skip_permission_flag,
-            shell,
-        )
-        .await
+        attach_to_container(container_name, current_dir, agent, false, shell).await
     } else {
         Ok(())
     }
@@ -287,7 +386,7 @@ pub async fn resume_container(
     container_name: &str,
     agent: &Agent,
     agent_continue: bool,
-    skip_permission_flag: Option<&str>,
+    settings: &Settings,
     shell: bool,
     attach: bool,
 ) -> Result<()> {
@@ -299,7 +398,8 @@ pub async fn resume_container(
 
     if !is_container_running(container_name)? {
         println!("Starting stopped container: {}", container_name);
-        let start_output = Command::new("docker")
+        let start_output = runtime()
+            .command()
             .args(&["start", container_name])
             .output()
             .context("Failed to start container")?;
@@ -310,32 +410,35 @@ pub async fn resume_container(
                 String::from_utf8_lossy(&start_output.stderr)
             );
         }
+        // File secrets live on a tmpfs that does not survive a restart
+        let file_secrets: BTreeMap<_, _> = settings
+            .secrets
+            .iter()
+            .filter(|(_, spec)| spec.inject == SecretTarget::File)
+            .map(|(name, spec)| (name.clone(), spec.clone()))
+            .collect();
+        write_secret_files(container_name, &resolve_secrets(&file_secrets)?)?;
     } else {
         println!("Container is already running");
     }
 
     if attach {
         let current_dir = env::current_dir().context("Failed to get current directory")?;
-        attach_to_container(
-            container_name,
-            &current_dir,
-            agent,
-            agent_continue,
-            skip_permission_flag,
-            shell,
-        )
-        .await
+        attach_to_container(container_name, &current_dir, agent, agent_continue, shell).await
     } else {
         Ok(())
     }
 }
 
+/// Shell command that starts `agent` in `current_dir`, used both when
+/// attaching in the terminal and by the web UI. With `agent_continue` the
+/// agent's resume arguments follow the binary, so they may be a flag or a
+/// subcommand; agents that cannot resume are an error.
 pub fn build_agent_command(
     current_dir: &Path,
     agent: &Agent,
     agent_continue: bool,
-    skip_permission_flag: Option<&str>,
-) -> String {
+) -> Result<String> {
     let path_str = current_dir.display().to_string();
     let escaped = path_str.replace('\'', "'\\''");
     let mut command = format!(
@@ -345,15 +448,16 @@ pub fn build_agent_command(
     );
 
     if agent_continue {
-        command.push_str(" --continue");
+        command.push(' ');
+        command.push_str(agent.continue_args()?);
     }
 
-    if let Some(flag) = skip_permission_flag {
+    if let Some(flag) = &agent.skip_permission_flag {
         command.push(' ');
         command.push_str(flag);
     }
 
-    command
+    Ok(command)
 }
 
 async fn attach_to_container(
@@ -361,7 +465,6 @@ async fn attach_to_container(
     current_dir: &Path,
     agent: &Agent,
     agent_continue: bool,
-    skip_permission_flag: Option<&str>,
     shell: bool,
 ) -> Result<()> {
     let allocate_tty = atty::is(atty::Stream::Stdout) && atty::is(atty::Stream::Stdin);
@@ -372,7 +475,8 @@ async fn attach_to_container(
     }
 
     // Ensure the directory structure exists in the container
-    let mkdir_status = Command::new("docker")
+    let mkdir_status = runtime()
+        .command()
         .args(&[
             "exec",
             container_name,
@@ -399,20 +503,22 @@ async fn attach_to_container(
         }
         args.push(container_name);
         args.extend(["/bin/bash", "-c", &command]);
-        let attach_status = Command::new("docker")
+        let attach_status = runtime()
+            .command()
             .args(&args)
             .status()
             .context("Failed to attach to container")?;
         if !attach_status.success() {
             println!(
-                "You can manually attach with: docker exec -it {} /bin/bash",
+                "You can manually attach with: {} exec -it {} /bin/bash",
+                runtime().binary(),
                 container_name
             );
         }
         return Ok(());
     }
 
-    let command = build_agent_command(current_dir, agent, agent_continue, skip_permission_flag);
+    let command = build_agent_command(current_dir, agent, agent_continue)?;
 
     let mut args = vec!["exec"];
     if allocate_tty {
@@ -422,7 +528,8 @@ async fn attach_to_container(
     }
     args.push(container_name);
     args.extend(["/bin/bash", "-c", &command]);
-    let attach_status = Command::new("docker")
+    let attach_status = runtime()
+        .command()
         .args(&args)
         .status()
         .context("Failed to attach to container")?;
@@ -430,84 +537,11 @@ async fn attach_to_container(
     if !attach_status.success() {
         println!("Failed to start {} automatically.", agent);
         println!(
-            "You can manually attach with: docker exec -it {} /bin/bash",
+            "You can manually attach with: {} exec -it {} /bin/bash",
+            runtime().binary(),
             container_name
         );
     }
 
     Ok(())
 }
-
-fn create_dockerfile_content(user: &str) -> String {
-    format!(
-        r#"FROM ubuntu:22.04
-
-# Avoid interactive prompts during package installation
-ENV DEBIAN_FRONTEND=noninteractive
-
-# Update and install required packages
-RUN apt-get update && apt-get install -y \
-    curl \
-    wget \
-    git \
-    build-essential \
-    python3 \
-    python3-pip \
-    sudo \
-    ca-certificates \
-    gnupg \
-    lsb-release \
-    tmux \
-    && rm -rf /var/lib/apt/lists/*
-
-# Install Node.js v22
-RUN curl -fsSL https://deb.nodesource.com/setup_22.x | bash - && \
-    apt-get install -y nodejs
-
-# Install Go
-RUN wget https://go.dev/dl/go1.24.5.linux-amd64.tar.gz && \
-    tar -C /usr/local -xzf go1.24.5.linux-amd64.tar.gz && \
-    rm go1.24.5.linux-amd64.tar.gz
-
-# Install Rust and Cargo
-RUN curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y && \
-    /root/.cargo/bin/rustup component add rustfmt clippy
-
-# Create user with sudo privileges
-RUN useradd -m -s /bin/bash {user} && \
-    echo "{user} ALL=(ALL) NOPASSWD:ALL" >> /etc/sudoers
-USER root
-# Install Claude Code
-RUN npm install -g @anthropic-ai/claude-code
-RUN npm install -g @google/gemini-cli
-RUN npm install -g @openai/codex
-RUN npm install -g @qwen-code/qwen-code@latest
-
-# Install Cursor CLI
-RUN curl https://cursor.com/install -fsS | bash
-# Switch to user
-USER {user}
-WORKDIR /home/{user}
-
-# Set up PATH environment for the user session
-ENV PATH="/home/{user}/.local/bin:/usr/local/go/bin:/home/{user}/.cargo/bin:$PATH"
-
-# Install Rust for the user and ensure cargo is available
-RUN curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y && \
-    ~/.cargo/bin/rustup component add rustfmt clippy
-
-# Install uv for Python tooling
-RUN curl -LsSf https://astral.sh/uv/install.sh | sh
-
-# Add Go, Rust, Cargo, and uv to PATH
-RUN echo 'export PATH="/usr/local/go/bin:$HOME/.cargo/bin:$HOME/.local/bin:$PATH"' >> ~/.bashrc
-
-# Set working directory to home
-WORKDIR /home/{user}
-
-# Keep container running
-CMD ["/bin/bash"]
-"#,
-        user = user
-    )
-}
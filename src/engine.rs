@@ -0,0 +1,155 @@
+use anyhow::{Context, Result};
+use once_cell::sync::OnceCell;
+use std::net::IpAddr;
+use std::process::Command;
+
+/// A container runtime CLI that speaks the Docker command-line dialect.
+///
+/// Every backend is driven by shelling out to its binary, so the trait only
+/// needs to describe how to reach that binary and where the runtimes differ.
+pub trait ContainerRuntime: Send + Sync {
+    /// Name used in settings and on the command line.
+    fn name(&self) -> &'static str;
+
+    /// Executable invoked for every container operation.
+    fn binary(&self) -> &'static str;
+
+    /// Extra arguments appended to `run` right after the subcommand.
+    fn run_args(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn command(&self) -> Command {
+        Command::new(self.binary())
+    }
+
+    /// Gateway address of `network`, or `None` if the network does not exist
+    /// or has none. Each runtime reports its networks in its own format.
+    fn network_gateway(&self, network: &str) -> Option<IpAddr>;
+
+    fn async_command(&self) -> tokio::process::Command {
+        tokio::process::Command::new(self.binary())
+    }
+
+    fn check_availability(&self) -> Result<()> {
+        let output = self.command().arg("--version").output().with_context(|| {
+            format!(
+                "Failed to check {} availability. Make sure {} is installed and running.",
+                self.name(),
+                self.binary()
+            )
+        })?;
+
+        if !output.status.success() {
+            anyhow::bail!("{} is not available or not running", self.name());
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Docker;
+
+impl ContainerRuntime for Docker {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn binary(&self) -> &'static str {
+        "docker"
+    }
+
+    fn network_gateway(&self, network: &str) -> Option<IpAddr> {
+        inspect_gateway(self, network, "{{range .IPAM.Config}}{{.Gateway}} {{end}}")
+    }
+}
+
+pub struct Podman;
+
+impl ContainerRuntime for Podman {
+    fn name(&self) -> &'static str {
+        "podman"
+    }
+
+    fn binary(&self) -> &'static str {
+        "podman"
+    }
+
+    // Rootless Podman maps the container user onto a subordinate UID by
+    // default, which leaves bind-mounted project files unwritable. Keep the
+    // host UID so the sandbox user owns what it edits.
+    fn run_args(&self) -> &'static [&'static str] {
+        &["--userns=keep-id"]
+    }
+
+    // Podman 4 (netavark) lists the gateways under `subnets`, not `IPAM`
+    fn network_gateway(&self, network: &str) -> Option<IpAddr> {
+        inspect_gateway(self, network, "{{range .Subnets}}{{.Gateway}} {{end}}")
+    }
+}
+
+pub struct Nerdctl;
+
+impl ContainerRuntime for Nerdctl {
+    fn name(&self) -> &'static str {
+        "nerdctl"
+    }
+
+    fn binary(&self) -> &'static str {
+        "nerdctl"
+    }
+
+    // The Docker-compatible inspect output is nerdctl's default mode
+    fn network_gateway(&self, network: &str) -> Option<IpAddr> {
+        inspect_gateway(self, network, "{{range .IPAM.Config}}{{.Gateway}} {{end}}")
+    }
+}
+
+/// First address printed by `network inspect -f <format> <network>`.
+fn inspect_gateway(runtime: &dyn ContainerRuntime, network: &str, format: &str) -> Option<IpAddr> {
+    let output = runtime
+        .command()
+        .args(["network", "inspect", "-f", format, network])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .find_map(|ip| ip.parse().ok())
+}
+
+pub const RUNTIME_NAMES: &[&str] = &["docker", "podman", "nerdctl"];
+
+static SELECTED: OnceCell<&'static dyn ContainerRuntime> = OnceCell::new();
+
+pub fn runtime_by_name(name: &str) -> Option<&'static dyn ContainerRuntime> {
+    match name.to_lowercase().as_str() {
+        "docker" => Some(&Docker),
+        "podman" => Some(&Podman),
+        "nerdctl" => Some(&Nerdctl),
+        _ => None,
+    }
+}
+
+/// Choose the runtime used for the rest of the process. Only the first call
+/// takes effect; `None` keeps the Docker default.
+pub fn select_runtime(name: Option<&str>) -> Result<&'static dyn ContainerRuntime> {
+    let selected = match name {
+        Some(name) => runtime_by_name(name).with_context(|| {
+            format!(
+                "Unknown container runtime '{}'. Expected one of: {}",
+                name,
+                RUNTIME_NAMES.join(", ")
+            )
+        })?,
+        None => &Docker,
+    };
+    Ok(*SELECTED.get_or_init(|| selected))
+}
+
+/// The runtime selected for this process, defaulting to Docker.
+pub fn runtime() -> &'static dyn ContainerRuntime {
+    *SELECTED.get_or_init(|| &Docker)
+}
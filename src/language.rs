@@ -1,6 +1,9 @@
 use anyhow::{Context, Result};
-use std::path::Path;
-use std::process::Command;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+
+use crate::engine::runtime;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ProjectLanguage {
@@ -10,9 +13,34 @@ pub enum ProjectLanguage {
     Go,
     Php,
     Ruby,
+    Java,
+    DotNet,
+    Elixir,
+    Deno,
+    Bun,
+    Cpp,
 }
 
 impl ProjectLanguage {
+    /// Parse a toolchain name as written in settings, e.g. "rust" or "node".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "rust" => Some(ProjectLanguage::Rust),
+            "node" | "nodejs" | "node.js" => Some(ProjectLanguage::NodeJs),
+            "python" => Some(ProjectLanguage::Python),
+            "go" | "golang" => Some(ProjectLanguage::Go),
+            "php" => Some(ProjectLanguage::Php),
+            "ruby" => Some(ProjectLanguage::Ruby),
+            "java" | "kotlin" | "jvm" => Some(ProjectLanguage::Java),
+            "dotnet" | ".net" | "csharp" | "c#" => Some(ProjectLanguage::DotNet),
+            "elixir" => Some(ProjectLanguage::Elixir),
+            "deno" => Some(ProjectLanguage::Deno),
+            "bun" => Some(ProjectLanguage::Bun),
+            "cpp" | "c++" | "c" | "cmake" => Some(ProjectLanguage::Cpp),
+            _ => None,
+        }
+    }
+
     pub fn name(&self) -> &'static str {
         match self {
             ProjectLanguage::Rust => "Rust",
@@ -21,6 +49,12 @@ impl ProjectLanguage {
             ProjectLanguage::Go => "Go",
             ProjectLanguage::Php => "PHP",
             ProjectLanguage::Ruby => "Ruby",
+            ProjectLanguage::Java => "Java",
+            ProjectLanguage::DotNet => ".NET",
+            ProjectLanguage::Elixir => "Elixir",
+            ProjectLanguage::Deno => "Deno",
+            ProjectLanguage::Bun => "Bun",
+            ProjectLanguage::Cpp => "C/C++",
         }
     }
 
@@ -32,6 +66,13 @@ impl ProjectLanguage {
             ProjectLanguage::Go => vec!["go", ".config/go"],
             ProjectLanguage::Php => vec![".composer"],
             ProjectLanguage::Ruby => vec![".gem", ".bundle"],
+            ProjectLanguage::Java => vec![".m2", ".gradle"],
+            ProjectLanguage::DotNet => vec![".nuget"],
+            ProjectLanguage::Elixir => vec![".hex", ".mix"],
+            ProjectLanguage::Deno => vec![".cache/deno"],
+            // Only the package cache; ~/.bun/bin holds the container's own binary
+            ProjectLanguage::Bun => vec![".bun/install/cache"],
+            ProjectLanguage::Cpp => vec![],
         }
     }
 
@@ -43,6 +84,12 @@ impl ProjectLanguage {
             ProjectLanguage::Go => "go",
             ProjectLanguage::Php => "composer",
             ProjectLanguage::Ruby => "bundle",
+            ProjectLanguage::Java => "java",
+            ProjectLanguage::DotNet => "dotnet",
+            ProjectLanguage::Elixir => "mix",
+            ProjectLanguage::Deno => "deno",
+            ProjectLanguage::Bun => "bun",
+            ProjectLanguage::Cpp => "cmake",
         }
     }
 
@@ -54,10 +101,104 @@ impl ProjectLanguage {
             ProjectLanguage::Go => "wget https://go.dev/dl/go1.24.5.linux-amd64.tar.gz && sudo tar -C /usr/local -xzf go1.24.5.linux-amd64.tar.gz && rm go1.24.5.linux-amd64.tar.gz",
             ProjectLanguage::Php => "sudo apt-get update && sudo apt-get install -y php-cli unzip && curl -sS https://getcomposer.org/installer | php -- --install-dir=/usr/local/bin --filename=composer",
             ProjectLanguage::Ruby => "sudo apt-get update && sudo apt-get install -y ruby-full && sudo gem install bundler",
+            ProjectLanguage::Java => "sudo apt-get update && sudo apt-get install -y openjdk-21-jdk-headless maven",
+            ProjectLanguage::DotNet => "curl -fsSL https://dot.net/v1/dotnet-install.sh | bash -s -- --channel LTS --install-dir \"$HOME/.dotnet\" && sudo ln -sf \"$HOME/.dotnet/dotnet\" /usr/local/bin/dotnet",
+            ProjectLanguage::Elixir => "sudo apt-get update && sudo apt-get install -y elixir erlang-dev erlang-xmerl && mix local.hex --force && mix local.rebar --force",
+            ProjectLanguage::Deno => "curl -fsSL https://deno.land/install.sh | sh -s -- -y && sudo ln -sf \"$HOME/.deno/bin/deno\" /usr/local/bin/deno",
+            ProjectLanguage::Bun => "curl -fsSL https://bun.sh/install | bash && sudo ln -sf \"$HOME/.bun/bin/bun\" /usr/local/bin/bun",
+            ProjectLanguage::Cpp => "sudo apt-get update && sudo apt-get install -y cmake ninja-build gdb",
+        }
+    }
+}
+
+/// Directories never worth scanning for marker files, even when not ignored.
+const DEFAULT_SCAN_EXCLUDES: &[&str] = &["node_modules", "target", "vendor", "venv", "__pycache__"];
+
+/// Options for the recursive marker-file scan in `scan_project_languages`.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Directory levels below the project root to look at; 0 scans only the root.
+    pub max_depth: usize,
+    /// Gitignore-style patterns to skip in addition to `.gitignore` and the defaults.
+    pub exclude: Vec<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 3,
+            exclude: Vec::new(),
         }
     }
 }
 
+/// A language and the directories, relative to the project root, whose marker
+/// files revealed it. The root itself is the empty path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedLanguage {
+    pub language: ProjectLanguage,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Detect languages in `root` and its subdirectories, so that monorepos with
+/// e.g. `services/api/go.mod` and `web/package.json` get every toolchain.
+/// Hidden directories and anything matched by `.gitignore` are skipped.
+pub fn scan_project_languages(root: &Path, options: &ScanOptions) -> Result<Vec<DetectedLanguage>> {
+    let mut overrides = OverrideBuilder::new(root);
+    let excludes = DEFAULT_SCAN_EXCLUDES
+        .iter()
+        .copied()
+        .chain(options.exclude.iter().map(String::as_str));
+    for pattern in excludes {
+        overrides
+            .add(&format!("!{}", pattern))
+            .with_context(|| format!("Invalid exclude pattern '{}'", pattern))?;
+    }
+    let walker = WalkBuilder::new(root)
+        .max_depth(Some(options.max_depth))
+        .require_git(false)
+        .overrides(overrides.build()?)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut detected: Vec<DetectedLanguage> = Vec::new();
+    for entry in walker.flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_dir()) {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(root)
+            .unwrap_or(entry.path())
+            .to_path_buf();
+        for language in detect_project_languages(entry.path()) {
+            match detected.iter_mut().find(|d| d.language == language) {
+                Some(found) => found.paths.push(relative.clone()),
+                None => detected.push(DetectedLanguage {
+                    language,
+                    paths: vec![relative.clone()],
+                }),
+            }
+        }
+    }
+    Ok(detected)
+}
+
+/// Absolute directories of the Node.js packages whose `node_modules` should be
+/// isolated from the host. The root counts whenever it has a `node_modules`.
+pub fn node_package_dirs(project_dir: &Path, detected: &[DetectedLanguage]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = detected
+        .iter()
+        .filter(|d| d.language == ProjectLanguage::NodeJs)
+        .flat_map(|d| d.paths.iter().map(|p| project_dir.join(p)))
+        .collect();
+    if project_dir.join("node_modules").exists() && !dirs.iter().any(|d| d == project_dir) {
+        dirs.insert(0, project_dir.to_path_buf());
+    }
+    dirs
+}
+
+/// Marker-file detection for a single directory.
 pub fn detect_project_languages(dir: &Path) -> Vec<ProjectLanguage> {
     let mut langs = Vec::new();
     if dir.join("Cargo.toml").exists() {
@@ -78,13 +219,56 @@ pub fn detect_project_languages(dir: &Path) -> Vec<ProjectLanguage> {
     if dir.join("Gemfile").exists() {
         langs.push(ProjectLanguage::Ruby);
     }
+    let java_markers = [
+        "pom.xml",
+        "build.gradle",
+        "build.gradle.kts",
+        "settings.gradle",
+        "settings.gradle.kts",
+    ];
+    if java_markers.iter().any(|marker| dir.join(marker).exists()) {
+        langs.push(ProjectLanguage::Java);
+    }
+    if dir.join("global.json").exists()
+        || has_file_with_extension(dir, &["csproj", "fsproj", "sln"])
+    {
+        langs.push(ProjectLanguage::DotNet);
+    }
+    if dir.join("mix.exs").exists() {
+        langs.push(ProjectLanguage::Elixir);
+    }
+    if dir.join("deno.json").exists() || dir.join("deno.jsonc").exists() {
+        langs.push(ProjectLanguage::Deno);
+    }
+    if dir.join("bun.lockb").exists() || dir.join("bun.lock").exists() {
+        langs.push(ProjectLanguage::Bun);
+    }
+    if dir.join("CMakeLists.txt").exists() {
+        langs.push(ProjectLanguage::Cpp);
+    }
     langs
 }
 
+// Project files such as `Api.csproj` are named after the project, so match on extension
+fn has_file_with_extension(dir: &Path, extensions: &[&str]) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        path.is_file()
+            && path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| extensions.contains(&ext))
+    })
+}
+
 pub fn ensure_language_tools(container_name: &str, languages: &[ProjectLanguage]) -> Result<()> {
     for lang in languages {
         let tool = lang.tool();
-        let check_status = Command::new("docker")
+        let check_status = runtime()
+            .command()
             .args([
                 "exec",
                 container_name,
@@ -98,7 +282,8 @@ pub fn ensure_language_tools(container_name: &str, languages: &[ProjectLanguage]
             continue;
         }
         println!("Installing toolchain for {}...", lang.name());
-        let install_status = Command::new("docker")
+        let install_status = runtime()
+            .command()
             .args(["exec", container_name, "bash", "-lc", lang.install_cmd()])
             .status()
             .with_context(|| format!("Failed to install {}", tool))?;
@@ -110,19 +295,21 @@ pub fn ensure_language_tools(container_name: &str, languages: &[ProjectLanguage]
 }
 
 // For languages that require per-project dependencies, prepare them inside the container.
-// Currently supports Node.js: runs `npm ci` if lockfile exists, else `npm install`.
-// For Node.js projects, copy host node_modules into the container's isolated
+// For Node.js packages, copy host node_modules into the container's isolated
 // volume path to keep installs off the host while avoiding a full reinstall.
 pub fn sync_node_modules_from_host(
     container_name: &str,
     project_dir: &Path,
-    languages: &[ProjectLanguage],
+    detected: &[DetectedLanguage],
 ) -> Result<()> {
-    if !languages.iter().any(|l| *l == ProjectLanguage::NodeJs) {
-        return Ok(());
+    for package_dir in node_package_dirs(project_dir, detected) {
+        sync_package_node_modules(container_name, &package_dir)?;
     }
+    Ok(())
+}
 
-    let host_nm = project_dir.join("node_modules");
+fn sync_package_node_modules(container_name: &str, package_dir: &Path) -> Result<()> {
+    let host_nm = package_dir.join("node_modules");
     if !host_nm.is_dir() {
         // Nothing to copy; host has no node_modules
         return Ok(());
@@ -134,7 +321,8 @@ pub fn sync_node_modules_from_host(
         "sudo mkdir -p '{}' && sudo chown -R $(id -u):$(id -g) '{}'",
         container_nm, container_nm
     );
-    let mkdir_status = Command::new("docker")
+    let mkdir_status = runtime()
+        .command()
         .args(["exec", container_name, "bash", "-lc", &mkdir_cmd])
         .status()
         .context("Failed to ensure node_modules path inside container")?;
@@ -145,7 +333,8 @@ pub fn sync_node_modules_from_host(
     // Copy contents of host node_modules into container path
     let src = format!("{}/.", host_nm.display());
     let dest = format!("{}:{}", container_name, container_nm);
-    let cp_status = Command::new("docker")
+    let cp_status = runtime()
+        .command()
         .args(["cp", &src, &dest])
         .status()
         .context("Failed to copy node_modules to container")?;
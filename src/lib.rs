@@ -4,11 +4,19 @@
     clippy::manual_contains
 )]
 
+pub mod agents;
 pub mod cli;
+pub mod completions;
 pub mod config;
 pub mod container;
+pub mod engine;
 pub mod language;
+pub mod output;
+pub mod project_config;
+pub mod proxy;
+pub mod secrets;
 pub mod server;
 pub mod settings;
 pub mod state;
+pub mod toolchain;
 pub mod worktree;
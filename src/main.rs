@@ -4,133 +4,361 @@
     clippy::manual_contains
 )]
 
+mod agents;
 mod cli;
+mod completions;
 mod config;
 mod container;
+mod engine;
 mod language;
+mod output;
+mod project_config;
+mod proxy;
+mod secrets;
 mod server;
 mod settings;
 mod state;
+mod toolchain;
 mod worktree;
 
 use anyhow::{Context, Result};
 use base64::Engine as _;
 use std::env;
-use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
 use std::time::Duration;
 
-use cli::{Cli, Commands};
+use agents::{Agent, AgentRegistry};
+use cli::{Cli, Commands, ConfigCommand, ImageCommand, OutputFormat, SecretsCommand};
+use completions::completion_script;
 use container::{
-    auto_remove_old_containers, check_docker_availability, cleanup_containers, create_container,
-    generate_container_name, list_all_containers, list_containers, resume_container,
+    apply_change, auto_remove_old_containers, build_agent_command, check_runtime_availability,
+    cleanup_containers, container_logs, create_container, exec_in_container,
+    find_isolated_container, generate_container_name, inspect_container, is_container_running,
+    list_all_containers, list_containers, prune_images, rebuild_image, remove_container,
+    resolve_container, resume_container, run_headless, stop_container, workspace_changes,
+    workspace_diff, MaskPatterns,
 };
-use settings::load_settings;
+use engine::{runtime, select_runtime};
+use output::{web_url, ContainerSummary, JsonOutput};
+use project_config::{
+    find_project_config, load_effective_settings, EffectiveSettings, PROJECT_CONFIG_FILE,
+};
+use server::auth::ServerAuth;
+use server::listener::web_base_url;
+use settings::{settings_file_path, validate_settings_file, MountSpec, ServerSettings, Settings};
 use state::{clear_last_container, load_last_container, save_last_container};
 use worktree::create_worktree;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let cli = Cli::parse_args();
+    let mut current_dir = env::current_dir().context("Failed to get current directory")?;
+    if let Some(Commands::Config {
+        command: ConfigCommand::Validate,
+    }) = &cli.command
+    {
+        return validate_config(&current_dir).map(|()| ExitCode::SUCCESS);
+    }
+    let effective = load_effective_settings(&current_dir, cli.settings_overrides())?;
+    for warning in effective.warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+    select_runtime(effective.settings.runtime.as_deref())?;
 
     if let Some(cmd) = &cli.command {
         match cmd {
-            Commands::Serve { daemon } => {
-                check_docker_availability()?;
+            Commands::Serve { daemon, .. } => {
+                check_runtime_availability()?;
                 if *daemon {
-                    let exe = env::current_exe()?;
-                    Command::new(exe)
-                        .arg("serve")
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .stdin(Stdio::null())
-                        .spawn()
+                    spawn_server(&effective.settings.server)
                         .context("failed to start daemonized server")?;
                 } else {
-                    server::serve().await?;
+                    server::serve(&effective.settings.server, web_host(&effective.settings))
+                        .await?;
                 }
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
             }
-            Commands::Stop => {
+            Commands::Stop { container: None } => {
                 server::stop().await?;
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Completions { shell } => {
+                print!("{}", completion_script(*shell));
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::ContainerNames => {
+                check_runtime_availability()?;
+                let mut names = list_containers(&current_dir)?;
+                for info in list_all_containers()? {
+                    if !names.contains(&info.name) {
+                        names.push(info.name);
+                    }
+                }
+                for name in names {
+                    println!("{}", name);
+                }
+                return Ok(ExitCode::SUCCESS);
             }
-            Commands::Restart { daemon } => {
-                let _ = server::stop().await;
-                check_docker_availability()?;
+            Commands::Exec { container, command } => {
+                check_runtime_availability()?;
+                let info = resolve_container(container, &current_dir)?;
+                if !is_container_running(&info.name)? {
+                    anyhow::bail!(
+                        "Container {} is not running; start it with `codesandbox start {}`",
+                        info.name,
+                        info.name
+                    );
+                }
+                let workdir = info.project_path.as_deref().unwrap_or("/");
+                let code = exec_in_container(&info.name, workdir, command)?;
+                return Ok(ExitCode::from(code as u8));
+            }
+            Commands::Logs {
+                container,
+                follow,
+                tail,
+            } => {
+                check_runtime_availability()?;
+                let info = resolve_container(container, &current_dir)?;
+                container_logs(&info.name, *follow, tail.as_deref())?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Restart { daemon, .. } => {
+                if server::stop().await.is_ok() {
+                    // Wait for the old server to release its address
+                    for _ in 0..30 {
+                        if server::running_server().await?.is_none() {
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+                check_runtime_availability()?;
                 if *daemon {
-                    let exe = env::current_exe()?;
-                    Command::new(exe)
-                        .arg("serve")
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .stdin(Stdio::null())
-                        .spawn()
+                    spawn_server(&effective.settings.server)
                         .context("failed to start daemonized server")?;
                 } else {
-                    server::serve().await?;
+                    server::serve(&effective.settings.server, web_host(&effective.settings))
+                        .await?;
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Config {
+                command: ConfigCommand::Show,
+            } => {
+                print_effective_settings(&effective);
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Proxy { port } => {
+                check_runtime_availability()?;
+                let port = port
+                    .or(effective.settings.network.proxy_port)
+                    .unwrap_or(proxy::DEFAULT_PROXY_PORT);
+                proxy::run(port).await?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Diff {
+                name_only,
+                container,
+            } => {
+                check_runtime_availability()?;
+                let name = match container {
+                    Some(name) => name.clone(),
+                    None => find_isolated_container(&current_dir)?,
+                };
+                if *name_only {
+                    for change in workspace_changes(&name, &current_dir)? {
+                        println!("{}", change);
+                    }
+                } else {
+                    print!("{}", workspace_diff(&name, &current_dir)?);
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Apply {
+                paths,
+                dry_run,
+                container,
+            } => {
+                check_runtime_availability()?;
+                let name = match container {
+                    Some(name) => name.clone(),
+                    None => find_isolated_container(&current_dir)?,
+                };
+                apply_changes(
+                    &name,
+                    &current_dir,
+                    paths,
+                    &effective.settings.env_files,
+                    *dry_run,
+                )?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Secrets { command } => {
+                manage_secrets(command)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Commands::Image { command } => {
+                check_runtime_availability()?;
+                match command {
+                    ImageCommand::Rebuild => {
+                        let tag = rebuild_image(&effective.settings).await?;
+                        println!("Rebuilt {}", tag);
+                    }
+                    ImageCommand::Prune => {
+                        let pruned = prune_images()?;
+                        for tag in pruned.removed.iter() {
+                            println!("Removed {}", tag);
+                        }
+                        for tag in pruned.kept.iter() {
+                            println!("Kept {} (in use by a container)", tag);
+                        }
+                        for (tag, error) in pruned.failed.iter() {
+                            println!("Failed to remove {}: {}", tag, error);
+                        }
+                        if !pruned.failed.is_empty() {
+                            anyhow::bail!("Failed to remove {} image(s)", pruned.failed.len());
+                        }
+                        if pruned.removed.is_empty() && pruned.kept.is_empty() {
+                            println!("No sandbox images found");
+                        }
+                    }
                 }
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
             }
             _ => {}
         }
     }
 
-    let mut current_dir = env::current_dir().context("Failed to get current directory")?;
     if let Some(branch) = &cli.worktree {
         current_dir = create_worktree(&current_dir, branch)
             .with_context(|| format!("Failed to create worktree for branch {}", branch))?;
     }
-    let settings = load_settings().unwrap_or_default();
-    let web_host = settings.web_host.as_deref().unwrap_or("localhost");
+    let settings = effective.settings;
+    let registry = AgentRegistry::from_settings(&settings)?;
+    let agent = registry.resolve(settings.agent.as_deref())?;
+    let web_base = web_base_url(web_host(&settings), &settings.server)?;
+    let web_base = web_base.as_str();
 
-    check_docker_availability()?;
+    // Everything below is either ls/ps or the container lifecycle; in JSON
+    // mode stdout carries a single document and nothing is interactive
+    let mut json = match cli.output_format() {
+        OutputFormat::Json => Some(JsonOutput::capture()?),
+        OutputFormat::Table => None,
+    };
+    let interactive = json.is_none() && atty::is(atty::Stream::Stdin);
+
+    check_runtime_availability()?;
     auto_remove_old_containers(settings.auto_remove_minutes.unwrap_or(60))?;
-    let skip_permission_flag = settings
-        .skip_permission_flags
-        .iter()
-        .find(|(agent, _)| agent.eq_ignore_ascii_case(cli.agent.command()))
-        .map(|(_, flag)| flag.to_string());
 
-    // Determine whether to use web flow
-    let use_web = cli.web || settings.web.unwrap_or(false);
+    // Determine whether to use web flow (--web is folded into settings)
+    let use_web = settings.web.unwrap_or(false);
+
+    match cli.command.as_ref() {
+        Some(Commands::Attach { container }) | Some(Commands::Start { container }) => {
+            let info = resolve_container(container, &current_dir)?;
+            let attach = matches!(cli.command, Some(Commands::Attach { .. }));
+            // Attach with the agent and from the directory the container was created for
+            let agent = info
+                .agent
+                .as_deref()
+                .and_then(|name| registry.get(name))
+                .cloned()
+                .unwrap_or(agent);
+            let project_dir = info
+                .project_path
+                .as_deref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| current_dir.clone());
+            if attach {
+                env::set_current_dir(&project_dir).with_context(|| {
+                    format!("Failed to change directory to {}", project_dir.display())
+                })?;
+            }
+            resume_container(
+                &info.name,
+                &agent,
+                false,
+                &settings,
+                cli.shell,
+                attach && json.is_none() && !use_web,
+            )
+            .await?;
+            if let Some(json) = json.as_mut() {
+                return print_summary(json, &info.name, web_base).map(|()| ExitCode::SUCCESS);
+            }
+            if attach && use_web {
+                maybe_open_web(&info.name, &agent, &project_dir, false, &settings).await?;
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::Stop {
+            container: Some(container),
+        }) => {
+            let info = resolve_container(container, &current_dir)?;
+            stop_container(&info.name)?;
+            if let Some(json) = json.as_mut() {
+                return print_summary(json, &info.name, web_base).map(|()| ExitCode::SUCCESS);
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::Rm { container }) => {
+            let info = resolve_container(container, &current_dir)?;
+            remove_container(&info.name)?;
+            if load_last_container()?.as_deref() == Some(info.name.as_str()) {
+                clear_last_container()?;
+            }
+            if let Some(json) = json.as_mut() {
+                return json
+                    .print(&serde_json::json!({ "removed": [info.name] }))
+                    .map(|()| ExitCode::SUCCESS);
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
 
     if cli.cleanup {
-        cleanup_containers(&current_dir)?;
+        let removed = cleanup_containers(&current_dir)?;
         clear_last_container()?;
+        if let Some(json) = json.as_mut() {
+            return json
+                .print(&serde_json::json!({ "removed": removed }))
+                .map(|()| ExitCode::SUCCESS);
+        }
         println!(
             "Removed all Code Sandbox containers for directory {}",
             current_dir.display()
         );
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
     if cli.continue_ {
+        // Fail before touching the container when the agent cannot resume
+        if !cli.shell {
+            agent.continue_args()?;
+        }
         match load_last_container()? {
             Some(container_name) => {
                 resume_container(
                     &container_name,
-                    &cli.agent,
+                    &agent,
                     true,
-                    skip_permission_flag.as_deref(),
+                    &settings,
                     cli.shell,
-                    !use_web,
+                    interactive && !use_web,
                 )
                 .await?;
+                if let Some(json) = json.as_mut() {
+                    return print_summary(json, &container_name, web_base)
+                        .map(|()| ExitCode::SUCCESS);
+                }
                 if use_web {
-                    maybe_open_web(
-                        &container_name,
-                        &cli.agent,
-                        &current_dir,
-                        true,
-                        skip_permission_flag.as_deref(),
-                        web_host,
-                    )
-                    .await?;
+                    maybe_open_web(&container_name, &agent, &current_dir, true, &settings).await?;
                 }
-                return Ok(());
+                return Ok(ExitCode::SUCCESS);
             }
             None => {
                 anyhow::bail!("No previous container found. Run without --continue to create a new container.");
@@ -140,19 +368,43 @@ async fn main() -> Result<()> {
 
     if let Some(Commands::Ps) = cli.command.as_ref() {
         let containers = list_all_containers()?;
+        if let Some(json) = json.as_mut() {
+            let auth = ServerAuth::load()?;
+            let summaries = containers
+                .iter()
+                .map(|info| {
+                    Ok(ContainerSummary::new(
+                        info,
+                        web_base,
+                        &auth.mint(&info.name)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return json.print(&summaries).map(|()| ExitCode::SUCCESS);
+        }
         if containers.is_empty() {
             println!("No running Code Sandbox containers found.");
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
-        println!("{:<4}{:<20}{:<20}Directory", "No.", "Project", "Container");
-        for (i, (project, name, path)) in containers.iter().enumerate() {
+        println!(
+            "{:<4}{:<20}{:<20}{:<32}Directory",
+            "No.", "Project", "Container", "Limits"
+        );
+        for (i, info) in containers.iter().enumerate() {
             println!(
-                "{:<4}{:<20}{:<20}{}",
+                "{:<4}{:<20}{:<20}{:<32}{}",
                 i + 1,
-                project,
-                name,
-                path.as_deref().unwrap_or("")
+                info.project_name(),
+                info.name,
+                info.limits.summary(),
+                info.project_path.as_deref().unwrap_or("")
             );
+            if !info.mounts.is_empty() {
+                println!("    mounts: {}", info.mounts.join(", "));
+            }
+        }
+        if !interactive {
+            return Ok(ExitCode::SUCCESS);
         }
         print!(
             "Select a container to attach (number), or type 'cd <number>' to open its directory: "
@@ -162,12 +414,12 @@ async fn main() -> Result<()> {
         io::stdin().read_line(&mut input)?;
         let input = input.trim();
         if input.is_empty() {
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
         if let Some(rest) = input.strip_prefix("cd ") {
             match rest.parse::<usize>() {
                 Ok(num) if num >= 1 && num <= containers.len() => {
-                    if let Some(path) = &containers[num - 1].2 {
+                    if let Some(path) = &containers[num - 1].project_path {
                         let escaped = path.replace('\'', "'\\''");
                         Command::new("bash")
                             .args(["-c", &format!("cd '{}' && exec bash", escaped)])
@@ -179,33 +431,17 @@ async fn main() -> Result<()> {
                 }
                 _ => println!("Invalid selection"),
             }
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
         match input.parse::<usize>() {
             Ok(num) if num >= 1 && num <= containers.len() => {
-                if let Some(path) = &containers[num - 1].2 {
+                if let Some(path) = &containers[num - 1].project_path {
                     env::set_current_dir(path)
                         .with_context(|| format!("Failed to change directory to {}", path))?;
-                    let (_, name, _) = &containers[num - 1];
-                    resume_container(
-                        name,
-                        &cli.agent,
-                        false,
-                        skip_permission_flag.as_deref(),
-                        cli.shell,
-                        !use_web,
-                    )
-                    .await?;
+                    let name = &containers[num - 1].name;
+                    resume_container(name, &agent, false, &settings, cli.shell, !use_web).await?;
                     if use_web {
-                        maybe_open_web(
-                            name,
-                            &cli.agent,
-                            &current_dir,
-                            false,
-                            skip_permission_flag.as_deref(),
-                            web_host,
-                        )
-                        .await?;
+                        maybe_open_web(name, &agent, &current_dir, false, &settings).await?;
                     }
                 } else {
                     println!("Path not available for selected container");
@@ -213,11 +449,22 @@ async fn main() -> Result<()> {
             }
             _ => println!("Invalid selection"),
         }
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
     if let Some(Commands::Ls) = cli.command.as_ref() {
         let containers = list_containers(&current_dir)?;
+        if let Some(json) = json.as_mut() {
+            let auth = ServerAuth::load()?;
+            let mut summaries = Vec::new();
+            for name in containers.iter() {
+                if let Some(info) = inspect_container(name)? {
+                    let token = auth.mint(name)?;
+                    summaries.push(ContainerSummary::new(&info, web_base, &token));
+                }
+            }
+            return json.print(&summaries).map(|()| ExitCode::SUCCESS);
+        }
         if containers.is_empty() {
             println!(
                 "No Code Sandbox containers found for directory {}",
@@ -229,15 +476,31 @@ async fn main() -> Result<()> {
             } else {
                 println!("\nCurrently running containers:");
                 println!("{:<20}Container", "Project");
-                for (project, name, _) in global {
-                    println!("{:<20}{}", project, name);
+                for info in global {
+                    println!("{:<20}{}", info.project_name(), info.name);
                 }
             }
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
 
         for (i, name) in containers.iter().enumerate() {
-            println!("{}: {}", i + 1, name);
+            let mut details = Vec::new();
+            if let Some(info) = inspect_container(name).ok().flatten() {
+                if !info.limits.is_empty() {
+                    details.push(info.limits.summary());
+                }
+                if !info.mounts.is_empty() {
+                    details.push(format!("mounts: {}", info.mounts.join(", ")));
+                }
+            }
+            if details.is_empty() {
+                println!("{}: {}", i + 1, name);
+            } else {
+                println!("{}: {} ({})", i + 1, name, details.join("; "));
+            }
+        }
+        if !interactive {
+            return Ok(ExitCode::SUCCESS);
         }
 
         print!("Select a container to attach (number, or press Enter to cancel): ");
@@ -246,182 +509,356 @@ async fn main() -> Result<()> {
         io::stdin().read_line(&mut input)?;
         let input = input.trim();
         if input.is_empty() {
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
 
         match input.parse::<usize>() {
             Ok(num) if num >= 1 && num <= containers.len() => {
                 let selected = &containers[num - 1];
-                resume_container(
-                    selected,
-                    &cli.agent,
-                    false,
-                    skip_permission_flag.as_deref(),
-                    cli.shell,
-                    !use_web,
-                )
-                .await?;
+                resume_container(selected, &agent, false, &settings, cli.shell, !use_web).await?;
                 if use_web {
-                    maybe_open_web(
-                        selected,
-                        &cli.agent,
-                        &current_dir,
-                        false,
-                        skip_permission_flag.as_deref(),
-                        web_host,
-                    )
-                    .await?;
+                    maybe_open_web(selected, &agent, &current_dir, false, &settings).await?;
                 }
             }
             _ => println!("Invalid selection"),
         }
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
-    if cli.worktree.is_some() {
+    let headless = match cli.command.as_ref() {
+        Some(Commands::Run {
+            prompt,
+            prompt_file,
+            transcript,
+            rm,
+        }) => Some((
+            read_prompt(prompt.as_deref(), prompt_file.as_deref())?,
+            transcript.clone(),
+            *rm,
+        )),
+        _ => None,
+    };
+
+    if cli.worktree.is_some() && headless.is_none() {
         let containers = list_containers(&current_dir)?;
         if let Some(latest) = containers.first() {
             println!("Attaching to existing container for worktree: {}", latest);
             resume_container(
                 latest,
-                &cli.agent,
+                &agent,
                 false,
-                skip_permission_flag.as_deref(),
+                &settings,
                 cli.shell,
-                !use_web,
+                interactive && !use_web,
             )
             .await?;
+            if let Some(json) = json.as_mut() {
+                return print_summary(json, latest, web_base).map(|()| ExitCode::SUCCESS);
+            }
             if use_web {
-                maybe_open_web(
-                    latest,
-                    &cli.agent,
-                    &current_dir,
-                    false,
-                    skip_permission_flag.as_deref(),
-                    web_host,
-                )
-                .await?;
+                maybe_open_web(latest, &agent, &current_dir, false, &settings).await?;
             }
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
     }
 
-    let additional_dir = match &cli.add_dir {
-        Some(dir) => Some(
-            fs::canonicalize(dir)
-                .with_context(|| format!("Failed to canonicalize path {}", dir.display()))?,
-        ),
-        None => None,
-    };
+    let additional_mounts = cli
+        .add_dir
+        .iter()
+        .map(|spec| MountSpec::parse(spec)?.canonicalize())
+        .collect::<Result<Vec<_>>>()?;
 
-    let container_name = generate_container_name(&current_dir, &cli.agent);
+    let container_name = generate_container_name(&current_dir, &agent);
 
     println!(
         "Starting {} Code Sandbox container: {container_name}",
-        cli.agent
+        agent
     );
 
     create_container(
         &container_name,
         &current_dir,
-        additional_dir.as_deref(),
-        &cli.agent,
-        skip_permission_flag.as_deref(),
+        &additional_mounts,
+        &agent,
+        &settings,
         cli.shell,
-        !use_web,
+        interactive && !use_web && headless.is_none(),
     )
     .await?;
     save_last_container(&container_name)?;
-    let token = container_name.clone();
+
+    if let Some((prompt, transcript, rm)) = headless {
+        let transcript = transcript.unwrap_or_else(|| {
+            settings_file_path()
+                .with_file_name("runs")
+                .join(format!("{}.log", container_name))
+        });
+        let run = run_headless(
+            &container_name,
+            &current_dir,
+            &agent,
+            &settings,
+            &prompt,
+            &transcript,
+        )?;
+        if rm {
+            remove_container(&container_name)?;
+            clear_last_container()?;
+        }
+        if let Some(json) = json.as_mut() {
+            json.print(&serde_json::json!({
+                "container": container_name,
+                "agent": agent.command(),
+                "exit_code": run.exit_code,
+                "transcript": transcript,
+                "removed": rm,
+                "diff": run.diff,
+            }))?;
+        } else {
+            println!();
+            println!("{} exited with code {}", agent, run.exit_code);
+            println!("Transcript: {}", transcript.display());
+            if run.diff.is_empty() {
+                println!("No changes to the project");
+            } else {
+                println!("Changes to the project:");
+                print!("{}", run.diff);
+            }
+        }
+        return Ok(ExitCode::from(run.exit_code as u8));
+    }
+    if let Some(json) = json.as_mut() {
+        return print_summary(json, &container_name, web_base).map(|()| ExitCode::SUCCESS);
+    }
 
     println!("Container {container_name} started successfully!");
     println!(
-        "Access the terminal at: http://{}:6789/container/{container_name}?token={token}",
-        web_host
+        "Access the terminal at: {}",
+        web_url(
+            web_base,
+            &container_name,
+            &ServerAuth::load()?.mint(&container_name)?
+        )
     );
     println!(
-        "To attach to the container manually, run: docker exec -it {container_name} /bin/bash"
+        "To attach to the container manually, run: {} exec -it {container_name} /bin/bash",
+        runtime().binary()
     );
 
     if use_web {
-        maybe_open_web(
-            &container_name,
-            &cli.agent,
-            &current_dir,
-            false,
-            skip_permission_flag.as_deref(),
-            web_host,
-        )
-        .await?;
+        maybe_open_web(&container_name, &agent, &current_dir, false, &settings).await?;
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The prompt for `codesandbox run`, from `--prompt` or `--prompt-file`.
+fn read_prompt(prompt: Option<&str>, prompt_file: Option<&Path>) -> Result<String> {
+    let prompt = match (prompt, prompt_file) {
+        (Some(prompt), _) => prompt.to_string(),
+        (None, Some(path)) if path == Path::new("-") => {
+            let mut prompt = String::new();
+            io::stdin()
+                .read_to_string(&mut prompt)
+                .context("Failed to read the prompt from stdin")?;
+            prompt
+        }
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prompt file {}", path.display()))?,
+        (None, None) => anyhow::bail!("Give the prompt with --prompt or --prompt-file"),
+    };
+    if prompt.trim().is_empty() {
+        anyhow::bail!("The prompt is empty");
+    }
+    Ok(prompt)
+}
+
+fn print_summary(json: &mut JsonOutput, container_name: &str, web_base: &str) -> Result<()> {
+    let info = inspect_container(container_name)?
+        .with_context(|| format!("Container {} not found", container_name))?;
+    let token = ServerAuth::load()?.mint(container_name)?;
+    json.print(&ContainerSummary::new(&info, web_base, &token))
 }
 
-fn build_agent_command_for_web(
+fn apply_changes(
+    container_name: &str,
     current_dir: &Path,
-    agent: &cli::Agent,
-    agent_continue: bool,
-    skip_permission_flag: Option<&str>,
-) -> String {
-    // Safely quote project path for bash -c
-    let path_str = current_dir.display().to_string();
-    let escaped = path_str.replace('\'', "'\\''");
-    let mut command = format!(
-        "cd '{}' && export PATH=\"$HOME/.local/bin:$PATH\" && {}",
-        escaped,
-        agent.command()
-    );
-    if agent_continue {
-        command.push_str(" --continue");
+    paths: &[std::path::PathBuf],
+    env_files: &[String],
+    dry_run: bool,
+) -> Result<()> {
+    // Accept paths relative to the project or absolute paths inside it
+    let filters: Vec<&Path> = paths
+        .iter()
+        .map(|p| p.strip_prefix(current_dir).unwrap_or(p))
+        .collect();
+    let changes: Vec<_> = workspace_changes(container_name, current_dir)?
+        .into_iter()
+        .filter(|c| filters.is_empty() || filters.iter().any(|f| c.matches(f)))
+        .collect();
+    if changes.is_empty() {
+        println!("No changes to apply from {}", container_name);
+        return Ok(());
     }
-    if let Some(flag) = skip_permission_flag {
-        command.push(' ');
-        command.push_str(flag);
+    let masked = MaskPatterns::new(current_dir, env_files)?;
+    for change in changes.iter() {
+        let is_dir = current_dir.join(&change.path).is_dir();
+        if masked.matches(&change.path, is_dir) {
+            println!("Skipping {} (masked env file)", change.path.display());
+            continue;
+        }
+        if !dry_run {
+            apply_change(container_name, current_dir, change)?;
+        }
+        println!("{}", change);
     }
-    command
+    Ok(())
 }
 
-async fn ensure_server_running() -> Result<()> {
-    // Try to contact the server briefly; if unavailable, spawn it in the background
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_millis(300))
-        .build()?;
-    let reachable = client.get("http://127.0.0.1:6789/").send().await.is_ok();
-    if !reachable {
-        let exe = env::current_exe()?;
-        let _child = Command::new(exe)
-            .arg("serve")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .stdin(Stdio::null())
-            .spawn()
-            .context("failed to start server in background")?;
-        // Give it a moment to bind
-        tokio::time::sleep(Duration::from_millis(300)).await;
+fn manage_secrets(command: &SecretsCommand) -> Result<()> {
+    let path = secrets::store_path();
+    let (mut entries, passphrase) = secrets::open_store()?;
+    match command {
+        SecretsCommand::Set { key } => {
+            let value = secrets::prompt_hidden(&format!("Value for {}: ", key))?;
+            if value.is_empty() {
+                anyhow::bail!("Refusing to store an empty value for {}", key);
+            }
+            entries.insert(key.clone(), value);
+            secrets::save_store(&path, &entries, &passphrase)?;
+            println!("Stored {} in {}", key, path.display());
+        }
+        SecretsCommand::Rm { key } => {
+            if entries.remove(key).is_none() {
+                anyhow::bail!("{} is not in {}", key, path.display());
+            }
+            secrets::save_store(&path, &entries, &passphrase)?;
+            println!("Removed {} from {}", key, path.display());
+        }
+        SecretsCommand::List => {
+            for key in entries.keys() {
+                println!("{}", key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_config(current_dir: &Path) -> Result<()> {
+    let global = settings_file_path();
+    let mut files = Vec::new();
+    if global.exists() {
+        files.push(global);
+    } else {
+        println!("{}: not found, using defaults", global.display());
+    }
+    match find_project_config(current_dir) {
+        Some(project) => files.push(project),
+        None => println!("No {} found for this directory", PROJECT_CONFIG_FILE),
+    }
+
+    let mut invalid = false;
+    for path in files {
+        match validate_settings_file(&path) {
+            Ok(warnings) if warnings.is_empty() => println!("{}: ok", path.display()),
+            Ok(warnings) => {
+                for warning in warnings {
+                    println!("{}: warning: {}", path.display(), warning);
+                }
+            }
+            Err(e) => {
+                println!("error: {:#}", e);
+                invalid = true;
+            }
+        }
+    }
+    if invalid {
+        anyhow::bail!("Configuration is invalid");
     }
     Ok(())
 }
 
+fn print_effective_settings(effective: &EffectiveSettings) {
+    let describe = |path: &Option<std::path::PathBuf>| {
+        path.as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "(none)".to_string())
+    };
+    println!("Global settings: {}", describe(&effective.global_file));
+    println!("Project config:  {}", describe(&effective.project_file));
+    println!();
+    let width = effective.values.keys().map(String::len).max().unwrap_or(0);
+    for (key, value) in effective.values.iter() {
+        let source = effective
+            .sources
+            .get(key)
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        println!("{:<width$} = {}  ({})", key, value, source, width = width);
+    }
+}
+
+fn web_host(settings: &Settings) -> &str {
+    settings.web_host.as_deref().unwrap_or("localhost")
+}
+
+/// Start `codesandbox serve` in the background with the effective server
+/// settings, which it might not see from its own working directory.
+fn spawn_server(settings: &ServerSettings) -> Result<()> {
+    let mut command = Command::new(env::current_exe()?);
+    command.args(["--runtime", runtime().name(), "serve"]);
+    if let Some(bind) = &settings.bind {
+        command.args(["--bind", bind]);
+    }
+    if settings.tls.unwrap_or(false) {
+        command.arg("--tls");
+    }
+    if let (Some(cert), Some(key)) = (&settings.tls_cert, &settings.tls_key) {
+        command.args(["--tls-cert", cert, "--tls-key", key]);
+    }
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .stdin(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+async fn ensure_server_running(settings: &ServerSettings) -> Result<()> {
+    if server::running_server().await?.is_some() {
+        return Ok(());
+    }
+    spawn_server(settings).context("failed to start server in background")?;
+    // Wait until it has bound and recorded its address
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if server::running_server().await?.is_some() {
+            return Ok(());
+        }
+    }
+    anyhow::bail!("The API server did not start; run `codesandbox serve` to see why")
+}
+
 async fn maybe_open_web(
     container_name: &str,
-    agent: &cli::Agent,
+    agent: &Agent,
     current_dir: &Path,
     agent_continue: bool,
-    skip_permission_flag: Option<&str>,
-    web_host: &str,
+    settings: &Settings,
 ) -> Result<()> {
-    ensure_server_running().await?;
+    ensure_server_running(&settings.server).await?;
+    let web_base = web_base_url(web_host(settings), &settings.server)?;
 
-    let token = container_name;
-    let cmd = build_agent_command_for_web(current_dir, agent, agent_continue, skip_permission_flag);
+    // A session token only opens this container's terminal and changes
+    let token = ServerAuth::load()?.mint(container_name)?;
+    let cmd = build_agent_command(current_dir, agent, agent_continue)?;
     let run_b64 = base64::engine::general_purpose::STANDARD.encode(cmd.as_bytes());
     // Also pass the desired working directory so the shell starts in project root
     let cwd_b64 = base64::engine::general_purpose::STANDARD
         .encode(current_dir.display().to_string().as_bytes());
     let url = format!(
-        "http://{}:6789/container/{}?token={}&run_b64={}&cwd_b64={}",
-        web_host, container_name, token, run_b64, cwd_b64
+        "{}/container/{}?token={}&run_b64={}&cwd_b64={}",
+        web_base, container_name, token, run_b64, cwd_b64
     );
 
     // Try to open the system browser
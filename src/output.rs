@@ -0,0 +1,88 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::container::ContainerInfo;
+use crate::settings::ResourceLimits;
+
+/// URL of the web terminal for `container_name` under `web_base` (scheme,
+/// host and port of the server), signed in with the session `token` minted
+/// for it.
+pub fn web_url(web_base: &str, container_name: &str, token: &str) -> String {
+    format!("{}/container/{}?token={}", web_base, container_name, token)
+}
+
+/// A container as printed by `ls`, `ps` and the lifecycle commands with `--json`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ContainerSummary {
+    pub name: String,
+    pub project_path: Option<String>,
+    pub agent: Option<String>,
+    pub branch: Option<String>,
+    pub status: Option<String>,
+    pub created: Option<String>,
+    pub web_url: String,
+    pub isolated: bool,
+    pub limits: ResourceLimits,
+    pub mounts: Vec<String>,
+}
+
+impl ContainerSummary {
+    pub fn new(info: &ContainerInfo, web_base: &str, token: &str) -> Self {
+        Self {
+            name: info.name.clone(),
+            project_path: info.project_path.clone(),
+            agent: info.agent.clone(),
+            branch: info.branch.clone(),
+            status: info.status.clone(),
+            created: info.created.clone(),
+            web_url: web_url(web_base, &info.name, token),
+            isolated: info.isolated,
+            limits: info.limits.clone(),
+            mounts: info.mounts.clone(),
+        }
+    }
+}
+
+/// Stdout reserved for a JSON document. While it exists, everything else
+/// written to stdout, including the output of child processes such as image
+/// builds, goes to stderr so that the document can be parsed as is.
+pub struct JsonOutput {
+    out: Box<dyn Write>,
+}
+
+impl JsonOutput {
+    #[cfg(unix)]
+    pub fn capture() -> Result<Self> {
+        use std::fs::File;
+        use std::os::fd::FromRawFd;
+
+        io::stdout().flush().ok();
+        // SAFETY: plain descriptor juggling on the process's own stdio; the
+        // duplicate is owned by the returned `File`.
+        let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let out = unsafe { File::from_raw_fd(saved) };
+        Ok(Self { out: Box::new(out) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn capture() -> Result<Self> {
+        Ok(Self {
+            out: Box::new(io::stdout()),
+        })
+    }
+
+    pub fn print<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        io::stdout().flush().ok();
+        serde_json::to_writer_pretty(&mut self.out, value)?;
+        writeln!(self.out)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
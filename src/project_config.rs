@@ -0,0 +1,340 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::proxy::Allowlist;
+use crate::settings::{
+    parse_settings_layer, settings_file_path, MountSpec, NetworkPolicy, NetworkSettings, Settings,
+};
+
+pub const PROJECT_CONFIG_FILE: &str = ".codesandbox.toml";
+
+/// Keys a project file may not set, because a cloned repository must not
+/// decide how the agent is unleashed or what it installs and runs, which
+/// runtime is driven, how the API server is exposed or which host commands and
+/// variables feed secrets. They come from the global settings or the command
+/// line.
+pub const GLOBAL_ONLY_KEYS: &[&str] = &[
+    "skip_permission_flags",
+    "agents",
+    "runtime",
+    "server",
+    "secrets",
+];
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingSource {
+    Default,
+    Global(PathBuf),
+    Project(PathBuf),
+    Cli,
+}
+
+impl fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::Global(path) => write!(f, "global: {}", path.display()),
+            SettingSource::Project(path) => write!(f, "project: {}", path.display()),
+            SettingSource::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// Settings after layering CLI > project > global > defaults.
+#[derive(Debug)]
+pub struct EffectiveSettings {
+    pub settings: Settings,
+    pub values: Map<String, Value>,
+    pub sources: BTreeMap<String, SettingSource>,
+    pub global_file: Option<PathBuf>,
+    pub project_file: Option<PathBuf>,
+    /// Unknown keys and, in lenient mode, files that were skipped.
+    pub warnings: Vec<String>,
+}
+
+/// Walk up from `start` looking for a `.codesandbox.toml`.
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|candidate| candidate.is_file())
+}
+
+/// A parsed settings file and the warnings it produced.
+type Layer = (Map<String, Value>, Vec<String>);
+
+/// Read and validate one settings file. `Ok(None)` means the file does not
+/// exist; invalid files are errors that carry the file's line and column.
+fn read_layer(path: &Path) -> Result<Option<Layer>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let (layer, warnings) = parse_settings_layer(path, &data)?;
+    let warnings = warnings
+        .into_iter()
+        .map(|w| format!("{}: {}", path.display(), w))
+        .collect();
+    Ok(Some((layer, warnings)))
+}
+
+/// Remove the keys in `GLOBAL_ONLY_KEYS` from a project layer, returning a
+/// warning for each.
+fn drop_global_only_keys(layer: &mut Map<String, Value>, config_path: &Path) -> Vec<String> {
+    GLOBAL_ONLY_KEYS
+        .iter()
+        .filter(|key| layer.remove(**key).is_some())
+        .map(|key| {
+            format!(
+                "{}: `{}` can only be set in the global settings; ignoring it",
+                config_path.display(),
+                key
+            )
+        })
+        .collect()
+}
+
+// Relative paths are relative to the project, not to wherever the CLI runs
+fn resolve_project_paths(layer: &mut Map<String, Value>, config_path: &Path) {
+    let base = config_path.parent().unwrap_or(Path::new("/"));
+    let resolve = |value: &mut Value| {
+        if let Value::String(path) = value {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base.join(path.as_str()).display().to_string();
+            }
+        }
+    };
+    if let Some(Value::Array(mounts)) = layer.get_mut("mounts") {
+        // Only the host part of `path[:container_path][:ro|rw]` is a host path
+        for mount in mounts.iter_mut() {
+            if let Value::String(spec) = mount {
+                let end = spec.find(':').unwrap_or(spec.len());
+                if Path::new(&spec[..end]).is_relative() {
+                    *spec = format!("{}{}", base.join(&spec[..end]).display(), &spec[end..]);
+                }
+            }
+        }
+    }
+    if let Some(fragment) = layer.get_mut("dockerfile_fragment") {
+        resolve(fragment);
+    }
+}
+
+/// Drop project mounts outside the project tree, returning a warning for
+/// each, so a cloned repository cannot expose `~/.ssh` and the like to the
+/// agent. Other host directories can only come from the global settings or
+/// the command line.
+fn restrict_project_mounts(layer: &mut Map<String, Value>, config_path: &Path) -> Vec<String> {
+    let root = config_path
+        .parent()
+        .and_then(|dir| fs::canonicalize(dir).ok());
+    let mut warnings = Vec::new();
+    let Some(Value::Array(mounts)) = layer.get_mut("mounts") else {
+        return warnings;
+    };
+    mounts.retain(|mount| {
+        let Value::String(spec) = mount else {
+            return true;
+        };
+        // Invalid specs are reported when the mounts are used
+        let Ok(parsed) = MountSpec::parse(spec) else {
+            return true;
+        };
+        let inside = match (&root, fs::canonicalize(&parsed.host)) {
+            (Some(root), Ok(host)) => host.starts_with(root),
+            _ => false,
+        };
+        if !inside {
+            warnings.push(format!(
+                "{}: mount `{}` is outside the project; ignoring it",
+                config_path.display(),
+                spec
+            ));
+        }
+        inside
+    });
+    warnings
+}
+
+/// Keep a project's `network` table from loosening the policy set below it:
+/// a less strict `policy` is dropped, and under an allowlist only hosts the
+/// lower layers already allow are kept. Returns a warning for each.
+fn narrow_project_network(
+    layer: &mut Map<String, Value>,
+    values: &Map<String, Value>,
+    config_path: &Path,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let Some(Value::Object(network)) = layer.get_mut("network") else {
+        return warnings;
+    };
+    let base: NetworkSettings = values
+        .get("network")
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+        .unwrap_or_default();
+    // Ordered from the most to the least strict
+    let rank = |policy: NetworkPolicy| match policy {
+        NetworkPolicy::None => 0,
+        NetworkPolicy::Allowlist => 1,
+        NetworkPolicy::Full => 2,
+    };
+    let loosened = network
+        .get("policy")
+        .and_then(|value| serde_json::from_value::<NetworkPolicy>(value.clone()).ok())
+        .is_some_and(|policy| rank(policy) > rank(base.policy));
+    if loosened {
+        let policy = network.remove("policy").unwrap_or_default();
+        warnings.push(format!(
+            "{}: network policy `{}` is less strict than `{}` from the global settings; ignoring it",
+            config_path.display(),
+            policy.as_str().unwrap_or_default(),
+            serde_json::to_value(base.policy).unwrap_or_default().as_str().unwrap_or_default()
+        ));
+    }
+    if base.policy == NetworkPolicy::Allowlist {
+        let allowed = Allowlist::with_defaults(&base.allow);
+        if let Some(Value::Array(hosts)) = network.get_mut("allow") {
+            hosts.retain(|host| {
+                let host = host.as_str().unwrap_or_default();
+                let keep = allowed.allows(host);
+                if !keep {
+                    warnings.push(format!(
+                        "{}: network host `{}` is not allowed by the global settings; ignoring it",
+                        config_path.display(),
+                        host
+                    ));
+                }
+                keep
+            });
+        }
+    }
+    warnings
+}
+
+/// Overlay `layer` onto `values`. Tables such as `skip_permission_flags` are
+/// merged key by key; every other value replaces the lower layer.
+fn apply_layer(
+    values: &mut Map<String, Value>,
+    sources: &mut BTreeMap<String, SettingSource>,
+    layer: Map<String, Value>,
+    source: SettingSource,
+) {
+    for (key, value) in layer {
+        match (values.get_mut(&key), value) {
+            (Some(Value::Object(base)), Value::Object(overlay)) => {
+                base.extend(overlay);
+            }
+            (_, value) => {
+                values.insert(key.clone(), value);
+            }
+        }
+        sources.insert(key, source.clone());
+    }
+}
+
+/// Resolve the settings for a sandbox started from `current_dir`.
+/// `cli` holds values given on the command line, keyed like `settings.json`.
+/// Invalid global or project files are errors.
+pub fn load_effective_settings(
+    current_dir: &Path,
+    cli: Map<String, Value>,
+) -> Result<EffectiveSettings> {
+    resolve(current_dir, cli, true)
+}
+
+/// Like `load_effective_settings`, but skips invalid files with a warning
+/// instead of failing. Used by the server, which should keep running.
+pub fn load_effective_settings_lenient(current_dir: &Path) -> EffectiveSettings {
+    match resolve(current_dir, Map::new(), false) {
+        Ok(effective) => effective,
+        Err(e) => {
+            eprintln!("warning: {:#}; using default settings", e);
+            EffectiveSettings {
+                settings: Settings::default(),
+                values: Map::new(),
+                sources: BTreeMap::new(),
+                global_file: None,
+                project_file: None,
+                warnings: Vec::new(),
+            }
+        }
+    }
+}
+
+fn resolve(current_dir: &Path, cli: Map<String, Value>, strict: bool) -> Result<EffectiveSettings> {
+    let mut values = match serde_json::to_value(Settings::default())? {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    let mut sources: BTreeMap<String, SettingSource> = values
+        .keys()
+        .map(|k| (k.clone(), SettingSource::Default))
+        .collect();
+    let mut warnings = Vec::new();
+
+    let mut load = |path: &Path| -> Result<Option<Map<String, Value>>> {
+        match read_layer(path) {
+            Ok(Some((layer, layer_warnings))) => {
+                warnings.extend(layer_warnings);
+                Ok(Some(layer))
+            }
+            Ok(None) => Ok(None),
+            Err(e) if strict => Err(e),
+            Err(e) => {
+                warnings.push(format!("{:#}; ignoring this file", e));
+                Ok(None)
+            }
+        }
+    };
+
+    let global_path = settings_file_path();
+    let global_file = match load(&global_path)? {
+        Some(layer) => {
+            apply_layer(
+                &mut values,
+                &mut sources,
+                layer,
+                SettingSource::Global(global_path.clone()),
+            );
+            Some(global_path)
+        }
+        None => None,
+    };
+
+    let project_file = find_project_config(current_dir);
+    if let Some(path) = &project_file {
+        if let Some(mut layer) = load(path)? {
+            warnings.extend(drop_global_only_keys(&mut layer, path));
+            resolve_project_paths(&mut layer, path);
+            warnings.extend(restrict_project_mounts(&mut layer, path));
+            warnings.extend(narrow_project_network(&mut layer, &values, path));
+            apply_layer(
+                &mut values,
+                &mut sources,
+                layer,
+                SettingSource::Project(path.clone()),
+            );
+        }
+    }
+
+    apply_layer(&mut values, &mut sources, cli, SettingSource::Cli);
+
+    let settings: Settings = serde_json::from_value(Value::Object(values.clone()))
+        .context("Invalid value in merged settings")?;
+
+    Ok(EffectiveSettings {
+        settings,
+        values,
+        sources,
+        global_file,
+        project_file,
+        warnings,
+    })
+}
@@ -0,0 +1,394 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::fs::OpenOptions;
+use std::net::{IpAddr, SocketAddr, TcpStream as StdTcpStream};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::engine::runtime;
+use crate::settings::settings_file_path;
+
+pub const DEFAULT_PROXY_PORT: u16 = 6790;
+/// Internal network for allowlist sandboxes: no route out except the proxy.
+pub const EGRESS_NETWORK: &str = "codesandbox-egress";
+/// Container label holding the comma-separated hosts a sandbox may reach.
+pub const LABEL_ALLOWED_HOSTS: &str = "codesandbox.network.allow";
+
+/// Hosts every allowlist sandbox may reach: the agents' model APIs, package
+/// registries, and the downloads used to install toolchains.
+pub const DEFAULT_ALLOWED_HOSTS: &[&str] = &[
+    "api.anthropic.com",
+    "statsig.anthropic.com",
+    "generativelanguage.googleapis.com",
+    "oauth2.googleapis.com",
+    "api.openai.com",
+    "chatgpt.com",
+    "dashscope.aliyuncs.com",
+    "dashscope-intl.aliyuncs.com",
+    "*.cursor.sh",
+    "registry.npmjs.org",
+    "registry.yarnpkg.com",
+    "pypi.org",
+    "files.pythonhosted.org",
+    "crates.io",
+    "index.crates.io",
+    "static.crates.io",
+    "static.rust-lang.org",
+    "sh.rustup.rs",
+    "proxy.golang.org",
+    "sum.golang.org",
+    "go.dev",
+    "dl.google.com",
+    "rubygems.org",
+    "repo.packagist.org",
+    "repo.maven.apache.org",
+    "repo1.maven.org",
+    "services.gradle.org",
+    "plugins.gradle.org",
+    "api.nuget.org",
+    "repo.hex.pm",
+    "builds.hex.pm",
+    "deno.land",
+    "jsr.io",
+    "bun.sh",
+    "astral.sh",
+    "nodejs.org",
+    "deb.nodesource.com",
+    "archive.ubuntu.com",
+    "security.ubuntu.com",
+    "github.com",
+    "*.githubusercontent.com",
+];
+
+/// Hosts a sandbox may connect to. `example.com` matches only that host;
+/// `*.example.com` matches its subdomains.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    entries: Vec<String>,
+}
+
+impl Allowlist {
+    pub fn new<I, S>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            entries: entries
+                .into_iter()
+                .map(|e| normalize_host(e.as_ref()))
+                .filter(|e| !e.is_empty())
+                .collect(),
+        }
+    }
+
+    /// The built-in hosts plus `extra`, as recorded on the container label.
+    pub fn with_defaults(extra: &[String]) -> Self {
+        Self::new(
+            DEFAULT_ALLOWED_HOSTS
+                .iter()
+                .copied()
+                .chain(extra.iter().map(String::as_str)),
+        )
+    }
+
+    pub fn from_label(value: &str) -> Self {
+        Self::new(value.split(','))
+    }
+
+    pub fn to_label(&self) -> String {
+        self.entries.join(",")
+    }
+
+    pub fn allows(&self, host: &str) -> bool {
+        let host = normalize_host(host);
+        self.entries
+            .iter()
+            .any(|entry| match entry.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => *entry == host,
+            })
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Maps a client address to the allowlist of the sandbox it belongs to.
+pub type Resolver = dyn Fn(IpAddr) -> Option<Allowlist> + Send + Sync;
+
+/// Serve HTTP proxy requests on `listener`: `CONNECT host:port` tunnels and
+/// plain `http://` requests. Requests to hosts outside the client's allowlist
+/// get `403 Forbidden` and are logged to stderr.
+pub async fn serve_proxy(listener: TcpListener, resolver: Arc<Resolver>) -> Result<()> {
+    loop {
+        let (client, peer) = listener.accept().await?;
+        let resolver = resolver.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(client, peer, resolver).await {
+                eprintln!(
+                    "{} proxy error from {}: {:#}",
+                    Utc::now().to_rfc3339(),
+                    peer,
+                    e
+                );
+            }
+        });
+    }
+}
+
+const MAX_HEAD: usize = 16 * 1024;
+
+async fn handle_client(
+    mut client: TcpStream,
+    peer: SocketAddr,
+    resolver: Arc<Resolver>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > MAX_HEAD {
+            return respond(&mut client, "431 Request Header Fields Too Large").await;
+        }
+        let mut chunk = [0u8; 4096];
+        let n = client.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let body = buf[head_end..].to_vec();
+
+    let Some(request) = parse_request(&head) else {
+        return respond(&mut client, "400 Bad Request").await;
+    };
+
+    let lookup = tokio::task::spawn_blocking(move || resolver(peer.ip())).await?;
+    if !lookup.is_some_and(|allowlist| allowlist.allows(&request.host)) {
+        eprintln!(
+            "{} denied {} {}:{} from {}",
+            Utc::now().to_rfc3339(),
+            request.method,
+            request.host,
+            request.port,
+            peer.ip()
+        );
+        return respond(&mut client, "403 Forbidden").await;
+    }
+
+    let mut upstream = match TcpStream::connect((request.host.as_str(), request.port)).await {
+        Ok(stream) => stream,
+        Err(_) => return respond(&mut client, "502 Bad Gateway").await,
+    };
+    match &request.forward_head {
+        None => {
+            client
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?
+        }
+        Some(forward_head) => upstream.write_all(forward_head.as_bytes()).await?,
+    }
+    upstream.write_all(&body).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+async fn respond(client: &mut TcpStream, status: &str) -> Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    client.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+struct ProxyRequest {
+    method: String,
+    host: String,
+    port: u16,
+    /// Request head to send upstream; `None` for `CONNECT` tunnels.
+    forward_head: Option<String>,
+}
+
+fn parse_request(head: &str) -> Option<ProxyRequest> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let version = request_line.next()?;
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_authority(target, 443)?;
+        return Some(ProxyRequest {
+            method,
+            host,
+            port,
+            forward_head: None,
+        });
+    }
+
+    // Plain HTTP requests carry an absolute URI; forward them in origin form
+    let rest = target.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_authority(authority, 80)?;
+    let mut forward_head = format!("{method} {path} {version}\r\n");
+    for line in lines.filter(|l| !l.is_empty()) {
+        let name = line.split(':').next().unwrap_or("").to_ascii_lowercase();
+        if name.starts_with("proxy-") || name == "connection" {
+            continue;
+        }
+        forward_head.push_str(line);
+        forward_head.push_str("\r\n");
+    }
+    forward_head.push_str("Connection: close\r\n\r\n");
+    Some(ProxyRequest {
+        method,
+        host,
+        port,
+        forward_head: Some(forward_head),
+    })
+}
+
+fn split_authority(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let authority = authority.rsplit('@').next()?;
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        let port = match after.strip_prefix(':') {
+            Some(port) => port.parse().ok()?,
+            None => default_port,
+        };
+        return Some((host.to_string(), port));
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host.to_string(), port.parse().ok()?)),
+        None if !authority.is_empty() => Some((authority.to_string(), default_port)),
+        None => None,
+    }
+}
+
+/// Create the internal egress network if needed and return its gateway, the
+/// host address the proxy listens on.
+pub fn ensure_egress_network() -> Result<IpAddr> {
+    let gateway = || runtime().network_gateway(EGRESS_NETWORK);
+    if let Some(ip) = gateway() {
+        return Ok(ip);
+    }
+
+    println!("Creating internal network {}", EGRESS_NETWORK);
+    let output = runtime()
+        .command()
+        .args(["network", "create", "--internal", EGRESS_NETWORK])
+        .output()
+        .context("Failed to create egress network")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to create network {}: {}",
+            EGRESS_NETWORK,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    gateway().with_context(|| format!("Network {} has no gateway address", EGRESS_NETWORK))
+}
+
+/// Find the sandbox on the egress network with address `ip` and read its allowlist label.
+pub fn container_allowlist(ip: IpAddr) -> Option<Allowlist> {
+    let output = runtime()
+        .command()
+        .args([
+            "network",
+            "inspect",
+            "-f",
+            "{{range .Containers}}{{.Name}} {{.IPv4Address}}\n{{end}}",
+            EGRESS_NETWORK,
+        ])
+        .output()
+        .ok()?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let name = listing.lines().find_map(|line| {
+        let (name, address) = line.split_once(' ')?;
+        let address = address.split('/').next()?;
+        (address.parse::<IpAddr>().ok()? == ip).then(|| name.to_string())
+    })?;
+
+    let format = format!("{{{{index .Config.Labels \"{}\"}}}}", LABEL_ALLOWED_HOSTS);
+    let output = runtime()
+        .command()
+        .args(["inspect", "-f", &format, &name])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| Allowlist::from_label(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Run the proxy for allowlist sandboxes in the foreground, listening on the
+/// egress network's gateway so that it is not exposed beyond this host.
+pub async fn run(port: u16) -> Result<()> {
+    let gateway = ensure_egress_network()?;
+    let listener = TcpListener::bind((gateway, port))
+        .await
+        .with_context(|| format!("Failed to listen on {}:{}", gateway, port))?;
+    println!("Egress proxy listening on {}:{}", gateway, port);
+    serve_proxy(listener, Arc::new(container_allowlist)).await
+}
+
+/// Start the egress proxy in the background unless it is already listening, and
+/// return the proxy URL sandboxes should use. Denied requests are logged to
+/// `proxy.log` next to `settings.json`.
+pub fn ensure_proxy_running(port: u16) -> Result<String> {
+    let gateway = ensure_egress_network()?;
+    let addr = SocketAddr::new(gateway, port);
+    let listening = || StdTcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_ok();
+    if !listening() {
+        let log_path = settings_file_path().with_file_name("proxy.log");
+        if let Some(dir) = log_path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .with_context(|| format!("Failed to open {}", log_path.display()))?;
+        let exe = std::env::current_exe()?;
+        Command::new(exe)
+            .args([
+                "--runtime",
+                runtime().name(),
+                "proxy",
+                "--port",
+                &port.to_string(),
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .context("Failed to start the egress proxy")?;
+        let started = (0..20).any(|_| {
+            std::thread::sleep(Duration::from_millis(100));
+            listening()
+        });
+        if !started {
+            anyhow::bail!(
+                "Egress proxy did not start on {}; see {}",
+                addr,
+                log_path.display()
+            );
+        }
+        println!(
+            "Started egress proxy on {} (log: {})",
+            addr,
+            log_path.display()
+        );
+    }
+    Ok(format!("http://{}", addr))
+}
@@ -0,0 +1,292 @@
+use anyhow::{anyhow, Context, Result};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::engine::runtime;
+use crate::settings::{settings_file_path, SecretSettings, SecretTarget};
+
+/// tmpfs directory holding file secrets inside the sandbox.
+pub const SECRETS_DIR: &str = "/run/secrets";
+/// Passphrase for the encrypted store; prompted for when unset.
+pub const PASSPHRASE_VAR: &str = "CODESANDBOX_SECRETS_PASSPHRASE";
+
+const STORE_MAGIC: &[u8] = b"CSBSECRETS1";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 200_000;
+
+/// A secret value ready to be passed to a sandbox.
+pub struct ResolvedSecret {
+    pub name: String,
+    pub value: String,
+    pub target: SecretTarget,
+}
+
+// Never print the value, even in debug output
+impl std::fmt::Debug for ResolvedSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResolvedSecret")
+            .field("name", &self.name)
+            .field("value", &"<redacted>")
+            .field("target", &self.target)
+            .finish()
+    }
+}
+
+/// Location of the encrypted store, next to `settings.json`.
+pub fn store_path() -> PathBuf {
+    settings_file_path().with_file_name("secrets.enc")
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<LessSafeKey> {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &key)
+        .map_err(|_| anyhow!("Failed to create the secrets key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypt `entries` with a key derived from `passphrase`. The layout is
+/// magic, salt, nonce and the ChaCha20-Poly1305 sealed JSON object.
+pub fn encrypt_store(entries: &BTreeMap<String, String>, passphrase: &str) -> Result<Vec<u8>> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| anyhow!("Failed to generate random bytes"))?;
+
+    let mut data = serde_json::to_vec(entries)?;
+    derive_key(passphrase, &salt)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| anyhow!("Failed to encrypt secrets"))?;
+
+    let mut out = Vec::with_capacity(STORE_MAGIC.len() + SALT_LEN + NONCE_LEN + data.len());
+    out.extend_from_slice(STORE_MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&data);
+    Ok(out)
+}
+
+/// Decrypt a store written by `encrypt_store`.
+pub fn decrypt_store(data: &[u8], passphrase: &str) -> Result<BTreeMap<String, String>> {
+    let rest = data
+        .strip_prefix(STORE_MAGIC)
+        .ok_or_else(|| anyhow!("Not a codesandbox secrets file"))?;
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        anyhow::bail!("Secrets file is truncated");
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| anyhow!("Secrets file is truncated"))?;
+    let mut sealed = sealed.to_vec();
+    let plain = derive_key(passphrase, salt)?
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .map_err(|_| anyhow!("Failed to decrypt secrets: wrong passphrase or corrupted file"))?;
+    serde_json::from_slice(plain).context("Secrets file contents are invalid")
+}
+
+/// Read a line from the terminal without echoing it.
+pub fn prompt_hidden(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush().ok();
+    let tty = atty::is(atty::Stream::Stdin);
+    if tty {
+        Command::new("stty")
+            .arg("-echo")
+            .stdin(Stdio::inherit())
+            .status()
+            .ok();
+    }
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line);
+    if tty {
+        Command::new("stty")
+            .arg("echo")
+            .stdin(Stdio::inherit())
+            .status()
+            .ok();
+        eprintln!();
+    }
+    read.context("Failed to read from stdin")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn passphrase() -> Result<String> {
+    match env::var(PASSPHRASE_VAR) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => prompt_hidden("Secrets passphrase: "),
+    }
+}
+
+/// Load the encrypted store at `path`; a missing file is an empty store.
+pub fn load_store(path: &Path, passphrase: &str) -> Result<BTreeMap<String, String>> {
+    match fs::read(path) {
+        Ok(data) => decrypt_store(&data, passphrase).with_context(|| format!("{}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Encrypt `entries` into `path`, readable only by the current user.
+pub fn save_store(path: &Path, entries: &BTreeMap<String, String>, passphrase: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let data = encrypt_store(entries, passphrase)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(&data))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Open the store once, asking for the passphrase when needed.
+pub fn open_store() -> Result<(BTreeMap<String, String>, String)> {
+    let path = store_path();
+    let passphrase = passphrase()?;
+    let entries = load_store(&path, &passphrase)?;
+    Ok((entries, passphrase))
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Look up the value of every configured secret. Values come from host
+/// environment variables, commands or the encrypted store; the store is only
+/// opened when a secret refers to it.
+pub fn resolve_secrets(specs: &BTreeMap<String, SecretSettings>) -> Result<Vec<ResolvedSecret>> {
+    let mut store: Option<BTreeMap<String, String>> = None;
+    let mut resolved = Vec::new();
+    for (name, spec) in specs {
+        if !valid_name(name) {
+            anyhow::bail!(
+                "secret `{}`: names may only contain letters, digits and underscores",
+                name
+            );
+        }
+        let value = match (&spec.env, &spec.command, &spec.store) {
+            (Some(var), None, None) => env::var(var)
+                .map_err(|_| anyhow!("secret `{}`: host variable {} is not set", name, var))?,
+            (None, Some(command), None) => {
+                let output = Command::new("sh")
+                    .args(["-c", command])
+                    .stdin(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .with_context(|| format!("secret `{}`: failed to run command", name))?;
+                if !output.status.success() {
+                    anyhow::bail!("secret `{}`: command exited with {}", name, output.status);
+                }
+                String::from_utf8(output.stdout)
+                    .map_err(|_| anyhow!("secret `{}`: command output is not UTF-8", name))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string()
+            }
+            (None, None, Some(key)) => {
+                if store.is_none() {
+                    store = Some(open_store()?.0);
+                }
+                store
+                    .as_ref()
+                    .and_then(|entries| entries.get(key))
+                    .cloned()
+                    .ok_or_else(|| {
+                        anyhow!("secret `{}`: `{}` is not in the secrets store", name, key)
+                    })?
+            }
+            _ => anyhow::bail!(
+                "secret `{}`: set exactly one of `env`, `command` or `store`",
+                name
+            ),
+        };
+        resolved.push(ResolvedSecret {
+            name: name.clone(),
+            value,
+            target: spec.inject,
+        });
+    }
+    Ok(resolved)
+}
+
+/// Add the `<runtime> run` arguments for `secrets`. Environment secrets are
+/// passed by name and their values through the runtime's own environment, so
+/// they never appear on a command line.
+pub fn apply_secret_args(docker_run: &mut Command, secrets: &[ResolvedSecret]) {
+    for secret in secrets {
+        match secret.target {
+            SecretTarget::Env => {
+                docker_run.args(["-e", &secret.name]);
+                docker_run.env(&secret.name, &secret.value);
+                println!(
+                    "Injecting secret {} as an environment variable",
+                    secret.name
+                );
+            }
+            SecretTarget::File => {
+                println!(
+                    "Injecting secret {} as {}/{}",
+                    secret.name, SECRETS_DIR, secret.name
+                );
+            }
+        }
+    }
+    if secrets.iter().any(|s| s.target == SecretTarget::File) {
+        docker_run.args([
+            "--tmpfs",
+            &format!("{}:rw,noexec,nosuid,size=1m,mode=0755", SECRETS_DIR),
+        ]);
+    }
+}
+
+/// Write the file secrets into the sandbox's tmpfs, readable only by the
+/// sandbox user. The values are streamed over stdin.
+pub fn write_secret_files(container_name: &str, secrets: &[ResolvedSecret]) -> Result<()> {
+    for secret in secrets.iter().filter(|s| s.target == SecretTarget::File) {
+        // Names are validated, so the path needs no quoting
+        let path = format!("{}/{}", SECRETS_DIR, secret.name);
+        let command =
+            format!("sudo sh -c 'umask 077; cat > {path}' && sudo chown $(id -u):$(id -g) {path}");
+        let mut child = runtime()
+            .command()
+            .args(["exec", "-i", container_name, "bash", "-c", &command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("Failed to write secret into container")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(secret.value.as_bytes())?;
+        }
+        if !child.wait()?.success() {
+            anyhow::bail!("Failed to write secret {} into container", secret.name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::settings::settings_file_path;
+
+use super::ErrorResponse;
+
+/// Cookie that keeps the browser signed in after opening a link with `?token=`.
+pub const TOKEN_COOKIE: &str = "codesandbox_token";
+
+const SECRET_LEN: usize = 32;
+const NONCE_LEN: usize = 16;
+
+/// Location of the per-install server secret, next to `settings.json`.
+pub fn secret_path() -> PathBuf {
+    settings_file_path().with_file_name("server.secret")
+}
+
+/// Read the server secret at `path`, generating it on first use. The file is
+/// created exclusively so the CLI and a server starting at the same time
+/// agree on one secret.
+pub fn load_or_create_secret(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(secret) if !secret.trim().is_empty() => return Ok(secret.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    let secret = URL_SAFE_NO_PAD.encode(random_bytes::<SECRET_LEN>()?);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    match options.open(path) {
+        Ok(mut file) => {
+            file.write_all(secret.as_bytes())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(secret)
+        }
+        // Another process won the race; use its secret
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let secret = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Ok(secret.trim().to_string())
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to write {}", path.display())),
+    }
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow!("Failed to generate random bytes"))?;
+    Ok(bytes)
+}
+
+/// What a verified token may use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// The install secret: every route.
+    All,
+    /// A session token: reading the terminal, changes and metadata of one
+    /// container, plus the web UI itself. It cannot stop or remove anything.
+    Container(String),
+}
+
+impl Access {
+    /// Whether a `method` request for `path` is allowed.
+    pub fn permits(&self, method: &Method, path: &str) -> bool {
+        let name = match self {
+            Access::All => return true,
+            Access::Container(name) => name,
+        };
+        if method != Method::GET && method != Method::HEAD {
+            return false;
+        }
+        for prefix in ["/terminal/", "/api/changed/", "/api/containers/"] {
+            if let Some(container) = path.strip_prefix(prefix) {
+                return container == name;
+            }
+        }
+        !(path.starts_with("/api/") || path == "/shutdown")
+    }
+}
+
+/// Issues and checks the tokens accepted by the API server. The install
+/// secret grants everything; session tokens are minted by the CLI for one
+/// container and signed with the secret, so the server keeps no token state.
+#[derive(Clone)]
+pub struct ServerAuth {
+    key: hmac::Key,
+    secret_tag: hmac::Tag,
+}
+
+impl ServerAuth {
+    pub fn new(secret: &str) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let secret_tag = hmac::sign(&key, secret.as_bytes());
+        Self { key, secret_tag }
+    }
+
+    /// Auth for this install, creating the secret when needed.
+    pub fn load() -> Result<Self> {
+        Ok(Self::new(&load_or_create_secret(&secret_path())?))
+    }
+
+    /// A new random session token for `container`.
+    pub fn mint(&self, container: &str) -> Result<String> {
+        let nonce = URL_SAFE_NO_PAD.encode(random_bytes::<NONCE_LEN>()?);
+        let tag = hmac::sign(&self.key, session_message(container, &nonce).as_bytes());
+        Ok(format!(
+            "{}.{}.{}",
+            container,
+            nonce,
+            URL_SAFE_NO_PAD.encode(tag.as_ref())
+        ))
+    }
+
+    /// What `token` grants, or `None` when it is not valid. Comparisons run
+    /// in constant time.
+    pub fn verify(&self, token: &str) -> Option<Access> {
+        // Comparing MACs of both values keeps timing independent of the secret
+        if hmac::verify(&self.key, token.as_bytes(), self.secret_tag.as_ref()).is_ok() {
+            return Some(Access::All);
+        }
+        let mut parts = token.rsplitn(3, '.');
+        let (tag, nonce, container) = (parts.next()?, parts.next()?, parts.next()?);
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        hmac::verify(
+            &self.key,
+            session_message(container, nonce).as_bytes(),
+            &tag,
+        )
+        .ok()?;
+        Some(Access::Container(container.to_string()))
+    }
+}
+
+fn session_message(container: &str, nonce: &str) -> String {
+    format!("session:{}:{}", container, nonce)
+}
+
+/// Tokens offered by a request: a bearer `Authorization` header, the `token`
+/// query parameter and the session cookie, in that order.
+fn request_tokens<B>(req: &Request<B>) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    if let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        tokens.push((token.trim().to_string(), false));
+    }
+    if let Some(query) = req.uri().query() {
+        for pair in query.split('&') {
+            if let Some(token) = pair.strip_prefix("token=") {
+                tokens.push((token.to_string(), true));
+            }
+        }
+    }
+    for cookies in req.headers().get_all(header::COOKIE) {
+        let Ok(cookies) = cookies.to_str() else {
+            continue;
+        };
+        for cookie in cookies.split(';') {
+            if let Some((name, value)) = cookie.trim().split_once('=') {
+                if name == TOKEN_COOKIE {
+                    tokens.push((value.to_string(), false));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+/// Middleware enforcing `ServerAuth` on every route. Requests without a
+/// valid token get 401, tokens for another container or for admin routes get
+/// 403. A token given in the query is stored in a cookie so the web UI's
+/// own requests stay signed in.
+pub async fn require_auth<B>(
+    State(auth): State<ServerAuth>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let verified = request_tokens(&req)
+        .into_iter()
+        .find_map(|(token, from_query)| {
+            auth.verify(&token)
+                .map(|access| (token, from_query, access))
+        });
+    let Some((token, from_query, access)) = verified else {
+        return reject(StatusCode::UNAUTHORIZED, "missing or invalid token");
+    };
+    if !access.permits(req.method(), req.uri().path()) {
+        return reject(StatusCode::FORBIDDEN, "token does not grant access");
+    }
+
+    let mut res = next.run(req).await;
+    if from_query {
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict",
+            TOKEN_COOKIE, token
+        );
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            res.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    res
+}
+
+fn reject(status: StatusCode, error: &str) -> Response {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.into(),
+        }),
+    )
+        .into_response()
+}
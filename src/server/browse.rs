@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::language::detect_project_languages;
+use crate::settings::ServerSettings;
+
+/// A directory entry as returned by `/api/list`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    // RFC 3339 modification time, when the platform reports one
+    pub modified: Option<String>,
+    pub is_git_repo: bool,
+    // Languages detected from marker files; empty for files
+    pub languages: Vec<String>,
+}
+
+/// The directories the web UI may browse and start sandboxes in. Paths are
+/// compared after resolving symlinks, so a link cannot lead outside them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceRoots {
+    roots: Vec<PathBuf>,
+}
+
+impl WorkspaceRoots {
+    /// Canonicalized roots; roots that do not exist are skipped.
+    pub fn new<I, P>(roots: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut canonical = Vec::new();
+        for root in roots {
+            match fs::canonicalize(root.as_ref()) {
+                Ok(root) if !canonical.contains(&root) => canonical.push(root),
+                Ok(_) => {}
+                Err(e) => eprintln!(
+                    "warning: skipping workspace root {}: {}",
+                    root.as_ref().display(),
+                    e
+                ),
+            }
+        }
+        Self { roots: canonical }
+    }
+
+    /// `server.workspace_roots`, with `~` expanded, or the home directory
+    /// when none are configured.
+    pub fn from_settings(settings: &ServerSettings) -> Self {
+        let home = home::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+        if settings.workspace_roots.is_empty() {
+            return Self::new([home]);
+        }
+        Self::new(
+            settings
+                .workspace_roots
+                .iter()
+                .map(|root| match root.strip_prefix("~/") {
+                    Some(rest) => home.join(rest),
+                    None if root == "~" => home.clone(),
+                    None => PathBuf::from(root),
+                }),
+        )
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Canonical form of `path` if it is one of the roots or inside one.
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, (StatusCode, String)> {
+        let canonical = fs::canonicalize(path)
+            .map_err(|e| (StatusCode::NOT_FOUND, format!("{}: {}", path.display(), e)))?;
+        if self.contains(&canonical) {
+            Ok(canonical)
+        } else {
+            Err((
+                StatusCode::FORBIDDEN,
+                format!("{} is outside the workspace roots", path.display()),
+            ))
+        }
+    }
+
+    fn contains(&self, canonical: &Path) -> bool {
+        self.roots.iter().any(|root| canonical.starts_with(root))
+    }
+
+    /// Entries of `dir`, which must already be resolved. Dotfiles are left out
+    /// unless `show_hidden`, and so are links pointing outside the roots.
+    pub fn list(&self, dir: &Path, show_hidden: bool) -> Result<Vec<DirEntryInfo>> {
+        let mut result = Vec::new();
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !show_hidden && name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            match fs::canonicalize(&path) {
+                Ok(target) if self.contains(&target) => {}
+                // Dangling or escaping links
+                _ => continue,
+            }
+            // Follows links, so a linked directory is listed as a directory
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let is_dir = metadata.is_dir();
+            result.push(DirEntryInfo {
+                name,
+                path: path.display().to_string(),
+                is_dir,
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .map(|time| DateTime::<Utc>::from(time).to_rfc3339()),
+                is_git_repo: is_dir && path.join(".git").exists(),
+                languages: if is_dir {
+                    detect_project_languages(&path)
+                        .iter()
+                        .map(|l| l.name().to_string())
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+        result.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        Ok(result)
+    }
+
+    /// The roots themselves, listed when no path is requested.
+    pub fn list_roots(&self) -> Vec<DirEntryInfo> {
+        self.roots
+            .iter()
+            .map(|root| DirEntryInfo {
+                name: root.display().to_string(),
+                path: root.display().to_string(),
+                is_dir: true,
+                size: 0,
+                modified: None,
+                is_git_repo: root.join(".git").exists(),
+                languages: detect_project_languages(root)
+                    .iter()
+                    .map(|l| l.name().to_string())
+                    .collect(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+use crate::settings::{settings_file_path, ServerSettings};
+
+/// Address `codesandbox serve` listens on unless `server.bind` or `--bind`
+/// says otherwise. Loopback only, so other machines cannot reach it.
+pub const DEFAULT_BIND: &str = "127.0.0.1:6789";
+
+/// Written by a running server so the CLI can find it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerState {
+    // Loopback URL the CLI uses to reach the server, e.g. "https://127.0.0.1:6789"
+    pub url: String,
+    pub pid: u32,
+    // Certificate the server presents, trusted by the CLI's requests
+    pub tls_cert: Option<PathBuf>,
+}
+
+impl ServerState {
+    /// Location of the state file, next to `settings.json`.
+    pub fn path() -> PathBuf {
+        settings_file_path().with_file_name("server.json")
+    }
+
+    /// The running server, or `None` when no state was written or the server
+    /// that wrote it has exited.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let state: Self = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(state).filter(|s| process_alive(s.pid)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Remove the state file if it still describes this process.
+    pub fn clear(path: &Path) {
+        let ours = fs::read_to_string(path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<Self>(&data).ok())
+            .is_some_and(|s| s.pid == std::process::id());
+        if ours {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Client for requests to this server, trusting its certificate.
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(cert) = &self.tls_cert {
+            let pem =
+                fs::read(cert).with_context(|| format!("Failed to read {}", cert.display()))?;
+            builder = builder.add_root_certificate(
+                reqwest::Certificate::from_pem(&pem)
+                    .with_context(|| format!("Invalid certificate {}", cert.display()))?,
+            );
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Parse a bind address: `host:port`, `:port` for loopback, or a bare port.
+pub fn parse_bind(bind: &str) -> Result<SocketAddr> {
+    let bind = bind.trim();
+    let with_host = if let Ok(port) = bind.parse::<u16>() {
+        format!("127.0.0.1:{}", port)
+    } else if let Some(port) = bind.strip_prefix(':') {
+        format!("127.0.0.1:{}", port)
+    } else {
+        bind.to_string()
+    };
+    with_host
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| anyhow!("invalid bind address `{}`: expected host:port", bind))
+}
+
+/// The address `settings` asks the server to listen on.
+pub fn bind_addr(settings: &ServerSettings) -> Result<SocketAddr> {
+    parse_bind(settings.bind.as_deref().unwrap_or(DEFAULT_BIND))
+}
+
+fn scheme(tls: bool) -> &'static str {
+    if tls {
+        "https"
+    } else {
+        "http"
+    }
+}
+
+/// URL the CLI uses to reach a server listening on `addr`; a wildcard
+/// address is reached over loopback.
+pub fn local_url(addr: SocketAddr, tls: bool) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    format!("{}://{}", scheme(tls), SocketAddr::new(ip, addr.port()))
+}
+
+/// Base of the web UI URLs shown to the user, e.g. `http://localhost:6789`.
+/// The scheme and port come from the running server, or from `settings`
+/// when none is running yet.
+pub fn web_base_url(web_host: &str, settings: &ServerSettings) -> Result<String> {
+    if let Some(state) = ServerState::load(&ServerState::path())? {
+        let url = reqwest::Url::parse(&state.url)
+            .with_context(|| format!("Invalid server URL {}", state.url))?;
+        if let Some(port) = url.port_or_known_default() {
+            return Ok(format!("{}://{}:{}", url.scheme(), web_host, port));
+        }
+    }
+    let addr = bind_addr(settings)?;
+    Ok(format!(
+        "{}://{}:{}",
+        scheme(settings.tls.unwrap_or(false)),
+        web_host,
+        addr.port()
+    ))
+}
+
+/// Certificate and key for HTTPS, or `None` when TLS is off. Without a
+/// configured pair a self-signed certificate for `hosts` and loopback is
+/// generated next to `settings.json` and reused while it covers them.
+pub fn tls_files(
+    settings: &ServerSettings,
+    hosts: &[String],
+) -> Result<Option<(PathBuf, PathBuf)>> {
+    if !settings.tls.unwrap_or(false) {
+        if settings.tls_cert.is_some() || settings.tls_key.is_some() {
+            bail!("server.tls_cert and server.tls_key need server.tls (or --tls)");
+        }
+        return Ok(None);
+    }
+    match (&settings.tls_cert, &settings.tls_key) {
+        (Some(cert), Some(key)) => Ok(Some((PathBuf::from(cert), PathBuf::from(key)))),
+        (None, None) => {
+            self_signed_files(&settings_file_path().with_file_name("tls"), hosts).map(Some)
+        }
+        _ => bail!("server.tls_cert and server.tls_key must be set together"),
+    }
+}
+
+/// Self-signed certificate and key in `dir`. They are generated again when
+/// missing or when `hosts` has names the certificate was not issued for,
+/// which are recorded in `dir/names`.
+pub fn self_signed_files(dir: &Path, hosts: &[String]) -> Result<(PathBuf, PathBuf)> {
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    let names_file = dir.join("names");
+    let names = certificate_names(hosts);
+    let issued = fs::read_to_string(&names_file).unwrap_or_default();
+    let covered = names
+        .iter()
+        .all(|name| issued.lines().any(|issued| issued == name));
+    if !covered || !cert.exists() || !key.exists() {
+        generate_self_signed(&cert, &key, hosts)?;
+        fs::write(&names_file, names.join("\n") + "\n")
+            .with_context(|| format!("Failed to write {}", names_file.display()))?;
+    }
+    Ok((cert, key))
+}
+
+/// `hosts` along with `localhost` and the loopback addresses.
+fn certificate_names(hosts: &[String]) -> Vec<String> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    for host in hosts {
+        if !names.contains(host) {
+            names.push(host.clone());
+        }
+    }
+    names
+}
+
+/// Write a self-signed certificate valid for `hosts`, `localhost` and the
+/// loopback addresses.
+pub fn generate_self_signed(cert: &Path, key: &Path, hosts: &[String]) -> Result<()> {
+    let generated = rcgen::generate_simple_self_signed(certificate_names(hosts))
+        .context("Failed to generate a self-signed certificate")?;
+    if let Some(dir) = cert.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+    fs::write(cert, generated.serialize_pem()?)
+        .with_context(|| format!("Failed to write {}", cert.display()))?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    io::Write::write_all(
+        &mut options
+            .open(key)
+            .with_context(|| format!("Failed to write {}", key.display()))?,
+        generated.serialize_private_key_pem().as_bytes(),
+    )
+    .with_context(|| format!("Failed to write {}", key.display()))?;
+    println!("Generated a self-signed certificate at {}", cert.display());
+    Ok(())
+}
@@ -6,31 +6,44 @@ use axum::{
         Path, Query,
     },
     http::{Request, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use base64::Engine as _;
 use futures::{SinkExt, StreamExt};
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::Stdio;
 use std::sync::Arc;
-use tokio::fs;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::process::Command;
 use tokio::sync::{oneshot, Mutex};
 use tower::{service_fn, ServiceExt};
 use tower_http::services::{ServeDir, ServeFile};
 
-use crate::cli::Agent;
-use crate::container::{check_docker_availability, create_container, generate_container_name};
+use crate::agents::AgentRegistry;
+use crate::container::{
+    check_runtime_availability, cleanup_containers, create_container, generate_container_name,
+    inspect_container, read_container_logs, remove_container, resume_container, stop_container,
+    ContainerInfo,
+};
+use crate::engine::runtime;
+use crate::project_config::load_effective_settings_lenient;
+use crate::settings::{ResourceLimits, ServerSettings};
+use crate::state::{clear_last_container, load_last_container};
+
+pub mod auth;
+pub mod browse;
+pub mod listener;
+pub mod registry;
 
-static CONTAINER_PATHS: Lazy<Mutex<HashMap<String, String>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+use auth::ServerAuth;
+use browse::{DirEntryInfo, WorkspaceRoots};
+use listener::ServerState;
+use registry::ContainerRegistry;
 
 #[derive(Serialize)]
 struct FileDiff {
@@ -45,20 +58,16 @@ struct ChangeResponse {
 }
 
 #[derive(Serialize)]
-struct ErrorResponse {
+pub(crate) struct ErrorResponse {
     error: String,
 }
 
-#[derive(Serialize)]
-struct DirEntryInfo {
-    name: String,
-    path: String,
-    is_dir: bool,
-}
-
 #[derive(Deserialize)]
 struct ListQuery {
     path: Option<String>,
+    // Include dotfiles
+    #[serde(default)]
+    hidden: bool,
 }
 
 #[derive(Deserialize)]
@@ -67,53 +76,62 @@ struct StartRequest {
     agent: String,
 }
 
+#[derive(Serialize)]
+struct RemovedResponse {
+    removed: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CleanupRequest {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+    // Only the last lines, e.g. "100"
+    tail: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LogsResponse {
+    container: String,
+    logs: String,
+}
+
 #[derive(Serialize)]
 struct StartResponse {
     container: String,
+    limits: ResourceLimits,
+    mounts: Vec<String>,
 }
 
 async fn list_dir(
-    Query(ListQuery { path }): Query<ListQuery>,
+    Extension(roots): Extension<WorkspaceRoots>,
+    Query(ListQuery { path, hidden }): Query<ListQuery>,
 ) -> Result<Json<Vec<DirEntryInfo>>, (StatusCode, Json<ErrorResponse>)> {
-    let base = path.unwrap_or_else(|| ".".to_string());
-    let mut entries = fs::read_dir(&base).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )
-    })?;
-    let mut result = Vec::new();
-    while let Some(entry) = entries.next_entry().await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )
-    })? {
-        let file_type = entry.file_type().await.map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: e.to_string(),
-                }),
-            )
-        })?;
-        result.push(DirEntryInfo {
-            name: entry.file_name().to_string_lossy().into(),
-            path: entry.path().display().to_string(),
-            is_dir: file_type.is_dir(),
-        });
+    let error = |(status, error): (StatusCode, String)| (status, Json(ErrorResponse { error }));
+    let Some(path) = path.filter(|p| !p.is_empty()) else {
+        return Ok(Json(roots.list_roots()));
+    };
+    let dir = roots.resolve(std::path::Path::new(&path)).map_err(error)?;
+    if !dir.is_dir() {
+        return Err(error((StatusCode::BAD_REQUEST, "not a directory".into())));
     }
-    Ok(Json(result))
+    let entries = tokio::task::spawn_blocking(move || roots.list(&dir, hidden))
+        .await
+        .map_err(|e| error((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())))?
+        .map_err(|e| error((StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))))?;
+    Ok(Json(entries))
 }
 
 async fn start_container_api(
+    Extension(roots): Extension<WorkspaceRoots>,
+    Extension(registry): Extension<ContainerRegistry>,
     Json(req): Json<StartRequest>,
 ) -> Result<Json<StartResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let path = PathBuf::from(&req.path);
+    let path = roots
+        .resolve(&PathBuf::from(&req.path))
+        .map_err(|(status, error)| (status, Json(ErrorResponse { error })))?;
     if !path.is_dir() {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -123,13 +141,27 @@ async fn start_container_api(
         ));
     }
 
-    let agent = match req.agent.to_lowercase().as_str() {
-        "claude" => Agent::Claude,
-        "gemini" => Agent::Gemini,
-        "codex" => Agent::Codex,
-        "qwen" => Agent::Qwen,
-        "cursor" => Agent::Cursor,
-        _ => {
+    if let Err(e) = check_runtime_availability() {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        ));
+    }
+
+    let effective = load_effective_settings_lenient(&path);
+    for warning in effective.warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+    let settings = effective.settings;
+
+    let agent = match AgentRegistry::from_settings(&settings)
+        .ok()
+        .and_then(|registry| registry.get(&req.agent).cloned())
+    {
+        Some(agent) => agent,
+        None => {
             return Err((
                 StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
@@ -139,56 +171,233 @@ async fn start_container_api(
         }
     };
 
-    if let Err(e) = check_docker_availability() {
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        ));
+    let container_name = generate_container_name(&path, &agent);
+    let limits = settings.limits.clone();
+    let name = container_name.clone();
+    let info = blocking(move || {
+        // Creating builds the image and drives the container runtime synchronously
+        tokio::runtime::Handle::current().block_on(create_container(
+            &name,
+            &path,
+            &[],
+            &agent,
+            &settings,
+            false,
+            false,
+        ))?;
+        // Read back from the labels so skipped mounts are not reported
+        Ok(inspect_container(&name).ok().flatten())
+    })
+    .await?;
+    let mounts = info
+        .as_ref()
+        .map(|info| info.mounts.clone())
+        .unwrap_or_default();
+    if let Some(info) = info {
+        registry.insert(info).await;
     }
 
-    let container_name = generate_container_name(&path, &agent);
-    if let Err(e) = create_container(&container_name, &path, None, &agent, None, false, false).await
-    {
-        return Err((
+    Ok(Json(StartResponse {
+        container: container_name,
+        limits,
+        mounts,
+    }))
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn api_error(status: StatusCode, error: impl std::fmt::Display) -> ApiError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.to_string(),
+        }),
+    )
+}
+
+/// Run a blocking container operation off the async runtime; its failures
+/// are reported as `500`.
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+/// The sandbox container `name`, or `404`.
+async fn known_container(
+    registry: &ContainerRegistry,
+    name: &str,
+) -> Result<ContainerInfo, ApiError> {
+    match registry.get(name).await {
+        Ok(Some(info)) => Ok(info),
+        Ok(None) => Err(api_error(StatusCode::NOT_FOUND, "unknown container")),
+        Err(e) => Err(api_error(
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        ));
+            format!("{:#}", e),
+        )),
     }
+}
 
-    {
-        let mut map = CONTAINER_PATHS.lock().await;
-        map.insert(container_name.clone(), path.display().to_string());
+async fn list_containers_api(
+    Extension(registry): Extension<ContainerRegistry>,
+) -> Result<Json<Vec<ContainerInfo>>, ApiError> {
+    registry
+        .list()
+        .await
+        .map(Json)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+async fn get_container_api(
+    Extension(registry): Extension<ContainerRegistry>,
+    Path(container): Path<String>,
+) -> Result<Json<ContainerInfo>, ApiError> {
+    known_container(&registry, &container).await.map(Json)
+}
+
+/// Start a stopped container the way `codesandbox start` does, with the
+/// settings of the project it was created for, without attaching.
+async fn start_existing(info: &ContainerInfo) -> Result<(), ApiError> {
+    let info = info.clone();
+    blocking(move || {
+        let settings = match &info.project_path {
+            Some(path) => {
+                let effective = load_effective_settings_lenient(std::path::Path::new(path));
+                for warning in effective.warnings.iter() {
+                    eprintln!("warning: {}", warning);
+                }
+                effective.settings
+            }
+            None => Default::default(),
+        };
+        let registry = AgentRegistry::from_settings(&settings)?;
+        // The agent the container was created with, as `codesandbox start` does
+        let agent = match info.agent.as_deref().and_then(|name| registry.get(name)) {
+            Some(agent) => agent.clone(),
+            None => registry.resolve(settings.agent.as_deref())?,
+        };
+        // Resuming drives the container runtime synchronously
+        tokio::runtime::Handle::current().block_on(resume_container(
+            &info.name, &agent, false, &settings, false, false,
+        ))
+    })
+    .await
+}
+
+async fn start_existing_api(
+    Extension(registry): Extension<ContainerRegistry>,
+    Path(container): Path<String>,
+) -> Result<Json<ContainerInfo>, ApiError> {
+    let info = known_container(&registry, &container).await?;
+    start_existing(&info).await?;
+    known_container(&registry, &container).await.map(Json)
+}
+
+async fn stop_container_api(
+    Extension(registry): Extension<ContainerRegistry>,
+    Path(container): Path<String>,
+) -> Result<Json<ContainerInfo>, ApiError> {
+    let info = known_container(&registry, &container).await?;
+    blocking(move || stop_container(&info.name)).await?;
+    known_container(&registry, &container).await.map(Json)
+}
+
+async fn restart_container_api(
+    Extension(registry): Extension<ContainerRegistry>,
+    Path(container): Path<String>,
+) -> Result<Json<ContainerInfo>, ApiError> {
+    let info = known_container(&registry, &container).await?;
+    let name = info.name.clone();
+    blocking(move || stop_container(&name)).await?;
+    start_existing(&info).await?;
+    known_container(&registry, &container).await.map(Json)
+}
+
+async fn remove_container_api(
+    Extension(registry): Extension<ContainerRegistry>,
+    Path(container): Path<String>,
+) -> Result<Json<RemovedResponse>, ApiError> {
+    let info = known_container(&registry, &container).await?;
+    let name = info.name.clone();
+    blocking(move || {
+        remove_container(&name)?;
+        if load_last_container()?.as_deref() == Some(name.as_str()) {
+            clear_last_container()?;
+        }
+        Ok(())
+    })
+    .await?;
+    registry.remove(&info.name).await;
+    Ok(Json(RemovedResponse {
+        removed: vec![info.name],
+    }))
+}
+
+async fn cleanup_api(
+    Extension(roots): Extension<WorkspaceRoots>,
+    Extension(registry): Extension<ContainerRegistry>,
+    Json(req): Json<CleanupRequest>,
+) -> Result<Json<RemovedResponse>, ApiError> {
+    let path = roots
+        .resolve(std::path::Path::new(&req.path))
+        .map_err(|(status, error)| api_error(status, error))?;
+    let removed = blocking(move || {
+        let removed = cleanup_containers(&path)?;
+        if let Some(last) = load_last_container()? {
+            if removed.contains(&last) {
+                clear_last_container()?;
+            }
+        }
+        Ok(removed)
+    })
+    .await?;
+    for name in removed.iter() {
+        registry.remove(name).await;
     }
+    Ok(Json(RemovedResponse { removed }))
+}
 
-    Ok(Json(StartResponse {
-        container: container_name,
+async fn container_logs_api(
+    Extension(registry): Extension<ContainerRegistry>,
+    Path(container): Path<String>,
+    Query(LogsQuery { tail }): Query<LogsQuery>,
+) -> Result<Json<LogsResponse>, ApiError> {
+    if let Some(tail) = &tail {
+        if tail != "all" && tail.parse::<u64>().is_err() {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "tail must be a number of lines or \"all\"",
+            ));
+        }
+    }
+    let info = known_container(&registry, &container).await?;
+    let name = info.name.clone();
+    let logs = blocking(move || read_container_logs(&name, tail.as_deref())).await?;
+    Ok(Json(LogsResponse {
+        container: info.name,
+        logs,
     }))
 }
 
 async fn get_changed(
+    Extension(registry): Extension<ContainerRegistry>,
     Path(container): Path<String>,
 ) -> Result<Json<ChangeResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let repo_path = {
-        let map = CONTAINER_PATHS.lock().await;
-        match map.get(&container) {
-            Some(p) => p.clone(),
-            None => {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(ErrorResponse {
-                        error: "unknown container".into(),
-                    }),
-                ))
-            }
-        }
+    let Some(repo_path) = known_container(&registry, &container).await?.project_path else {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "container has no project directory",
+        ));
     };
 
     // Get git status to find changed files
-    let status_output = Command::new("docker")
+    let status_output = runtime()
+        .async_command()
         .args([
             "exec",
             "-w",
@@ -227,7 +436,8 @@ async fn get_changed(
                 let diff_text = match (index_status, worktree_status) {
                     ('?', '?') => {
                         // Untracked file - show entire content as added
-                        let cat_output = Command::new("docker")
+                        let cat_output = runtime()
+                            .async_command()
                             .args(["exec", "-w", &repo_path, &container, "cat", &path])
                             .output()
                             .await;
@@ -250,7 +460,8 @@ async fn get_changed(
                     }
                     _ => {
                         // Use git diff for tracked files
-                        let diff_output = Command::new("docker")
+                        let diff_output = runtime()
+                            .async_command()
                             .args([
                                 "exec", "-w", &repo_path, &container, "git", "diff", "HEAD", "--",
                                 &path,
@@ -263,7 +474,8 @@ async fn get_changed(
                                     String::from_utf8_lossy(&diff_out.stdout).to_string();
                                 if diff_content.is_empty() {
                                     // Try diff against index for staged changes
-                                    let staged_diff = Command::new("docker")
+                                    let staged_diff = runtime()
+                                        .async_command()
                                         .args([
                                             "exec", "-w", &repo_path, &container, "git", "diff",
                                             "--cached", "--", &path,
@@ -319,7 +531,6 @@ async fn get_changed(
 
 #[derive(Deserialize)]
 pub struct TerminalParams {
-    token: Option<String>,
     run: Option<String>,
     run_b64: Option<String>,
     cwd: Option<String>,
@@ -331,26 +542,16 @@ pub async fn terminal_ws(
     Path(container): Path<String>,
     Query(params): Query<TerminalParams>,
 ) -> Response {
-    let token_matches = params
-        .token
-        .as_ref()
-        .map(|t| t == &container)
-        .unwrap_or(true);
-
-    if token_matches {
-        ws.on_upgrade(move |socket| {
-            handle_terminal(
-                socket,
-                container,
-                params.run,
-                params.run_b64,
-                params.cwd,
-                params.cwd_b64,
-            )
-        })
-    } else {
-        (StatusCode::UNAUTHORIZED, "invalid token").into_response()
-    }
+    ws.on_upgrade(move |socket| {
+        handle_terminal(
+            socket,
+            container,
+            params.run,
+            params.run_b64,
+            params.cwd,
+            params.cwd_b64,
+        )
+    })
 }
 
 async fn handle_terminal(
@@ -373,7 +574,8 @@ async fn handle_terminal(
 
     // If a working directory was provided, ensure it exists inside the container
     if let Some(ref workdir) = resolved_cwd {
-        let _ = Command::new("docker")
+        let _ = runtime()
+            .async_command()
             .args(["exec", &container, "mkdir", "-p", workdir])
             .status()
             .await;
@@ -389,8 +591,8 @@ async fn handle_terminal(
         run.clone()
     };
 
-    // Build docker exec command, adding -w when we have a workdir
-    let mut docker_cmd = Command::new("docker");
+    // Build the runtime's exec command, adding -w when we have a workdir
+    let mut docker_cmd = runtime().async_command();
     docker_cmd.arg("exec");
     docker_cmd.arg("-i");
     if let Some(ref workdir) = resolved_cwd {
@@ -456,9 +658,7 @@ async fn handle_terminal(
     // Keep stdin injection only when no autorun was provided (compat for /terminal?run=...).
     if autorun.is_none() {
         if let Some(cmd_plain) = run {
-            let _ = stdin
-                .write_all(format!("{}\n", cmd_plain).as_bytes())
-                .await;
+            let _ = stdin.write_all(format!("{}\n", cmd_plain).as_bytes()).await;
             let _ = stdin.flush().await;
         }
     }
@@ -521,7 +721,8 @@ async fn handle_terminal(
                             // Resize the active tmux window in the target container
                             let container_clone = container.clone();
                             tokio::spawn(async move {
-                                let _ = Command::new("docker")
+                                let _ = runtime()
+                                    .async_command()
                                     .args([
                                         "exec",
                                         &container_clone,
@@ -563,18 +764,25 @@ async fn handle_terminal(
     let _ = child.kill().await;
 }
 
-async fn shutdown_handler(
-    Extension(tx): Extension<Arc<Mutex<Option<oneshot::Sender<()>>>>>,
-) -> StatusCode {
+async fn shutdown_handler(Extension(tx): Extension<ShutdownSender>) -> StatusCode {
     if let Some(tx) = tx.lock().await.take() {
         let _ = tx.send(());
     }
     StatusCode::OK
 }
 
-pub async fn serve() -> Result<()> {
-    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
-    let shutdown_tx = Arc::new(Mutex::new(Some(shutdown_tx)));
+type ShutdownSender = Arc<Mutex<Option<oneshot::Sender<()>>>>;
+
+/// All routes of the API server and web UI, behind `auth`. Browsing and
+/// starting sandboxes are limited to `roots`; containers are looked up in
+/// `registry`. Sending on `shutdown_tx` is how `/shutdown` stops the server.
+pub fn app(
+    auth: ServerAuth,
+    roots: WorkspaceRoots,
+    registry: ContainerRegistry,
+    shutdown_tx: oneshot::Sender<()>,
+) -> Router {
+    let shutdown_tx: ShutdownSender = Arc::new(Mutex::new(Some(shutdown_tx)));
     let serve_dir = ServeDir::new("web/dist").fallback(ServeFile::new("web/dist/index.html"));
     let static_files = service_fn(move |req: Request<Body>| {
         let serve_dir = serve_dir.clone();
@@ -591,27 +799,136 @@ pub async fn serve() -> Result<()> {
             }
         }
     });
-    let app = Router::new()
+    Router::new()
         .route("/api/changed/:container", get(get_changed))
+        .route("/api/containers", get(list_containers_api))
+        .route(
+            "/api/containers/:container",
+            get(get_container_api).delete(remove_container_api),
+        )
+        .route("/api/containers/:container/start", post(start_existing_api))
+        .route("/api/containers/:container/stop", post(stop_container_api))
+        .route(
+            "/api/containers/:container/restart",
+            post(restart_container_api),
+        )
+        .route("/api/containers/:container/logs", get(container_logs_api))
+        .route("/api/cleanup", post(cleanup_api))
         .route("/api/list", get(list_dir))
         .route("/api/start", post(start_container_api))
         .route("/terminal/:container", get(terminal_ws))
         .route("/shutdown", get(shutdown_handler))
         .nest_service("/", static_files)
-        .layer(Extension(shutdown_tx));
-    let addr = SocketAddr::from(([0, 0, 0, 0], 6789));
-    println!("Listening on {addr}");
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(async {
-            shutdown_rx.await.ok();
-        })
-        .await?;
-    Ok(())
+        .layer(middleware::from_fn_with_state(auth, auth::require_auth))
+        .layer(Extension(shutdown_tx))
+        .layer(Extension(roots))
+        .layer(Extension(registry))
+}
+
+/// Run the server on the address and transport in `settings` until
+/// `/shutdown` is requested. While it runs, `ServerState` records where it
+/// listens so the CLI can find it.
+pub async fn serve(settings: &ServerSettings, web_host: &str) -> Result<()> {
+    let auth = ServerAuth::load()?;
+    let addr = listener::bind_addr(settings)?;
+    let mut hosts = vec![web_host.to_string()];
+    if !addr.ip().is_unspecified() {
+        hosts.push(addr.ip().to_string());
+    }
+    let tls = listener::tls_files(settings, &hosts)?;
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let roots = WorkspaceRoots::from_settings(settings);
+    for root in roots.roots() {
+        println!("Workspace root: {}", root.display());
+    }
+    let registry = ContainerRegistry::default();
+    match registry.scan().await {
+        Ok(count) => println!("Found {} sandbox container(s)", count),
+        Err(e) => eprintln!("warning: {:#}", e),
+    }
+    let app = app(auth, roots, registry, shutdown_tx);
+
+    let handle = axum_server::Handle::new();
+    let shutdown = handle.clone();
+    tokio::spawn(async move {
+        shutdown_rx.await.ok();
+        shutdown.graceful_shutdown(Some(Duration::from_secs(5)));
+    });
+    let state_path = ServerState::path();
+    let listening = handle.clone();
+    let tls_cert = tls.as_ref().map(|(cert, _)| cert.clone());
+    let written_path = state_path.clone();
+    tokio::spawn(async move {
+        // Known only once bound, which matters for port 0
+        if let Some(addr) = listening.listening().await {
+            let state = ServerState {
+                url: listener::local_url(addr, tls_cert.is_some()),
+                pid: std::process::id(),
+                tls_cert,
+            };
+            println!("Listening on {addr}");
+            println!(
+                "Open {}/?token=<secret>; the secret is in {}",
+                state.url,
+                auth::secret_path().display()
+            );
+            if let Err(e) = state.save(&written_path) {
+                eprintln!("warning: {:#}", e);
+            }
+        }
+    });
+
+    let result = match tls {
+        Some((cert, key)) => {
+            let config = RustlsConfig::from_pem_file(&cert, &key)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to load TLS certificate {} and key {}",
+                        cert.display(),
+                        key.display()
+                    )
+                })?;
+            axum_server::bind_rustls(addr, config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+        None => {
+            axum_server::bind(addr)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+    };
+    ServerState::clear(&state_path);
+    result.with_context(|| format!("Failed to serve on {}", addr))
+}
+
+/// The running server, if its state file is current and it answers.
+pub async fn running_server() -> Result<Option<ServerState>> {
+    let Some(state) = ServerState::load(&ServerState::path())? else {
+        return Ok(None);
+    };
+    let reachable = state
+        .client()?
+        .get(&state.url)
+        .timeout(Duration::from_millis(300))
+        .send()
+        .await
+        .is_ok();
+    Ok(Some(state).filter(|_| reachable))
 }
 
 pub async fn stop() -> Result<()> {
-    reqwest::get("http://127.0.0.1:6789/shutdown")
+    let state =
+        ServerState::load(&ServerState::path())?.context("The API server is not running")?;
+    let secret = auth::load_or_create_secret(&auth::secret_path())?;
+    state
+        .client()?
+        .get(format!("{}/shutdown", state.url))
+        .bearer_auth(secret)
+        .send()
         .await
         .context("failed to send shutdown signal")?;
     Ok(())
@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::container::{inspect_container, list_sandbox_containers, ContainerInfo};
+
+/// The sandbox containers the server knows about. The container labels are
+/// the source of truth, so containers created by the CLI or before a server
+/// restart are found as well; this only caches what was last read from them.
+#[derive(Clone, Default)]
+pub struct ContainerRegistry {
+    containers: Arc<RwLock<BTreeMap<String, ContainerInfo>>>,
+}
+
+impl ContainerRegistry {
+    /// Replace the cache with every sandbox container, running or stopped,
+    /// returning how many were found.
+    pub async fn scan(&self) -> Result<usize> {
+        let found = tokio::task::spawn_blocking(list_sandbox_containers)
+            .await
+            .context("Failed to scan containers")??;
+        let mut containers = self.containers.write().await;
+        *containers = found
+            .into_iter()
+            .map(|info| (info.name.clone(), info))
+            .collect();
+        Ok(containers.len())
+    }
+
+    /// Record a container the server just created.
+    pub async fn insert(&self, info: ContainerInfo) {
+        self.containers
+            .write()
+            .await
+            .insert(info.name.clone(), info);
+    }
+
+    /// Forget a container that was removed.
+    pub async fn remove(&self, name: &str) {
+        self.containers.write().await.remove(name);
+    }
+
+    /// Current metadata of `name`, or `None` if it is not a sandbox
+    /// container. The cached entry is used when the runtime cannot be asked.
+    pub async fn get(&self, name: &str) -> Result<Option<ContainerInfo>> {
+        let owned = name.to_string();
+        let inspected = tokio::task::spawn_blocking(move || inspect_container(&owned))
+            .await
+            .context("Failed to inspect container")?;
+        let mut containers = self.containers.write().await;
+        match inspected {
+            Ok(Some(info)) => {
+                containers.insert(info.name.clone(), info.clone());
+                Ok(Some(info))
+            }
+            Ok(None) => {
+                containers.remove(name);
+                Ok(None)
+            }
+            Err(e) => containers.get(name).cloned().map(Some).ok_or(e),
+        }
+    }
+
+    /// Every sandbox container, rescanned so the list is current.
+    pub async fn list(&self) -> Result<Vec<ContainerInfo>> {
+        self.scan().await?;
+        Ok(self.containers.read().await.values().cloned().collect())
+    }
+}
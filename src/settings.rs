@@ -1,11 +1,11 @@
-use anyhow::Result;
-use serde::Deserialize;
-use std::collections::HashMap;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Settings {
     pub auto_remove_minutes: Option<u64>,
     #[serde(default)]
@@ -16,6 +16,281 @@ pub struct Settings {
     pub web: Option<bool>,
     // Hostname to use when printing/opening the web UI URL (defaults to "localhost")
     pub web_host: Option<String>,
+    // Container runtime binary to drive: "docker" (default), "podman" or "nerdctl"
+    pub runtime: Option<String>,
+    // Agent started when --agent is not given
+    pub agent: Option<String>,
+    // Toolchains installed even when no marker file is detected (e.g. "rust", "node")
+    #[serde(default)]
+    pub toolchains: Vec<String>,
+    // Extra host directories as `path[:container_path][:ro|rw]`; read-only at
+    // the same path unless stated otherwise
+    #[serde(default)]
+    pub mounts: Vec<String>,
+    // Image the sandbox is built FROM (Debian/Ubuntu based); defaults to ubuntu:22.04
+    pub base_image: Option<String>,
+    // Path to a Dockerfile fragment appended to the generated Dockerfile
+    pub dockerfile_fragment: Option<String>,
+    // How many directory levels below the project root to scan for marker files
+    pub language_scan_depth: Option<usize>,
+    // Extra gitignore-style patterns skipped by the language scan
+    #[serde(default)]
+    pub language_scan_exclude: Vec<String>,
+    // CPU, memory, process and storage limits applied to new sandboxes
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    // Outbound network access for new sandboxes
+    #[serde(default)]
+    pub network: NetworkSettings,
+    // Mount the project read-only and keep edits in the container until applied
+    pub isolated: Option<bool>,
+    // Secrets passed to new sandboxes, keyed by the name the sandbox sees
+    #[serde(default)]
+    pub secrets: BTreeMap<String, SecretSettings>,
+    // Agents added to, or overriding fields of, the built-in ones, keyed by name
+    #[serde(default)]
+    pub agents: BTreeMap<String, AgentSettings>,
+    // Address and TLS of the API server started by `codesandbox serve`
+    #[serde(default)]
+    pub server: ServerSettings,
+}
+
+/// Where the API server listens and whether it serves HTTPS.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ServerSettings {
+    // Address to listen on as host:port (defaults to 127.0.0.1:6789);
+    // use 0.0.0.0:6789 to reach the web UI from other machines
+    pub bind: Option<String>,
+    // Serve HTTPS; without tls_cert and tls_key a self-signed certificate is generated
+    pub tls: Option<bool>,
+    // PEM certificate chain and private key for HTTPS
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    // Directories the web UI may browse and start sandboxes in (defaults to
+    // the home directory)
+    #[serde(default)]
+    pub workspace_roots: Vec<String>,
+}
+
+/// An agent CLI. For a built-in agent, fields that are set replace the
+/// built-in values; an empty string clears a flag.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AgentSettings {
+    // Name shown in messages (defaults to the agent's key)
+    pub display_name: Option<String>,
+    // Executable started in the sandbox (defaults to the agent's key)
+    pub binary: Option<String>,
+    // Shell command run as root while building the image to install the agent
+    pub install: Option<String>,
+    // Config directories to mount: "aider" mounts ./.aider, ~/.aider or ~/.config/aider
+    pub config_dirs: Option<Vec<String>>,
+    // Flag that skips the agent's permission prompts
+    pub skip_permission_flag: Option<String>,
+    // Flag or subcommand that resumes the previous conversation for
+    // `--continue`, e.g. "--continue" or "resume --last"
+    pub continue_args: Option<String>,
+    // Arguments for `codesandbox run`; `{prompt}` stands for the prompt,
+    // which is piped to stdin otherwise
+    pub headless_args: Option<String>,
+}
+
+/// How a secret reaches the sandbox.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretTarget {
+    /// An environment variable named after the secret.
+    #[default]
+    Env,
+    /// A file named after the secret under `/run/secrets`, on a tmpfs.
+    File,
+}
+
+/// Where a secret's value comes from. Exactly one source must be set.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SecretSettings {
+    // Host environment variable holding the value
+    pub env: Option<String>,
+    // Shell command printing the value, e.g. "pass show github/token"
+    pub command: Option<String>,
+    // Entry in the encrypted store managed by `codesandbox secrets`
+    pub store: Option<String>,
+    #[serde(default)]
+    pub inject: SecretTarget,
+}
+
+/// How much of the network a sandbox can reach.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkPolicy {
+    /// Unrestricted access (the runtime's default bridge network).
+    #[default]
+    Full,
+    /// Only hosts on the allowlist, through the built-in egress proxy.
+    Allowlist,
+    /// No network at all.
+    None,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct NetworkSettings {
+    #[serde(default)]
+    pub policy: NetworkPolicy,
+    // Hosts allowed in addition to the built-in list; "*.example.com" matches subdomains
+    #[serde(default)]
+    pub allow: Vec<String>,
+    // Port of the egress proxy on the host (defaults to 6790)
+    pub proxy_port: Option<u16>,
+}
+
+/// An extra directory mounted into the sandbox, written as
+/// `path[:container_path][:ro|rw]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountSpec {
+    pub host: PathBuf,
+    pub container: PathBuf,
+    pub read_only: bool,
+}
+
+impl MountSpec {
+    /// Parse a mount spec. Without a container path the directory is mounted
+    /// at its host path; without a mode it is read-only.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut parts: Vec<&str> = spec.split(':').collect();
+        let read_only = match parts.last().copied() {
+            Some("ro") if parts.len() > 1 => {
+                parts.pop();
+                true
+            }
+            Some("rw") if parts.len() > 1 => {
+                parts.pop();
+                false
+            }
+            _ => true,
+        };
+        let (host, container) = match parts.as_slice() {
+            [host] => (*host, *host),
+            [host, container] if Path::new(container).is_absolute() => (*host, *container),
+            [_, _] => {
+                return Err(anyhow!(
+                    "invalid mount `{}`: container path must be absolute",
+                    spec
+                ))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "invalid mount `{}`: expected path[:container_path][:ro|rw]",
+                    spec
+                ))
+            }
+        };
+        if host.is_empty() {
+            return Err(anyhow!("invalid mount `{}`: missing host path", spec));
+        }
+        Ok(Self {
+            host: PathBuf::from(host),
+            container: PathBuf::from(container),
+            read_only,
+        })
+    }
+
+    /// Resolve the host path; a mount at the host path follows it.
+    pub fn canonicalize(&self) -> Result<Self> {
+        let host = fs::canonicalize(&self.host)
+            .with_context(|| format!("Failed to canonicalize path {}", self.host.display()))?;
+        let container = if self.container == self.host {
+            host.clone()
+        } else {
+            self.container.clone()
+        };
+        Ok(Self {
+            host,
+            container,
+            read_only: self.read_only,
+        })
+    }
+
+    /// `-v` argument for `<runtime> run`.
+    pub fn volume_arg(&self) -> String {
+        let mut arg = format!("{}:{}", self.host.display(), self.container.display());
+        if self.read_only {
+            arg.push_str(":ro");
+        }
+        arg
+    }
+}
+
+impl std::fmt::Display for MountSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = if self.read_only { "ro" } else { "rw" };
+        if self.host == self.container {
+            write!(f, "{}:{}", self.host.display(), mode)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}",
+                self.host.display(),
+                self.container.display(),
+                mode
+            )
+        }
+    }
+}
+
+/// Resource limits for a sandbox container. Unset fields mean no limit.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ResourceLimits {
+    pub cpus: Option<f64>,
+    // Runtime size syntax, e.g. "4g" or "512m"
+    pub memory: Option<String>,
+    pub pids_limit: Option<u64>,
+    // Writable layer quota; only some storage drivers support it
+    pub storage: Option<String>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Arguments for `<runtime> run` that apply these limits.
+    pub fn run_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cpus) = self.cpus {
+            args.extend(["--cpus".to_string(), cpus.to_string()]);
+        }
+        if let Some(memory) = &self.memory {
+            args.extend(["--memory".to_string(), memory.clone()]);
+        }
+        if let Some(pids_limit) = self.pids_limit {
+            args.extend(["--pids-limit".to_string(), pids_limit.to_string()]);
+        }
+        if let Some(storage) = &self.storage {
+            args.extend(["--storage-opt".to_string(), format!("size={}", storage)]);
+        }
+        args
+    }
+
+    /// Short human-readable form, e.g. `cpus=2 memory=4g`, or `-` without limits.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(cpus) = self.cpus {
+            parts.push(format!("cpus={}", cpus));
+        }
+        if let Some(memory) = &self.memory {
+            parts.push(format!("memory={}", memory));
+        }
+        if let Some(pids_limit) = self.pids_limit {
+            parts.push(format!("pids={}", pids_limit));
+        }
+        if let Some(storage) = &self.storage {
+            parts.push(format!("storage={}", storage));
+        }
+        if parts.is_empty() {
+            "-".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
 }
 
 impl Default for Settings {
@@ -34,6 +309,20 @@ impl Default for Settings {
             env_files: default_env_files(),
             web: Some(false),
             web_host: Some("localhost".to_string()),
+            runtime: None,
+            agent: None,
+            toolchains: Vec::new(),
+            mounts: Vec::new(),
+            base_image: None,
+            dockerfile_fragment: None,
+            language_scan_depth: None,
+            language_scan_exclude: Vec::new(),
+            limits: ResourceLimits::default(),
+            network: NetworkSettings::default(),
+            isolated: Some(false),
+            secrets: BTreeMap::new(),
+            agents: BTreeMap::new(),
+            server: ServerSettings::default(),
         }
     }
 }
@@ -48,7 +337,16 @@ fn default_env_files() -> Vec<String> {
     ]
 }
 
-fn settings_file_path() -> PathBuf {
+impl Settings {
+    pub fn skip_permission_flag(&self, agent_command: &str) -> Option<&str> {
+        self.skip_permission_flags
+            .iter()
+            .find(|(agent, _)| agent.eq_ignore_ascii_case(agent_command))
+            .map(|(_, flag)| flag.as_str())
+    }
+}
+
+pub fn settings_file_path() -> PathBuf {
     if let Ok(dir) = env::var("CODESANDBOX_CONFIG_HOME") {
         return PathBuf::from(dir).join("settings.json");
     }
@@ -58,13 +356,127 @@ fn settings_file_path() -> PathBuf {
         .join("settings.json")
 }
 
-pub fn load_settings() -> Result<Settings> {
-    let path = settings_file_path();
-    if let Ok(data) = fs::read_to_string(path) {
-        if let Ok(settings) = serde_json::from_str::<Settings>(&data) {
-            return Ok(settings);
+/// Parse `settings.json` contents strictly. Syntax and type errors are
+/// reported with their line and column; unknown keys are returned as warnings.
+pub fn parse_settings_json(data: &str) -> Result<(Settings, Vec<String>)> {
+    let mut ignored = Vec::new();
+    let mut de = serde_json::Deserializer::from_str(data);
+    let settings: Settings =
+        serde_ignored::deserialize(&mut de, |path| ignored.push(path.to_string()))
+            .and_then(|settings| de.end().map(|_| settings))
+            .map_err(|e| {
+                let message = e.to_string();
+                let suffix = format!(" at line {} column {}", e.line(), e.column());
+                let message = message.strip_suffix(&suffix).unwrap_or(&message);
+                anyhow!("{}:{}: {}", e.line(), e.column(), message)
+            })?;
+    Ok((settings, unknown_key_warnings(&ignored)))
+}
+
+/// Parse `.codesandbox.toml` contents with the same rules as `settings.json`.
+pub fn parse_settings_toml(data: &str) -> Result<(Settings, Vec<String>)> {
+    let mut ignored = Vec::new();
+    let de = toml::Deserializer::new(data);
+    let settings: Settings = serde_ignored::deserialize(de, |path| ignored.push(path.to_string()))
+        .map_err(|e| {
+            let offset = e.span().map(|span| span.start).unwrap_or(0);
+            let (line, column) = line_column(data, offset);
+            anyhow!("{}:{}: {}", line, column, e.message())
+        })?;
+    Ok((settings, unknown_key_warnings(&ignored)))
+}
+
+fn line_column(data: &str, offset: usize) -> (usize, usize) {
+    let before = &data[..offset.min(data.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    (line, column)
+}
+
+fn unknown_key_warnings(ignored: &[String]) -> Vec<String> {
+    let known: Vec<String> = match serde_json::to_value(Settings::default()) {
+        Ok(serde_json::Value::Object(map)) => map.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    ignored
+        .iter()
+        .map(|key| {
+            let suggestion = known
+                .iter()
+                .map(|k| (edit_distance(key, k), k))
+                .filter(|(distance, _)| *distance <= 2)
+                .min_by_key(|(distance, _)| *distance);
+            match suggestion {
+                Some((_, k)) => format!("unknown key `{}` (did you mean `{}`?)", key, k),
+                None => format!("unknown key `{}`", key),
+            }
+        })
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
         }
+        previous = current;
     }
-    Ok(Settings::default())
+    previous[b.len()]
 }
 
+/// Check a settings file without loading it, returning its warnings.
+/// The format follows the extension: `.toml` for project files, JSON otherwise.
+pub fn validate_settings_file(path: &Path) -> Result<Vec<String>> {
+    let data =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let parsed = if path.extension().is_some_and(|ext| ext == "toml") {
+        parse_settings_toml(&data)
+    } else {
+        parse_settings_json(&data)
+    };
+    parsed
+        .map(|(_, warnings)| warnings)
+        .map_err(|e| anyhow!("{}:{}", path.display(), e))
+}
+
+/// Parse the contents of a settings file for layering, returning the keys it
+/// sets and its warnings. Values are checked as strictly as by
+/// `validate_settings_file`; only when they fail is the file parsed again to
+/// locate the problem.
+pub fn parse_settings_layer(
+    path: &Path,
+    data: &str,
+) -> Result<(serde_json::Map<String, serde_json::Value>, Vec<String>)> {
+    let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+    let value = if is_toml {
+        toml::from_str::<toml::Table>(data)
+            .ok()
+            .and_then(|table| serde_json::to_value(table).ok())
+    } else {
+        serde_json::from_str::<serde_json::Value>(data).ok()
+    };
+    let mut ignored = Vec::new();
+    let checked = value.filter(|value| {
+        serde_ignored::deserialize::<_, _, Settings>(value, |key| ignored.push(key.to_string()))
+            .is_ok()
+    });
+    match checked {
+        Some(serde_json::Value::Object(map)) => Ok((map, unknown_key_warnings(&ignored))),
+        Some(_) => Err(anyhow!("{}: expected a table of settings", path.display())),
+        None => {
+            let located = if is_toml {
+                parse_settings_toml(data)
+            } else {
+                parse_settings_json(data)
+            };
+            match located {
+                Err(e) => Err(anyhow!("{}:{}", path.display(), e)),
+                Ok(_) => Err(anyhow!("{}: invalid settings", path.display())),
+            }
+        }
+    }
+}
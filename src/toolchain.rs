@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::engine::runtime;
+use crate::language::{DetectedLanguage, ProjectLanguage};
+
+/// A toolchain version pinned by a file in the project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedVersion {
+    pub language: ProjectLanguage,
+    pub version: String,
+    /// The file the pin came from, relative to the project root.
+    pub source: PathBuf,
+}
+
+/// Collect version pins from the project root and from every directory a
+/// language was detected in. The first pin found for a language wins; the root
+/// comes first, and conflicting pins further down are reported and ignored.
+pub fn read_pinned_versions(
+    project_dir: &Path,
+    detected: &[DetectedLanguage],
+) -> Vec<PinnedVersion> {
+    let mut dirs = vec![PathBuf::new()];
+    for found in detected {
+        for path in found.paths.iter() {
+            if !dirs.contains(path) {
+                dirs.push(path.clone());
+            }
+        }
+    }
+
+    let mut pins: Vec<PinnedVersion> = Vec::new();
+    for dir in dirs {
+        for pin in pins_in_dir(project_dir, &dir) {
+            match pins.iter().find(|p| p.language == pin.language) {
+                Some(existing) if existing.version != pin.version => println!(
+                    "Ignoring {} pin {} from {}; using {} from {}",
+                    pin.language.name(),
+                    pin.version,
+                    pin.source.display(),
+                    existing.version,
+                    existing.source.display()
+                ),
+                Some(_) => {}
+                None => pins.push(pin),
+            }
+        }
+    }
+    pins
+}
+
+fn pins_in_dir(project_dir: &Path, dir: &Path) -> Vec<PinnedVersion> {
+    let read = |name: &str| fs::read_to_string(project_dir.join(dir).join(name)).ok();
+    let pin = |language, version: Option<String>, name: &str| {
+        version.map(|version| PinnedVersion {
+            language,
+            version,
+            source: dir.join(name),
+        })
+    };
+
+    // Dedicated version files take precedence over .tool-versions
+    let mut pins: Vec<PinnedVersion> = [
+        pin(
+            ProjectLanguage::Rust,
+            read("rust-toolchain.toml").and_then(|s| parse_rust_toolchain(&s)),
+            "rust-toolchain.toml",
+        ),
+        pin(
+            ProjectLanguage::Rust,
+            read("rust-toolchain").and_then(|s| parse_rust_toolchain(&s)),
+            "rust-toolchain",
+        ),
+        pin(
+            ProjectLanguage::NodeJs,
+            read(".nvmrc").and_then(|s| parse_node_version(&s)),
+            ".nvmrc",
+        ),
+        pin(
+            ProjectLanguage::NodeJs,
+            read(".node-version").and_then(|s| parse_node_version(&s)),
+            ".node-version",
+        ),
+        pin(
+            ProjectLanguage::Python,
+            read(".python-version").and_then(|s| first_line(&s)),
+            ".python-version",
+        ),
+        pin(
+            ProjectLanguage::Go,
+            read("go.mod").and_then(|s| parse_go_mod(&s)),
+            "go.mod",
+        ),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if let Some(contents) = read(".tool-versions") {
+        for (language, version) in parse_tool_versions(&contents) {
+            pins.extend(pin(language, Some(version), ".tool-versions"));
+        }
+    }
+
+    let mut unique: Vec<PinnedVersion> = Vec::new();
+    for pin in pins {
+        if !unique.iter().any(|p| p.language == pin.language) {
+            unique.push(pin);
+        }
+    }
+    unique
+}
+
+fn first_line(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// `rust-toolchain.toml` (`[toolchain] channel = ...`) or the legacy
+/// single-line `rust-toolchain` file.
+pub fn parse_rust_toolchain(contents: &str) -> Option<String> {
+    if let Ok(table) = contents.parse::<toml::Table>() {
+        if let Some(channel) = table
+            .get("toolchain")
+            .and_then(|t| t.get("channel"))
+            .and_then(|c| c.as_str())
+        {
+            return Some(channel.to_string());
+        }
+    }
+    first_line(contents).filter(|line| !line.contains('=') && !line.starts_with('['))
+}
+
+/// `.nvmrc` / `.node-version`: `20`, `v20.11.0`, `lts/*` or `lts/iron`.
+pub fn parse_node_version(contents: &str) -> Option<String> {
+    let version = first_line(contents)?;
+    let version = match version.strip_prefix("lts/") {
+        Some("*") => "lts".to_string(),
+        Some(codename) => codename.to_string(),
+        None => version.trim_start_matches('v').to_string(),
+    };
+    Some(version)
+}
+
+/// The `toolchain` directive if present, otherwise the `go` directive.
+pub fn parse_go_mod(contents: &str) -> Option<String> {
+    let directive = |name: &str| {
+        contents.lines().find_map(|line| {
+            let mut parts = line.split_whitespace();
+            (parts.next() == Some(name)).then(|| parts.next()).flatten()
+        })
+    };
+    if let Some(toolchain) = directive("toolchain") {
+        if let Some(version) = toolchain.strip_prefix("go") {
+            return Some(version.to_string());
+        }
+    }
+    let version = directive("go")?;
+    // Since Go 1.21 the first release of a minor version is `1.N.0`
+    let parts: Vec<&str> = version.split('.').collect();
+    match parts.as_slice() {
+        ["1", minor] if minor.parse::<u32>().is_ok_and(|m| m >= 21) => {
+            Some(format!("{}.0", version))
+        }
+        _ => Some(version.to_string()),
+    }
+}
+
+/// asdf / mise `.tool-versions`: `<tool> <version> [fallback...]` per line.
+pub fn parse_tool_versions(contents: &str) -> Vec<(ProjectLanguage, String)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or("");
+            let mut parts = line.split_whitespace();
+            let tool = parts.next()?;
+            let version = parts.next()?;
+            let language = match tool {
+                "rust" => ProjectLanguage::Rust,
+                "nodejs" | "node" => ProjectLanguage::NodeJs,
+                "python" => ProjectLanguage::Python,
+                "golang" | "go" => ProjectLanguage::Go,
+                _ => return None,
+            };
+            Some((language, version.to_string()))
+        })
+        .collect()
+}
+
+/// The first dotted number in a `--version` banner, e.g. `1.79.0` from
+/// `rustc 1.79.0 (129f3b996 2024-06-10)` or `1.24.5` from `go version go1.24.5`.
+pub fn extract_version(output: &str) -> Option<String> {
+    let start = output.find(|c: char| c.is_ascii_digit())?;
+    let version: String = output[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    Some(version.trim_end_matches('.').to_string())
+}
+
+fn numeric_parts(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Whether the version the image provides satisfies a pin. Go versions are
+/// minimums, as in `go.mod`; other pins match by prefix (`3.12` accepts
+/// `3.12.4`). Channels and aliases such as `stable` or `lts` can't be read
+/// from a version banner, so `provided` is then the channel recorded when it
+/// was installed and has to match exactly.
+pub fn version_satisfies(language: ProjectLanguage, pinned: &str, provided: &str) -> bool {
+    let (Some(pinned), Some(provided)) = (numeric_parts(pinned), numeric_parts(provided)) else {
+        return numeric_parts(pinned).is_none() && pinned == provided;
+    };
+    if language == ProjectLanguage::Go {
+        return provided >= pinned;
+    }
+    provided.len() >= pinned.len() && provided[..pinned.len()] == pinned[..]
+}
+
+/// Where an installed channel pin is recorded in the container.
+const CHANNEL_RECORDS: &str = "$HOME/.cache/codesandbox/toolchains";
+
+fn channel_record(language: ProjectLanguage) -> String {
+    format!("{}/{}", CHANNEL_RECORDS, language.tool())
+}
+
+/// Command printing what the container provides for `pin`: the version banner,
+/// or for a channel the channel recorded when it was installed.
+fn version_cmd(pin: &PinnedVersion) -> String {
+    if numeric_parts(&pin.version).is_none() {
+        return format!("cat \"{}\" 2>/dev/null", channel_record(pin.language));
+    }
+    match pin.language {
+        ProjectLanguage::Rust => "rustc --version",
+        ProjectLanguage::NodeJs => "node --version",
+        ProjectLanguage::Python => "python3 --version",
+        ProjectLanguage::Go => "go version",
+        _ => "true",
+    }
+    .to_string()
+}
+
+/// Command installing the pinned version, or `None` when a pin is left alone.
+/// Only a pin in the project root switches the container's toolchain. Below
+/// the root, Rust and Python versions are installed for rustup and uv to pick
+/// from the pin file inside that directory; Node.js has no such mechanism, and
+/// Go downloads the toolchain a module asks for by itself.
+pub fn install_cmd(pin: &PinnedVersion) -> Option<String> {
+    let version = pin.version.as_str();
+    let root = pin.source.parent() == Some(Path::new(""));
+    let cmd = match pin.language {
+        ProjectLanguage::Rust if root => format!(
+            "rustup toolchain install {v} --profile minimal --component rustfmt,clippy && rustup default {v}",
+            v = version
+        ),
+        ProjectLanguage::Rust => format!(
+            "rustup toolchain install {} --profile minimal --component rustfmt,clippy",
+            version
+        ),
+        ProjectLanguage::NodeJs if root => format!("sudo npm install -g n && sudo n {}", version),
+        ProjectLanguage::Python if root => format!(
+            "uv python install {v} && ln -sf \"$(uv python find {v})\" \"$HOME/.local/bin/python3\" && ln -sf \"$(uv python find {v})\" \"$HOME/.local/bin/python\"",
+            v = version
+        ),
+        ProjectLanguage::Python => format!("uv python install {}", version),
+        ProjectLanguage::Go if root => format!(
+            "cd /tmp && wget -q https://go.dev/dl/go{v}.linux-amd64.tar.gz && sudo rm -rf /usr/local/go && sudo tar -C /usr/local -xzf go{v}.linux-amd64.tar.gz && rm go{v}.linux-amd64.tar.gz",
+            v = version
+        ),
+        _ => return None,
+    };
+    if numeric_parts(version).is_some() {
+        return Some(cmd);
+    }
+    // Record the channel so the next start can tell it is installed
+    let record = channel_record(pin.language);
+    Some(format!(
+        "{} && mkdir -p \"{}\" && echo {} > \"{}\"",
+        cmd, CHANNEL_RECORDS, version, record
+    ))
+}
+
+// Versions come from project files and end up in a shell command
+fn is_safe_version(version: &str) -> bool {
+    !version.is_empty()
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Compare each pin with the version the image provides, report the result and
+/// install the pinned version on a mismatch. A failed install is reported but
+/// does not stop the sandbox from starting.
+pub fn ensure_pinned_toolchains(
+    container_name: &str,
+    project_dir: &Path,
+    detected: &[DetectedLanguage],
+) -> Result<()> {
+    for pin in read_pinned_versions(project_dir, detected) {
+        let name = pin.language.name();
+        if !is_safe_version(&pin.version) {
+            println!(
+                "Ignoring {} pin '{}' from {}: unsupported version format",
+                name,
+                pin.version,
+                pin.source.display()
+            );
+            continue;
+        }
+
+        // Ask from the pin's directory, where rustup honors a nested pin file
+        let workdir = project_dir.join(pin.source.parent().unwrap_or(Path::new("")));
+        let output = runtime()
+            .command()
+            .arg("exec")
+            .arg("-w")
+            .arg(&workdir)
+            .args([container_name, "bash", "-lc", &version_cmd(&pin)])
+            .output()
+            .with_context(|| format!("Failed to check the {} version", name))?;
+        let comparable = numeric_parts(&pin.version).is_some();
+        let provided = output
+            .status
+            .success()
+            .then(|| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                if comparable {
+                    extract_version(&stdout)
+                } else {
+                    Some(stdout.trim().to_string()).filter(|channel| !channel.is_empty())
+                }
+            })
+            .flatten();
+        if let Some(provided) = provided
+            .as_deref()
+            .filter(|provided| version_satisfies(pin.language, &pin.version, provided))
+        {
+            println!(
+                "{} {} satisfies {} from {}",
+                name,
+                provided,
+                pin.version,
+                pin.source.display()
+            );
+            continue;
+        }
+        let Some(install) = install_cmd(&pin) else {
+            println!(
+                "{} {} from {} applies only to that directory; leaving the container's {} as is",
+                name,
+                pin.version,
+                pin.source.display(),
+                name
+            );
+            continue;
+        };
+        match &provided {
+            Some(provided) if comparable => println!(
+                "{} version mismatch: {} pins {}, image provides {}; installing {}",
+                name,
+                pin.source.display(),
+                pin.version,
+                provided,
+                pin.version
+            ),
+            _ => println!(
+                "{} pinned to {} by {}; installing",
+                name,
+                pin.version,
+                pin.source.display()
+            ),
+        }
+
+        // Stream the install output like `ensure_language_tools` does
+        let status = runtime()
+            .command()
+            .args(["exec", container_name, "bash", "-lc", &install])
+            .status()
+            .with_context(|| format!("Failed to install {} {}", name, pin.version))?;
+        if !status.success() {
+            println!(
+                "Warning: installing {} {} failed ({}); continuing with {}",
+                name,
+                pin.version,
+                status,
+                provided.as_deref().unwrap_or("no preinstalled version")
+            );
+        }
+    }
+    Ok(())
+}
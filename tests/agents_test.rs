@@ -0,0 +1,73 @@
+use codesandbox::agents::{AgentRegistry, DEFAULT_AGENT};
+use codesandbox::settings::{AgentSettings, Settings};
+
+#[test]
+fn builtin_agents_are_registered() {
+    let registry = AgentRegistry::builtin();
+    assert_eq!(
+        registry.names(),
+        vec!["claude", "gemini", "codex", "qwen", "cursor"]
+    );
+    let cursor = registry.get("cursor").unwrap();
+    assert_eq!(cursor.command(), "cursor-agent");
+    assert_eq!(cursor.to_string(), "Cursor");
+    assert_eq!(cursor.config_dirs, vec!["cursor"]);
+    // Containers labelled before the registry recorded the binary
+    assert_eq!(registry.get("cursor-agent").unwrap().name, "cursor");
+    assert_eq!(
+        registry.resolve(None).unwrap().name,
+        DEFAULT_AGENT.to_string()
+    );
+}
+
+#[test]
+fn settings_add_and_override_agents() {
+    let mut settings = Settings::default();
+    settings.agents.insert(
+        "aider".to_string(),
+        AgentSettings {
+            install: Some("pip install aider-chat".to_string()),
+            config_dirs: Some(vec!["aider".to_string()]),
+            skip_permission_flag: Some("--yes-always".to_string()),
+            headless_args: Some("--message {prompt}".to_string()),
+            ..Default::default()
+        },
+    );
+    settings.agents.insert(
+        "claude".to_string(),
+        AgentSettings {
+            continue_args: Some(String::new()),
+            ..Default::default()
+        },
+    );
+    settings
+        .skip_permission_flags
+        .insert("codex".to_string(), "--full-auto".to_string());
+
+    let registry = AgentRegistry::from_settings(&settings).unwrap();
+    let aider = registry.resolve(Some("aider")).unwrap();
+    assert_eq!(aider.command(), "aider");
+    assert_eq!(aider.to_string(), "aider");
+    assert_eq!(aider.install.as_deref(), Some("pip install aider-chat"));
+    assert_eq!(aider.skip_permission_flag.as_deref(), Some("--yes-always"));
+    assert!(aider.continue_args.is_none());
+
+    let claude = registry.get("claude").unwrap();
+    assert!(claude.continue_args.is_none());
+    assert_eq!(
+        claude.skip_permission_flag.as_deref(),
+        Some("--dangerously-skip-permissions")
+    );
+    assert_eq!(
+        registry
+            .get("codex")
+            .unwrap()
+            .skip_permission_flag
+            .as_deref(),
+        Some("--full-auto")
+    );
+
+    let err = registry.resolve(Some("opencode")).unwrap_err().to_string();
+    assert!(err.contains("Unknown agent 'opencode'"));
+    assert!(err.contains("aider"));
+}
@@ -3,14 +3,15 @@ use clap::Parser;
 #[path = "../src/cli.rs"]
 mod cli;
 
-use cli::{Agent, Cli, Commands};
+use cli::{Cli, Commands, ConfigCommand, ImageCommand, OutputFormat};
+use std::path::PathBuf;
 
 #[test]
 fn parse_continue_flag() {
     let cli = Cli::parse_from(["codesandbox", "--continue"]);
     assert!(cli.continue_);
     assert!(!cli.cleanup);
-    assert!(cli.add_dir.is_none());
+    assert!(cli.add_dir.is_empty());
 }
 
 #[test]
@@ -18,14 +19,14 @@ fn parse_cleanup_flag() {
     let cli = Cli::parse_from(["codesandbox", "--cleanup"]);
     assert!(cli.cleanup);
     assert!(!cli.continue_);
-    assert!(cli.add_dir.is_none());
+    assert!(cli.add_dir.is_empty());
 }
 
 #[test]
 fn parse_ls_subcommand() {
     let cli = Cli::parse_from(["codesandbox", "ls"]);
     assert!(matches!(cli.command, Some(Commands::Ls)));
-    assert!(cli.add_dir.is_none());
+    assert!(cli.add_dir.is_empty());
 }
 
 #[test]
@@ -39,7 +40,7 @@ fn parse_serve_subcommand() {
     let cli = Cli::parse_from(["codesandbox", "serve"]);
     assert!(matches!(
         cli.command,
-        Some(Commands::Serve { daemon: false })
+        Some(Commands::Serve { daemon: false, .. })
     ));
 }
 
@@ -48,14 +49,64 @@ fn parse_serve_daemon_flag() {
     let cli = Cli::parse_from(["codesandbox", "serve", "-d"]);
     assert!(matches!(
         cli.command,
-        Some(Commands::Serve { daemon: true })
+        Some(Commands::Serve { daemon: true, .. })
     ));
 }
 
 #[test]
 fn parse_stop_subcommand() {
     let cli = Cli::parse_from(["codesandbox", "stop"]);
-    assert!(matches!(cli.command, Some(Commands::Stop)));
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Stop { container: None })
+    ));
+
+    let cli = Cli::parse_from(["codesandbox", "stop", "2"]);
+    match cli.command {
+        Some(Commands::Stop {
+            container: Some(container),
+        }) => assert_eq!(container, "2"),
+        _ => panic!("expected stop with a container"),
+    }
+}
+
+#[test]
+fn parse_container_subcommands() {
+    let cli = Cli::parse_from(["codesandbox", "attach", "csb-demo"]);
+    assert!(matches!(cli.command, Some(Commands::Attach { container }) if container == "csb-demo"));
+
+    let cli = Cli::parse_from(["codesandbox", "exec", "1", "--", "ls", "-la"]);
+    match cli.command {
+        Some(Commands::Exec { container, command }) => {
+            assert_eq!(container, "1");
+            assert_eq!(command, vec!["ls", "-la"]);
+        }
+        _ => panic!("expected exec"),
+    }
+    assert!(Cli::try_parse_from(["codesandbox", "exec", "1"]).is_err());
+
+    let cli = Cli::parse_from(["codesandbox", "logs", "csb-demo", "-f", "--tail", "50"]);
+    match cli.command {
+        Some(Commands::Logs {
+            container,
+            follow,
+            tail,
+        }) => {
+            assert_eq!(container, "csb-demo");
+            assert!(follow);
+            assert_eq!(tail.as_deref(), Some("50"));
+        }
+        _ => panic!("expected logs"),
+    }
+
+    assert!(matches!(
+        Cli::parse_from(["codesandbox", "start", "csb-demo"]).command,
+        Some(Commands::Start { .. })
+    ));
+    assert!(matches!(
+        Cli::parse_from(["codesandbox", "rm", "csb-demo"]).command,
+        Some(Commands::Rm { .. })
+    ));
 }
 
 #[test]
@@ -63,7 +114,7 @@ fn parse_restart_subcommand() {
     let cli = Cli::parse_from(["codesandbox", "restart"]);
     assert!(matches!(
         cli.command,
-        Some(Commands::Restart { daemon: false })
+        Some(Commands::Restart { daemon: false, .. })
     ));
 }
 
@@ -72,7 +123,7 @@ fn parse_restart_daemon_flag() {
     let cli = Cli::parse_from(["codesandbox", "restart", "-d"]);
     assert!(matches!(
         cli.command,
-        Some(Commands::Restart { daemon: true })
+        Some(Commands::Restart { daemon: true, .. })
     ));
 }
 
@@ -85,22 +136,29 @@ fn conflicting_flags_error() {
 #[test]
 fn parse_add_dir() {
     let cli = Cli::parse_from(["codesandbox", "--add_dir", "/tmp/foo"]);
-    assert_eq!(
-        cli.add_dir.as_deref(),
-        Some(std::path::Path::new("/tmp/foo"))
-    );
+    assert_eq!(cli.add_dir, vec!["/tmp/foo".to_string()]);
+
+    let cli = Cli::parse_from([
+        "codesandbox",
+        "--add_dir",
+        "../api",
+        "--add_dir",
+        "/tmp/scratch:/scratch:rw",
+    ]);
+    assert_eq!(cli.add_dir, vec!["../api", "/tmp/scratch:/scratch:rw"]);
 }
 
 #[test]
-fn default_agent_is_claude() {
+fn agent_defaults_to_configuration() {
     let cli = Cli::parse_from(["codesandbox"]);
-    assert!(matches!(cli.agent, Agent::Claude));
+    assert!(cli.agent.is_none());
 }
 
 #[test]
 fn parse_agent_option() {
     let cli = Cli::parse_from(["codesandbox", "--agent", "qwen"]);
-    assert!(matches!(cli.agent, Agent::Qwen));
+    assert_eq!(cli.agent.as_deref(), Some("qwen"));
+    assert_eq!(cli.settings_overrides()["agent"], serde_json::json!("qwen"));
 }
 
 #[test]
@@ -120,3 +178,206 @@ fn parse_worktree_option() {
     let cli = Cli::parse_from(["codesandbox", "--worktree", "feature"]);
     assert_eq!(cli.worktree.as_deref(), Some("feature"));
 }
+
+#[test]
+fn parse_runtime_option() {
+    let cli = Cli::parse_from(["codesandbox", "--runtime", "podman", "ls"]);
+    assert_eq!(cli.runtime.as_deref(), Some("podman"));
+    assert!(Cli::try_parse_from(["codesandbox", "--runtime", "lxc"]).is_err());
+}
+
+#[test]
+fn parse_config_show_subcommand() {
+    let cli = Cli::parse_from(["codesandbox", "config", "show"]);
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Config {
+            command: ConfigCommand::Show
+        })
+    ));
+}
+
+#[test]
+fn resource_limit_flags_become_overrides() {
+    let cli = Cli::parse_from([
+        "codesandbox",
+        "--cpus",
+        "2",
+        "--memory",
+        "4g",
+        "--pids-limit",
+        "512",
+    ]);
+    let overrides = cli.settings_overrides();
+    assert_eq!(
+        overrides["limits"],
+        serde_json::json!({ "cpus": 2.0, "memory": "4g", "pids_limit": 512 })
+    );
+
+    let cli = Cli::parse_from(["codesandbox"]);
+    assert!(!cli.settings_overrides().contains_key("limits"));
+}
+
+#[test]
+fn parse_image_subcommands() {
+    let cli = Cli::parse_from(["codesandbox", "image", "rebuild"]);
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Image {
+            command: ImageCommand::Rebuild
+        })
+    ));
+    let cli = Cli::parse_from(["codesandbox", "image", "prune"]);
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Image {
+            command: ImageCommand::Prune
+        })
+    ));
+}
+
+#[test]
+fn parse_config_validate_subcommand() {
+    let cli = Cli::parse_from(["codesandbox", "config", "validate"]);
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Config {
+            command: ConfigCommand::Validate
+        })
+    ));
+}
+
+#[test]
+fn network_flag_becomes_override() {
+    let cli = Cli::parse_from(["codesandbox", "--network", "allowlist"]);
+    assert_eq!(
+        cli.settings_overrides()["network"],
+        serde_json::json!({ "policy": "allowlist" })
+    );
+    assert!(Cli::try_parse_from(["codesandbox", "--network", "open"]).is_err());
+
+    let cli = Cli::parse_from(["codesandbox", "proxy", "--port", "7000"]);
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Proxy { port: Some(7000) })
+    ));
+}
+
+#[test]
+fn parse_isolated_flag_and_review_commands() {
+    let cli = Cli::parse_from(["codesandbox", "--isolated"]);
+    assert_eq!(
+        cli.settings_overrides()["isolated"],
+        serde_json::json!(true)
+    );
+
+    let cli = Cli::parse_from(["codesandbox", "diff", "--name-only"]);
+    assert!(matches!(
+        cli.command,
+        Some(Commands::Diff {
+            name_only: true,
+            container: None
+        })
+    ));
+
+    let cli = Cli::parse_from(["codesandbox", "apply", "src", "README.md", "--dry-run"]);
+    match cli.command {
+        Some(Commands::Apply {
+            paths,
+            dry_run,
+            container,
+        }) => {
+            assert_eq!(
+                paths,
+                vec![PathBuf::from("src"), PathBuf::from("README.md")]
+            );
+            assert!(dry_run);
+            assert!(container.is_none());
+        }
+        _ => panic!("expected apply"),
+    }
+}
+
+#[test]
+fn parse_output_format() {
+    let cli = Cli::parse_from(["codesandbox", "ls"]);
+    assert_eq!(cli.output_format(), OutputFormat::Table);
+
+    let cli = Cli::parse_from(["codesandbox", "ps", "--format", "json"]);
+    assert_eq!(cli.output_format(), OutputFormat::Json);
+
+    let cli = Cli::parse_from(["codesandbox", "--json", "--cleanup"]);
+    assert!(cli.cleanup);
+    assert_eq!(cli.output_format(), OutputFormat::Json);
+}
+
+#[test]
+fn parse_run_subcommand() {
+    let cli = Cli::parse_from([
+        "codesandbox",
+        "run",
+        "--agent",
+        "codex",
+        "--prompt-file",
+        "task.md",
+        "--rm",
+    ]);
+    assert_eq!(cli.agent.as_deref(), Some("codex"));
+    match cli.command {
+        Some(Commands::Run {
+            prompt,
+            prompt_file,
+            transcript,
+            rm,
+        }) => {
+            assert!(prompt.is_none());
+            assert_eq!(prompt_file, Some(PathBuf::from("task.md")));
+            assert!(transcript.is_none());
+            assert!(rm);
+        }
+        _ => panic!("expected run"),
+    }
+
+    assert!(Cli::try_parse_from(["codesandbox", "run"]).is_err());
+    assert!(Cli::try_parse_from([
+        "codesandbox",
+        "run",
+        "--prompt",
+        "fix it",
+        "--prompt-file",
+        "task.md"
+    ])
+    .is_err());
+}
+
+#[test]
+fn serve_listen_flags_become_overrides() {
+    let cli = Cli::parse_from(["codesandbox", "serve", "--bind", "0.0.0.0:8443", "--tls"]);
+    assert_eq!(
+        cli.settings_overrides()["server"],
+        serde_json::json!({ "bind": "0.0.0.0:8443", "tls": true })
+    );
+
+    let cli = Cli::parse_from([
+        "codesandbox",
+        "restart",
+        "--tls-cert",
+        "/certs/cert.pem",
+        "--tls-key",
+        "/certs/key.pem",
+    ]);
+    assert_eq!(
+        cli.settings_overrides()["server"],
+        serde_json::json!({
+            "tls": true,
+            "tls_cert": "/certs/cert.pem",
+            "tls_key": "/certs/key.pem"
+        })
+    );
+
+    assert!(Cli::try_parse_from(["codesandbox", "serve", "--tls-cert", "cert.pem"]).is_err());
+    assert!(Cli::parse_from(["codesandbox", "serve"])
+        .settings_overrides()
+        .get("server")
+        .is_none());
+}
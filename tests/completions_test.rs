@@ -0,0 +1,25 @@
+use codesandbox::cli::CompletionShell;
+use codesandbox::completions::completion_script;
+
+#[test]
+fn scripts_complete_subcommands_and_container_names() {
+    for shell in [
+        CompletionShell::Bash,
+        CompletionShell::Zsh,
+        CompletionShell::Fish,
+    ] {
+        let script = completion_script(shell);
+        assert!(script.contains("attach"), "{:?}", shell);
+        assert!(script.contains("codesandbox __containers"), "{:?}", shell);
+    }
+    let bash = completion_script(CompletionShell::Bash);
+    let subcommands = bash
+        .lines()
+        .find(|line| line.contains("compgen -W \""))
+        .unwrap();
+    assert!(subcommands.contains("logs"));
+    // Hidden helpers are not offered as subcommands
+    assert!(!subcommands.contains("__containers"));
+    assert!(bash.contains("attach|exec|start|stop|rm|logs)"));
+    assert!(bash.contains("complete -o default -F _codesandbox codesandbox"));
+}
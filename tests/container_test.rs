@@ -1,3 +1,6 @@
+#[path = "../src/agents.rs"]
+mod agents;
+
 #[path = "../src/config.rs"]
 mod config;
 
@@ -7,18 +10,79 @@ mod cli;
 #[path = "../src/settings.rs"]
 mod settings;
 
+#[path = "../src/engine.rs"]
+mod engine;
+
 #[path = "../src/language.rs"]
 mod language;
 
+#[path = "../src/toolchain.rs"]
+mod toolchain;
+
+#[path = "../src/proxy.rs"]
+mod proxy;
+
+#[path = "../src/secrets.rs"]
+mod secrets;
+
 #[path = "../src/container/mod.rs"]
 mod container;
 
-use cli::Agent;
+use agents::{Agent, AgentRegistry};
 use container::{auto_remove_old_containers, generate_container_name};
-use std::{env, fs, process::Command, sync::Mutex};
+use std::{env, fs, path::Path, process::Command};
 use tempfile::tempdir;
 
-static DOCKER_LOCK: Mutex<()> = Mutex::new(());
+/// Serializes the tests that put a fake `docker` first on PATH.
+static DOCKER_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Fake runtime that records the arguments of `run`, `exec` and `cp` in
+/// `run.log`, `exec.log` and `cp.log` next to itself and succeeds otherwise.
+const RECORDING_RUNTIME: &str = r#"#!/bin/bash
+cmd="$1"; shift
+case "$cmd" in
+  run) echo "$@" > "${0%/*}/run.log" ;;
+  exec) echo "$@" >> "${0%/*}/exec.log" ;;
+  cp) echo "$@" >> "${0%/*}/cp.log" ;;
+esac
+exit 0
+"#;
+
+/// Run `f` with `script` installed as `docker` first on PATH. `f` gets the
+/// directory holding the script, which is where scripts write their logs
+/// (`${0%/*}/<name>`) and the config home; it is removed once `f` returns.
+async fn with_fake_runtime<T>(script: &str, f: impl AsyncFnOnce(&Path) -> T) -> T {
+    let _lock = DOCKER_LOCK.lock().await;
+    let bin = tempdir().expect("temp dir");
+    let docker_path = bin.path().join("docker");
+    fs::write(&docker_path, script).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&docker_path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let original_path = env::var("PATH").unwrap_or_default();
+    env::set_var(
+        "PATH",
+        format!("{}:{}", bin.path().display(), original_path),
+    );
+    // Keep state files such as agent-versions.json out of the real config
+    env::set_var("CODESANDBOX_CONFIG_HOME", bin.path());
+    let result = f(bin.path()).await;
+    env::remove_var("CODESANDBOX_CONFIG_HOME");
+    env::set_var("PATH", original_path);
+    result
+}
+
+/// Contents of the log `name` written by a fake runtime in `bin`.
+fn read_log(bin: &Path, name: &str) -> String {
+    fs::read_to_string(bin.join(name)).unwrap_or_default()
+}
+
+fn claude() -> Agent {
+    AgentRegistry::builtin().resolve(Some("claude")).unwrap()
+}
 
 #[test]
 fn test_generate_container_name_with_git_repo() {
@@ -60,7 +124,7 @@ fn test_generate_container_name_with_git_repo() {
         .status()
         .expect("git commit");
 
-    let name = generate_container_name(&repo_path, &Agent::Claude);
+    let name = generate_container_name(&repo_path, &claude());
     let prefix = "csb-claude-my-repo--feature-test-";
     assert!(name.starts_with(prefix));
     let ts = &name[prefix.len()..];
@@ -74,7 +138,7 @@ fn test_generate_container_name_without_git_repo() {
     let dir_path = tmp.path().join("Another Repo");
     fs::create_dir(&dir_path).expect("create dir");
 
-    let name = generate_container_name(&dir_path, &Agent::Claude);
+    let name = generate_container_name(&dir_path, &claude());
     let prefix = "csb-claude-another-repo-unknown-";
     assert!(name.starts_with(prefix));
     let ts = &name[prefix.len()..];
@@ -82,14 +146,8 @@ fn test_generate_container_name_without_git_repo() {
     assert!(ts.chars().all(|c| c.is_ascii_digit()));
 }
 
-#[test]
-fn test_auto_remove_old_containers() {
-    let _lock = DOCKER_LOCK.lock().unwrap();
-    let tmp = tempdir().expect("temp dir");
-    let bin_dir = tmp.path();
-    let rm_log = bin_dir.join("rm.log");
-
-    let docker_path = bin_dir.join("docker");
+#[tokio::test]
+async fn test_auto_remove_old_containers() {
     let script = r#"#!/bin/bash
 set -e
 cmd="$1"
@@ -117,52 +175,49 @@ case "$cmd" in
     ;;
   rm)
     name="${!#}"
-    echo "$name" >> "__LOG__"
+    echo "$name" >> "${0%/*}/rm.log"
     ;;
   *)
     exit 1
     ;;
 esac
-"#
-    .replace("__LOG__", rm_log.to_str().unwrap());
-    fs::write(&docker_path, script).unwrap();
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&docker_path).unwrap().permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&docker_path, perms).unwrap();
-    }
-
-    let original_path = env::var("PATH").unwrap_or_default();
-    env::set_var("PATH", format!("{}:{}", bin_dir.display(), original_path));
-
-    auto_remove_old_containers(1).unwrap();
-
-    env::set_var("PATH", original_path);
+"#;
+    let removed = with_fake_runtime(script, async |bin| {
+        auto_remove_old_containers(1).unwrap();
+        read_log(bin, "rm.log")
+    })
+    .await;
 
-    let removed = fs::read_to_string(&rm_log).unwrap();
     assert_eq!(removed.trim(), "csb-old");
 }
 
-#[test]
-fn test_list_all_containers() {
-    let _lock = DOCKER_LOCK.lock().unwrap();
-    let tmp = tempdir().expect("temp dir");
-    let bin_dir = tmp.path();
-    let docker_path = bin_dir.join("docker");
+#[tokio::test]
+async fn test_list_all_containers() {
     let script = r#"#!/bin/bash
 cmd="$1"
 shift
 case "$cmd" in
   ps)
-    echo "csb-claude-proj-main-123456"
-    echo "unrelated"
+    [[ " $* " == *" --filter label=codesandbox.project "* ]] || exit 1
+    echo "csb-claude-my-proj-feature-x-123456"
     ;;
   inspect)
     name="${!#}"
-    if [ "$name" = "csb-claude-proj-main-123456" ]; then
-      echo "/projects/proj"
+    if [ "$name" = "csb-claude-my-proj-feature-x-123456" ]; then
+      cat <<'JSON'
+[{"Created": "2024-01-01T00:00:00Z",
+  "State": {"Status": "running"},
+  "Config": {"Labels": {
+    "codesandbox.project": "/projects/my-proj",
+    "codesandbox.agent": "claude",
+    "codesandbox.branch": "feature-x",
+    "codesandbox.worktree": "false",
+    "codesandbox.isolated": "true",
+    "codesandbox.version": "0.1.0",
+    "codesandbox.limits.cpus": "2",
+    "codesandbox.limits.memory": "4g"
+  }}}]
+JSON
     fi
     ;;
   *)
@@ -170,71 +225,90 @@ case "$cmd" in
     ;;
 esac
 "#;
-    fs::write(&docker_path, script).unwrap();
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&docker_path).unwrap().permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&docker_path, perms).unwrap();
-    }
+    let containers =
+        with_fake_runtime(script, async |_| container::list_all_containers().unwrap()).await;
 
-    let original_path = env::var("PATH").unwrap_or_default();
-    env::set_var("PATH", format!("{}:{}", bin_dir.display(), original_path));
-
-    let containers = container::list_all_containers().unwrap();
+    assert_eq!(containers.len(), 1);
+    let info = &containers[0];
+    assert_eq!(info.project_name(), "my-proj");
+    assert_eq!(info.name, "csb-claude-my-proj-feature-x-123456");
+    assert_eq!(info.project_path.as_deref(), Some("/projects/my-proj"));
+    assert_eq!(info.agent.as_deref(), Some("claude"));
+    assert_eq!(info.branch.as_deref(), Some("feature-x"));
+    assert_eq!(info.status.as_deref(), Some("running"));
+    assert_eq!(info.created.as_deref(), Some("2024-01-01T00:00:00Z"));
+    assert!(!info.worktree);
+    assert!(info.isolated);
+    assert_eq!(info.limits.cpus, Some(2.0));
+    assert_eq!(info.limits.memory.as_deref(), Some("4g"));
+    assert_eq!(info.limits.pids_limit, None);
+    assert_eq!(info.limits.summary(), "cpus=2 memory=4g");
+}
 
-    env::set_var("PATH", original_path);
+#[tokio::test]
+async fn test_cleanup_filters_on_project_label() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("my-proj");
+    fs::create_dir(&project_dir).unwrap();
+    let script = r#"#!/bin/bash
+cmd="$1"
+shift
+case "$cmd" in
+  ps)
+    echo "$@" > "${0%/*}/ps.log"
+    echo "csb-claude-my-proj-main-123456"
+    ;;
+  rm)
+    echo "${!#}" >> "${0%/*}/rm.log"
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#;
+    let (ps_args, removed) = with_fake_runtime(script, async |bin| {
+        container::cleanup_containers(&project_dir).unwrap();
+        (read_log(bin, "ps.log"), read_log(bin, "rm.log"))
+    })
+    .await;
 
-    assert_eq!(containers.len(), 1);
-    assert_eq!(containers[0].0, "proj");
-    assert_eq!(containers[0].1, "csb-claude-proj-main-123456");
-    assert_eq!(containers[0].2.as_deref(), Some("/projects/proj"));
+    let canonical = fs::canonicalize(&project_dir).unwrap();
+    assert!(ps_args.contains(&format!(
+        "--filter label=codesandbox.project={}",
+        canonical.display()
+    )));
+    assert_eq!(removed.trim(), "csb-claude-my-proj-main-123456");
 }
 
 #[tokio::test]
 async fn create_container_masks_only_existing_env_files() {
-    let _lock = DOCKER_LOCK.lock().unwrap();
     let tmp = tempdir().expect("temp dir");
     let project_dir = tmp.path().join("proj");
     fs::create_dir(&project_dir).expect("create project dir");
     fs::write(project_dir.join(".env"), "SECRET=1").expect("write env");
 
-    let bin_dir = tmp.path().join("bin");
-    fs::create_dir(&bin_dir).unwrap();
-    let run_log = tmp.path().join("run.log");
-    let script = format!(
-        "#!/bin/bash\ncmd=\"$1\"; shift\ncase \"$cmd\" in\n  build) exit 0 ;;\n  run) echo \"$@\" > \"{}\"; exit 0 ;;\n  exec) exit 0 ;;\n  *) exit 0 ;;\nesac\n",
-        run_log.display()
-    );
-    let docker_path = bin_dir.join("docker");
-    fs::write(&docker_path, script).unwrap();
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&docker_path).unwrap().permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&docker_path, perms).unwrap();
-    }
-
-    let original_path = env::var("PATH").unwrap_or_default();
-    env::set_var("PATH", format!("{}:{}", bin_dir.display(), original_path));
-
-    container::create_container(
-        "test",
-        &project_dir,
-        None,
-        &Agent::Claude,
-        None,
-        false,
-        false,
-    )
+    let run_args = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings::Settings::default(),
+            false,
+            false,
+        )
         .await
         .unwrap();
+        read_log(bin, "run.log")
+    })
+    .await;
 
-    env::set_var("PATH", original_path);
-
-    let run_args = fs::read_to_string(&run_log).unwrap();
+    let canonical = fs::canonicalize(&project_dir).unwrap();
+    assert!(run_args.contains(&format!(
+        "--label codesandbox.project={}",
+        canonical.display()
+    )));
+    assert!(run_args.contains("--label codesandbox.agent=claude"));
     assert!(run_args.contains(&project_dir.join(".env").display().to_string()));
     assert!(!run_args.contains(&project_dir.join(".env.local").display().to_string()));
     assert!(!run_args.contains(
@@ -260,74 +334,830 @@ async fn create_container_masks_only_existing_env_files() {
 
 #[tokio::test]
 async fn create_container_isolates_node_modules_and_copies_from_host() {
-    let _lock = DOCKER_LOCK.lock().unwrap();
     let tmp = tempdir().expect("temp dir");
     let project_dir = tmp.path().join("proj-node");
     fs::create_dir(&project_dir).expect("create project dir");
     // Minimal Node project
-    fs::write(project_dir.join("package.json"), "{\n  \"name\": \"test\"\n}\n").unwrap();
+    fs::write(
+        project_dir.join("package.json"),
+        "{\n  \"name\": \"test\"\n}\n",
+    )
+    .unwrap();
     // Create a host node_modules with a file to verify copy
     let nm_dir = project_dir.join("node_modules");
     fs::create_dir_all(nm_dir.join(".keep")).unwrap();
 
-    let bin_dir = tmp.path().join("bin");
-    fs::create_dir(&bin_dir).unwrap();
-    let run_log = tmp.path().join("run_node.log");
-    let exec_log = tmp.path().join("exec_node.log");
-    let cp_log = tmp.path().join("cp_node.log");
+    let (run_args, cp_args) = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test-node",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings::Settings::default(),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        (read_log(bin, "run.log"), read_log(bin, "cp.log"))
+    })
+    .await;
+
+    let node_modules_path = project_dir.join("node_modules");
+    // Ensure the node_modules anonymous volume is present in run args
+    assert!(
+        run_args.contains(&format!(" {} ", node_modules_path.display()))
+            || run_args.ends_with(&format!(" {}", node_modules_path.display()))
+            || run_args.starts_with(&format!("{} ", node_modules_path.display()))
+    );
+
+    // Ensure docker cp was invoked to copy node_modules
+    let expected_dest = format!("test-node:{}", project_dir.join("node_modules").display());
+    assert!(cp_args.contains(&expected_dest));
+}
+
+#[tokio::test]
+async fn create_container_uses_base_image_and_fragment_with_hashed_tag() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-image");
+    fs::create_dir(&project_dir).expect("create project dir");
+    let fragment = tmp.path().join("extra.Dockerfile");
+    fs::write(&fragment, "RUN pip install pandas\n").unwrap();
+
     let script = r#"#!/bin/bash
-set -e
 cmd="$1"; shift
 case "$cmd" in
-  build) exit 0 ;;
-  run) echo "$@" > "__RUN__"; exit 0 ;;
-  exec) echo "$@" >> "__EXEC__"; exit 0 ;;
-  cp) echo "$@" >> "__CP__"; exit 0 ;;
+  build)
+    echo "$@" > "${0%/*}/build.log"
+    while [ $# -gt 0 ]; do
+      if [ "$1" = "-f" ]; then cat "$2" > "${0%/*}/Dockerfile"; fi
+      shift
+    done
+    exit 0 ;;
+  run) echo "$@" > "${0%/*}/run.log"; exit 0 ;;
   *) exit 0 ;;
 esac
-"#
-    .replace("__RUN__", run_log.to_str().unwrap())
-    .replace("__EXEC__", exec_log.to_str().unwrap())
-    .replace("__CP__", cp_log.to_str().unwrap());
-    let docker_path = bin_dir.join("docker");
-    fs::write(&docker_path, script).unwrap();
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&docker_path).unwrap().permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&docker_path, perms).unwrap();
+"#;
+    let settings = settings::Settings {
+        base_image: Some("python:3.12-bookworm".to_string()),
+        dockerfile_fragment: Some(fragment.display().to_string()),
+        ..Default::default()
+    };
+    let (dockerfile, build_args, run_args) = with_fake_runtime(script, async |bin| {
+        container::create_container(
+            "test-image",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        (
+            read_log(bin, "Dockerfile"),
+            read_log(bin, "build.log"),
+            read_log(bin, "run.log"),
+        )
+    })
+    .await;
+
+    assert!(dockerfile.starts_with("FROM python:3.12-bookworm\n"));
+    let fragment_at = dockerfile.find("RUN pip install pandas").unwrap();
+    assert!(fragment_at < dockerfile.find("CMD [\"/bin/bash\"]").unwrap());
+
+    let tag = build_args
+        .split_whitespace()
+        .skip_while(|arg| *arg != "-t")
+        .nth(1)
+        .unwrap()
+        .to_string();
+    assert!(tag.starts_with("codesandbox-image:"));
+    assert_ne!(tag, "codesandbox-image:latest");
+    assert!(run_args.contains(&format!("{} /bin/bash", tag)));
+}
+
+#[tokio::test]
+async fn create_container_reuses_image_built_from_same_dockerfile() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-cache");
+    fs::create_dir(&project_dir).expect("create project dir");
+
+    // `build` records the hash label; `image inspect` reports it back
+    let script = r#"#!/bin/bash
+cmd="$1"; shift
+case "$cmd" in
+  build)
+    echo "$@" >> "${0%/*}/builds.log"
+    while [ $# -gt 0 ]; do
+      if [ "$1" = "--label" ]; then echo "${2#*=}" > "${0%/*}/label"; fi
+      shift
+    done
+    exit 0 ;;
+  image) [ -f "${0%/*}/label" ] && cat "${0%/*}/label"; exit 0 ;;
+  *) exit 0 ;;
+esac
+"#;
+    let builds = with_fake_runtime(script, async |bin| {
+        let settings = settings::Settings::default();
+        for name in ["first", "second"] {
+            container::create_container(
+                name,
+                &project_dir,
+                &[],
+                &claude(),
+                &settings,
+                false,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        let changed = settings::Settings {
+            base_image: Some("ubuntu:24.04".to_string()),
+            ..Default::default()
+        };
+        container::create_container(
+            "third",
+            &project_dir,
+            &[],
+            &claude(),
+            &changed,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        read_log(bin, "builds.log")
+    })
+    .await;
+
+    assert_eq!(builds.lines().count(), 2);
+    assert!(builds
+        .lines()
+        .all(|line| line.contains("--label codesandbox.dockerfile-hash=")));
+}
+
+#[tokio::test]
+async fn test_prune_images_keeps_images_in_use() {
+    let script = r#"#!/bin/bash
+case "$1" in
+  images)
+    echo "codesandbox-image:aaaaaaaaaaaa"
+    echo "codesandbox-image:bbbbbbbbbbbb"
+    echo "codesandbox-image:<none>"
+    ;;
+  ps)
+    echo "ubuntu:22.04"
+    echo "codesandbox-image:bbbbbbbbbbbb"
+    ;;
+  rmi)
+    [ "$2" = "codesandbox-image:aaaaaaaaaaaa" ] || exit 1
+    ;;
+esac
+"#;
+    let pruned = with_fake_runtime(script, async |_| container::prune_images().unwrap()).await;
+
+    assert_eq!(pruned.removed, ["codesandbox-image:aaaaaaaaaaaa"]);
+    assert_eq!(pruned.kept, ["codesandbox-image:bbbbbbbbbbbb"]);
+    assert!(pruned.failed.is_empty());
+}
+
+#[tokio::test]
+async fn test_prune_images_reports_failed_removals() {
+    let script = r#"#!/bin/bash
+case "$1" in
+  images)
+    echo "codesandbox-image:aaaaaaaaaaaa"
+    echo "codesandbox-image:bbbbbbbbbbbb"
+    ;;
+  rmi)
+    [ "$2" = "codesandbox-image:bbbbbbbbbbbb" ] && exit 0
+    echo "Error response from daemon: permission denied" >&2
+    exit 1
+    ;;
+esac
+"#;
+    let pruned = with_fake_runtime(script, async |_| container::prune_images().unwrap()).await;
+
+    // The failure does not stop the images after it from being removed
+    assert_eq!(pruned.removed, ["codesandbox-image:bbbbbbbbbbbb"]);
+    assert_eq!(pruned.failed.len(), 1);
+    let (tag, error) = &pruned.failed[0];
+    assert_eq!(tag, "codesandbox-image:aaaaaaaaaaaa");
+    assert!(error.contains("permission denied"), "{error}");
+}
+
+#[tokio::test]
+async fn create_container_applies_resource_limits() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-limits");
+    fs::create_dir(&project_dir).expect("create project dir");
+
+    let settings = settings::Settings {
+        limits: settings::ResourceLimits {
+            cpus: Some(1.5),
+            memory: Some("2g".to_string()),
+            pids_limit: Some(256),
+            storage: Some("10G".to_string()),
+        },
+        ..Default::default()
+    };
+    let run_args = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test-limits",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        read_log(bin, "run.log")
+    })
+    .await;
+
+    assert!(run_args.contains("--cpus 1.5 --memory 2g --pids-limit 256 --storage-opt size=10G"));
+    assert!(run_args.contains("--label codesandbox.limits.cpus=1.5"));
+    assert!(run_args.contains("--label codesandbox.limits.memory=2g"));
+    assert!(run_args.contains("--label codesandbox.limits.pids=256"));
+    assert!(run_args.contains("--label codesandbox.limits.storage=10G"));
+}
+
+#[tokio::test]
+async fn create_container_without_network() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-offline");
+    fs::create_dir(&project_dir).expect("create project dir");
+    fs::write(project_dir.join("requirements.txt"), "requests\n").unwrap();
+
+    let settings = settings::Settings {
+        network: settings::NetworkSettings {
+            policy: settings::NetworkPolicy::None,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (run_args, execs) = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test-offline",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        (read_log(bin, "run.log"), read_log(bin, "exec.log"))
+    })
+    .await;
+
+    assert!(run_args.contains("--network none"));
+    assert!(run_args.contains("--label codesandbox.network=none"));
+    assert!(!run_args.contains("codesandbox.network.allow"));
+    // No toolchain installs are attempted without a network
+    assert!(!execs.contains("pip"), "{execs}");
+}
+
+#[tokio::test]
+async fn create_container_isolated_mounts_project_read_only() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-isolated");
+    fs::create_dir(&project_dir).expect("create project dir");
+    fs::write(project_dir.join(".env"), "SECRET=1\n").unwrap();
+
+    let settings = settings::Settings {
+        isolated: Some(true),
+        ..Default::default()
+    };
+    let (run_args, execs) = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test-isolated",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        (read_log(bin, "run.log"), read_log(bin, "exec.log"))
+    })
+    .await;
+
+    let dir = project_dir.display().to_string();
+    assert!(run_args.contains(&format!("-v {}:/codesandbox/host:ro", dir)));
+    assert!(run_args.contains(&format!("-v {} ", dir)));
+    assert!(!run_args.contains(&format!("{}:{}", dir, dir)));
+    assert!(run_args.contains(":/codesandbox/host/.env:ro"));
+    assert!(run_args.contains("--label codesandbox.isolated=true"));
+    assert!(execs.contains("tar -C /codesandbox/host"), "{execs}");
+}
+
+#[test]
+fn parse_isolated_workspace_changes() {
+    let hash = |c: char| c.to_string().repeat(64);
+    let host = [
+        "d\0755\0\0docs\0d\0755\0\0src\0l\0777\0target-a\0link\0",
+        "f\0644\0\0src/main.rs\0f\0644\0\0old.txt\0f\0644\0\0a and b: c.txt\0",
+        "f\0644\0\0docs/readme\0f\0644\0\0run.sh\0\0",
+        &format!("{}  ./src/main.rs\0", hash('a')),
+        &format!("{}  ./old.txt\0", hash('b')),
+        &format!("{}  ./a and b: c.txt\0", hash('c')),
+        &format!("{}  ./docs/readme\0", hash('d')),
+        &format!("{}  ./run.sh\0", hash('e')),
+    ]
+    .concat();
+    let workspace = [
+        "d\0755\0\0src\0d\0700\0\0new dir\0l\0777\0target-b\0link\0",
+        "f\0644\0\0src/main.rs\0f\0644\0\0a and b: c.txt\0f\0644\0\0docs\0",
+        "f\0644\0\0new dir/x\0f\0644\0\0src/new.rs\0f\0755\0\0run.sh\0\0",
+        &format!("{}  ./src/main.rs\0", hash('e')),
+        &format!("{}  ./a and b: c.txt\0", hash('c')),
+        &format!("{}  ./docs\0", hash('d')),
+        &format!("{}  ./new dir/x\0", hash('f')),
+        &format!("{}  ./src/new.rs\0", hash('f')),
+        &format!("{}  ./run.sh\0", hash('e')),
+    ]
+    .concat();
+    let changes = container::parse_changes(host.as_bytes(), workspace.as_bytes()).unwrap();
+    let listed: Vec<String> = changes.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        listed,
+        vec![
+            "M docs",
+            "M link",
+            "A new dir",
+            "D old.txt",
+            "M run.sh",
+            "M src/main.rs",
+            "A src/new.rs"
+        ]
+    );
+    assert_eq!(changes[2].kind, container::ChangeKind::Added);
+
+    assert!(changes[5].matches(std::path::Path::new("src")));
+    assert!(changes[5].matches(std::path::Path::new("./src/main.rs")));
+    assert!(changes[5].matches(std::path::Path::new(".")));
+    assert!(!changes[5].matches(std::path::Path::new("src/main")));
+
+    assert!(container::parse_changes(b"d\0755\0\0src", b"\0").is_err());
+}
+
+#[test]
+fn apply_change_stays_inside_the_project() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj");
+    let outside = tmp.path().join("outside");
+    fs::create_dir(&project_dir).unwrap();
+    fs::create_dir(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, project_dir.join("escape")).unwrap();
+
+    for path in ["escape/file", "../outside/file"] {
+        let change = container::Change {
+            kind: container::ChangeKind::Added,
+            path: path.into(),
+        };
+        let error = container::apply_change("csb-isolated", &project_dir, &change).unwrap_err();
+        assert!(error.to_string().contains("Refusing to apply"), "{error}");
     }
+    assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+}
 
-    let original_path = env::var("PATH").unwrap_or_default();
-    env::set_var("PATH", format!("{}:{}", bin_dir.display(), original_path));
-
-    container::create_container(
-        "test-node",
-        &project_dir,
-        None,
-        &Agent::Claude,
-        None,
-        false,
-        false,
-    )
-    .await
-    .unwrap();
+#[tokio::test]
+async fn create_container_mounts_extra_directories() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-mounts");
+    fs::create_dir(&project_dir).expect("create project dir");
+    let shared = fs::canonicalize(tmp.path()).unwrap().join("shared");
+    fs::create_dir(&shared).unwrap();
 
-    env::set_var("PATH", original_path);
+    let scratch = settings::MountSpec::parse("/tmp:/scratch:rw").unwrap();
+    let settings = settings::Settings {
+        mounts: vec![
+            shared.display().to_string(),
+            "/does/not/exist:rw".to_string(),
+        ],
+        ..Default::default()
+    };
+    let run_args = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test-mounts",
+            &project_dir,
+            &[scratch],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        read_log(bin, "run.log")
+    })
+    .await;
 
-    let run_args = fs::read_to_string(&run_log).unwrap();
-    let node_modules_path = project_dir.join("node_modules");
-    // Ensure the node_modules anonymous volume is present in run args
-    assert!(run_args.contains(&format!(" {} ", node_modules_path.display()))
-        || run_args.ends_with(&format!(" {}", node_modules_path.display()))
-        || run_args.starts_with(&format!("{} ", node_modules_path.display())));
+    let shared = shared.display();
+    assert!(run_args.contains("-v /tmp:/scratch "));
+    assert!(run_args.contains(&format!("-v {shared}:{shared}:ro")));
+    assert!(!run_args.contains("/does/not/exist"));
+    assert!(run_args.contains(&format!(
+        "--label codesandbox.mounts=/tmp:/scratch:rw;{shared}:ro"
+    )));
+}
 
-    // Ensure docker cp was invoked to copy node_modules
-    let cp_args = fs::read_to_string(&cp_log).unwrap();
-    let expected_dest = format!(
-        "test-node:{}",
-        project_dir.join("node_modules").display()
+#[tokio::test]
+async fn create_container_injects_secrets_without_exposing_values() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-secrets");
+    fs::create_dir(&project_dir).expect("create project dir");
+
+    let script = r#"#!/bin/bash
+cmd="$1"; shift
+case "$cmd" in
+  run) echo "$@ ENV=$CSB_API_KEY" > "${0%/*}/run.log"; exit 0 ;;
+  exec) if [ "$1" = "-i" ]; then echo "$@ STDIN=$(cat)" >> "${0%/*}/exec.log"; fi; exit 0 ;;
+  *) exit 0 ;;
+esac
+"#;
+    let mut settings = settings::Settings::default();
+    settings.secrets.insert(
+        "CSB_API_KEY".to_string(),
+        settings::SecretSettings {
+            command: Some("echo sk-env-value".to_string()),
+            ..Default::default()
+        },
     );
-    assert!(cp_args.contains(&expected_dest));
+    settings.secrets.insert(
+        "DEPLOY_TOKEN".to_string(),
+        settings::SecretSettings {
+            command: Some("echo file-value".to_string()),
+            inject: settings::SecretTarget::File,
+            ..Default::default()
+        },
+    );
+    let (run_log, execs) = with_fake_runtime(script, async |bin| {
+        container::create_container(
+            "test-secrets",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        (read_log(bin, "run.log"), read_log(bin, "exec.log"))
+    })
+    .await;
+
+    let (run_args, run_env) = run_log.split_once(" ENV=").unwrap();
+    assert!(run_args.contains("-e CSB_API_KEY "));
+    assert!(run_args.contains("--tmpfs /run/secrets:"));
+    assert!(!run_args.contains("sk-env-value"));
+    assert!(!run_args.contains("file-value"));
+    assert_eq!(run_env.trim(), "sk-env-value");
+
+    assert!(execs.contains("/run/secrets/DEPLOY_TOKEN"), "{execs}");
+    assert!(execs.contains("STDIN=file-value"), "{execs}");
+    assert!(!execs.contains("sk-env-value"), "{execs}");
+}
+
+#[test]
+fn env_file_patterns_match_throughout_the_tree() {
+    let tmp = tempdir().expect("temp dir");
+    let root = tmp.path();
+    for file in [
+        ".env",
+        "apps/web/.env.local",
+        "apps/web/src/main.ts",
+        "config/secrets.yml",
+        "config/app.yml",
+        "deploy/keys/id_rsa",
+        "docs/config/secrets.yml",
+        "node_modules/pkg/.env",
+        "services/api/certs/server.pem",
+        "public/.env",
+    ] {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x").unwrap();
+    }
+
+    let patterns: Vec<String> = [
+        ".env",
+        ".env.*",
+        "!public/.env",
+        "/config/secrets.yml",
+        "keys/",
+        "**/certs/*.pem",
+    ]
+    .iter()
+    .map(ToString::to_string)
+    .collect();
+    let masks = container::MaskPatterns::new(root, &patterns).unwrap();
+    let found = masks.find(root).unwrap();
+    let listed: Vec<(String, bool)> = found
+        .iter()
+        .map(|m| (m.path.display().to_string(), m.is_dir))
+        .collect();
+    assert_eq!(
+        listed,
+        vec![
+            (".env".to_string(), false),
+            ("apps/web/.env.local".to_string(), false),
+            ("config/secrets.yml".to_string(), false),
+            ("deploy/keys".to_string(), true),
+            ("services/api/certs/server.pem".to_string(), false),
+        ]
+    );
+
+    assert!(masks.matches(std::path::Path::new("deploy/keys/id_rsa"), false));
+    assert!(masks.matches(std::path::Path::new("a/b/.env"), false));
+    assert!(!masks.matches(std::path::Path::new("public/.env"), false));
+    assert!(!masks.matches(std::path::Path::new("docs/config/secrets.yml"), false));
+}
+
+#[tokio::test]
+async fn create_container_masks_nested_env_files_and_directories() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-monorepo");
+    fs::create_dir_all(project_dir.join("apps/web")).unwrap();
+    fs::create_dir_all(project_dir.join("secrets")).unwrap();
+    fs::write(project_dir.join("apps/web/.env.local"), "SECRET=1").unwrap();
+    fs::write(project_dir.join("secrets/key"), "SECRET=1").unwrap();
+
+    let settings = settings::Settings {
+        env_files: vec![".env.local".to_string(), "secrets/".to_string()],
+        ..Default::default()
+    };
+    let run_args = with_fake_runtime(RECORDING_RUNTIME, async |bin| {
+        container::create_container(
+            "test-monorepo-env",
+            &project_dir,
+            &[],
+            &claude(),
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        read_log(bin, "run.log")
+    })
+    .await;
+
+    let web_env = project_dir.join("apps/web/.env.local");
+    let secrets = project_dir.join("secrets");
+    assert!(run_args.contains(&format!("/file:{}:ro", web_env.display())));
+    assert!(run_args.contains(&format!("/dir:{}:ro", secrets.display())));
+    assert!(!run_args.contains("secrets/key"));
+}
+
+#[tokio::test]
+async fn resolve_container_by_name_or_ls_number() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj");
+    fs::create_dir(&project_dir).unwrap();
+    let script = r#"#!/bin/bash
+cmd="$1"
+shift
+case "$cmd" in
+  ps)
+    echo "csb-new"
+    echo "csb-old"
+    ;;
+  inspect)
+    case "${!#}" in
+      csb-new|csb-old)
+        echo '[{"Config":{"Labels":{"codesandbox.project":"/work/proj"}}}]'
+        ;;
+      *)
+        exit 1
+        ;;
+    esac
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#;
+    let (by_name, by_number, out_of_range, unknown) = with_fake_runtime(script, async |_| {
+        (
+            container::resolve_container("csb-old", &project_dir),
+            container::resolve_container("1", &project_dir),
+            container::resolve_container("3", &project_dir),
+            container::resolve_container("csb-missing", &project_dir),
+        )
+    })
+    .await;
+
+    assert_eq!(by_name.unwrap().name, "csb-old");
+    assert_eq!(by_number.unwrap().name, "csb-new");
+    assert!(out_of_range
+        .unwrap_err()
+        .to_string()
+        .contains("No container number 3"));
+    assert!(unknown.unwrap_err().to_string().contains("csb-missing"));
+}
+
+#[tokio::test]
+async fn run_headless_records_transcript_exit_code_and_diff() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj");
+    fs::create_dir(&project_dir).unwrap();
+    for args in [
+        vec!["init", "-q"],
+        vec!["config", "user.email", "test@example.com"],
+        vec!["config", "user.name", "Test User"],
+    ] {
+        Command::new("git")
+            .args(&args)
+            .current_dir(&project_dir)
+            .status()
+            .unwrap();
+    }
+    fs::write(project_dir.join("tracked.txt"), "before\n").unwrap();
+    fs::write(project_dir.join("edited.txt"), "committed\n").unwrap();
+    Command::new("git")
+        .args(["add", "."])
+        .current_dir(&project_dir)
+        .status()
+        .unwrap();
+    Command::new("git")
+        .args(["commit", "-qm", "init"])
+        .current_dir(&project_dir)
+        .status()
+        .unwrap();
+    // Work in progress from before the run is not the agent's
+    fs::write(project_dir.join("edited.txt"), "uncommitted\n").unwrap();
+    fs::write(project_dir.join("untracked.txt"), "mine\n").unwrap();
+
+    // The fake agent echoes its prompt and edits the project like an agent would
+    let script = r#"#!/bin/bash
+cmd="$1"
+shift
+case "$cmd" in
+  exec)
+    echo "$@" > "${0%/*}/exec.log"
+    echo "agent saw: $(cat)"
+    echo "after" > "__PROJECT__/tracked.txt"
+    echo "new" > "__PROJECT__/added.txt"
+    exit 3
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#
+    .replace("__PROJECT__", project_dir.to_str().unwrap());
+    let transcript = tmp.path().join("runs").join("run.log");
+    let settings = settings::Settings::default();
+    let (run, exec_args) = with_fake_runtime(&script, async |bin| {
+        let run = container::run_headless(
+            "csb-run",
+            &project_dir,
+            &claude(),
+            &settings,
+            "fix the bug",
+            &transcript,
+        );
+        (run, read_log(bin, "exec.log"))
+    })
+    .await;
+
+    let run = run.unwrap();
+    assert_eq!(run.exit_code, 3);
+    assert_eq!(
+        fs::read_to_string(&transcript).unwrap(),
+        "agent saw: fix the bug\n"
+    );
+    assert!(exec_args.starts_with("-i csb-run bash -c CODESANDBOX_PROMPT="));
+    assert!(exec_args.contains("-p \"$CODESANDBOX_PROMPT\""));
+    assert!(run.diff.contains("-before\n+after"));
+    assert!(run.diff.contains("+++ b/added.txt"));
+    assert!(!run.diff.contains("edited.txt"), "{}", run.diff);
+    assert!(!run.diff.contains("untracked.txt"), "{}", run.diff);
+}
+
+#[tokio::test]
+async fn create_container_installs_and_mounts_configured_agents() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-agents");
+    fs::create_dir_all(project_dir.join(".aider")).unwrap();
+
+    let script = r#"#!/bin/bash
+cmd="$1"; shift
+case "$cmd" in
+  build)
+    while [ $# -gt 0 ]; do
+      if [ "$1" = "-f" ]; then cat "$2" > "${0%/*}/Dockerfile"; fi
+      shift
+    done
+    exit 0 ;;
+  run) echo "$@" > "${0%/*}/run.log"; exit 0 ;;
+  *) exit 0 ;;
+esac
+"#;
+    let mut settings = settings::Settings::default();
+    settings.agents.insert(
+        "aider".to_string(),
+        settings::AgentSettings {
+            install: Some("pip install aider-chat".to_string()),
+            config_dirs: Some(vec!["aider".to_string()]),
+            ..Default::default()
+        },
+    );
+    let aider = AgentRegistry::from_settings(&settings)
+        .unwrap()
+        .resolve(Some("aider"))
+        .unwrap();
+    let (dockerfile, run_args) = with_fake_runtime(script, async |bin| {
+        container::create_container(
+            "test-agents",
+            &project_dir,
+            &[],
+            &aider,
+            &settings,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        (read_log(bin, "Dockerfile"), read_log(bin, "run.log"))
+    })
+    .await;
+
+    assert!(dockerfile.contains("RUN npm install -g @anthropic-ai/claude-code"));
+    assert!(dockerfile.contains("RUN pip install aider-chat\n"));
+    assert!(run_args.contains("--label codesandbox.agent=aider"));
+    assert!(run_args.contains(&format!("{}:/home/", project_dir.join(".aider").display())));
+}
+
+#[tokio::test]
+async fn create_container_pins_npm_agents_to_recorded_versions() {
+    let tmp = tempdir().expect("temp dir");
+    let project_dir = tmp.path().join("proj-pinned");
+    fs::create_dir(&project_dir).unwrap();
+
+    let script = r#"#!/bin/bash
+cmd="$1"; shift
+case "$cmd" in
+  build)
+    while [ $# -gt 0 ]; do
+      if [ "$1" = "-t" ]; then echo "$2" >> "${0%/*}/tags.log"; fi
+      if [ "$1" = "-f" ]; then cat "$2" > "${0%/*}/Dockerfile"; fi
+      shift
+    done
+    exit 0 ;;
+  *) exit 0 ;;
+esac
+"#;
+    let mut settings = settings::Settings::default();
+    // Not a package at all; used as written
+    settings.agents.insert(
+        "odd".to_string(),
+        settings::AgentSettings {
+            install: Some("npm install -g @latest".to_string()),
+            ..Default::default()
+        },
+    );
+    let (dockerfile, tags) = with_fake_runtime(script, async |bin| {
+        for claude_version in ["1.2.3", "1.2.4"] {
+            let versions = serde_json::json!({
+                "@anthropic-ai/claude-code": claude_version,
+                "@google/gemini-cli": "0.5.0",
+                "@openai/codex": "0.30.0",
+                "@qwen-code/qwen-code": "0.1.0",
+            });
+            fs::write(bin.join("agent-versions.json"), versions.to_string()).unwrap();
+            container::create_container(
+                "test-pinned",
+                &project_dir,
+                &[],
+                &claude(),
+                &settings,
+                false,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        (read_log(bin, "Dockerfile"), read_log(bin, "tags.log"))
+    })
+    .await;
+
+    assert!(dockerfile.contains("RUN npm install -g @anthropic-ai/claude-code@1.2.4\n"));
+    assert!(dockerfile.contains("RUN npm install -g @qwen-code/qwen-code@0.1.0\n"));
+    assert!(dockerfile.contains("RUN curl https://cursor.com/install -fsS | bash\n"));
+    assert!(dockerfile.contains("RUN npm install -g @latest\n"));
+    let tags: Vec<&str> = tags.lines().collect();
+    assert_eq!(tags.len(), 2);
+    assert_ne!(tags[0], tags[1]);
 }
@@ -0,0 +1,48 @@
+use codesandbox::engine::{runtime_by_name, ContainerRuntime, Docker, Podman};
+
+#[test]
+fn known_runtimes_resolve_by_name() {
+    assert_eq!(runtime_by_name("docker").unwrap().binary(), "docker");
+    assert_eq!(runtime_by_name("Podman").unwrap().binary(), "podman");
+    assert_eq!(runtime_by_name("nerdctl").unwrap().binary(), "nerdctl");
+    assert!(runtime_by_name("lxc").is_none());
+}
+
+#[test]
+fn podman_keeps_host_user_namespace() {
+    assert_eq!(Podman.run_args(), &["--userns=keep-id"]);
+    assert!(Docker.run_args().is_empty());
+}
+
+#[test]
+fn network_gateway_uses_each_runtime_inspect_format() {
+    let tmp = tempfile::tempdir().unwrap();
+    // Each fake answers only the template its real counterpart understands
+    for (binary, field, gateway) in [
+        ("docker", ".IPAM.Config", "172.18.0.1"),
+        ("podman", ".Subnets", "10.89.0.1"),
+    ] {
+        let script = format!(
+            "#!/bin/bash\n[[ \"$*\" == *\"{{{{range {field}}}}}\"* ]] || exit 1\necho \"{gateway} \"\n"
+        );
+        let path = tmp.path().join(binary);
+        std::fs::write(&path, script).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+    let original_path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var(
+        "PATH",
+        format!("{}:{}", tmp.path().display(), original_path),
+    );
+
+    let docker = Docker.network_gateway("codesandbox-egress");
+    let podman = Podman.network_gateway("codesandbox-egress");
+
+    std::env::set_var("PATH", original_path);
+    assert_eq!(docker, Some("172.18.0.1".parse().unwrap()));
+    assert_eq!(podman, Some("10.89.0.1".parse().unwrap()));
+}
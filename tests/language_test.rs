@@ -1,8 +1,15 @@
+#[path = "../src/engine.rs"]
+mod engine;
+
 #[path = "../src/language.rs"]
 mod language;
 
-use language::{detect_project_languages, ProjectLanguage};
+use language::{
+    detect_project_languages, node_package_dirs, scan_project_languages, DetectedLanguage,
+    ProjectLanguage, ScanOptions,
+};
 use std::fs;
+use std::path::PathBuf;
 use tempfile::tempdir;
 
 #[test]
@@ -15,3 +22,142 @@ fn detect_languages() {
     assert!(langs.contains(&ProjectLanguage::NodeJs));
 }
 
+#[test]
+fn detect_jvm_dotnet_and_other_languages() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("build.gradle.kts"), "").unwrap();
+    fs::write(tmp.path().join("Api.csproj"), "").unwrap();
+    fs::write(tmp.path().join("mix.exs"), "").unwrap();
+    fs::write(tmp.path().join("deno.json"), "{}").unwrap();
+    fs::write(tmp.path().join("bun.lockb"), "").unwrap();
+    fs::write(tmp.path().join("CMakeLists.txt"), "").unwrap();
+    let langs = detect_project_languages(tmp.path());
+    assert_eq!(
+        langs,
+        vec![
+            ProjectLanguage::Java,
+            ProjectLanguage::DotNet,
+            ProjectLanguage::Elixir,
+            ProjectLanguage::Deno,
+            ProjectLanguage::Bun,
+            ProjectLanguage::Cpp,
+        ]
+    );
+    assert_eq!(
+        ProjectLanguage::Java.global_config_paths(),
+        vec![".m2", ".gradle"]
+    );
+    assert_eq!(ProjectLanguage::DotNet.tool(), "dotnet");
+}
+
+#[test]
+fn dotnet_is_not_detected_from_unrelated_files() {
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("legacy.csproj")).unwrap();
+    fs::write(tmp.path().join("README.md"), "").unwrap();
+    assert!(detect_project_languages(tmp.path()).is_empty());
+}
+
+#[test]
+fn toolchain_names_parse() {
+    assert_eq!(
+        ProjectLanguage::from_name("kotlin"),
+        Some(ProjectLanguage::Java)
+    );
+    assert_eq!(
+        ProjectLanguage::from_name(".NET"),
+        Some(ProjectLanguage::DotNet)
+    );
+    assert_eq!(
+        ProjectLanguage::from_name("c++"),
+        Some(ProjectLanguage::Cpp)
+    );
+    assert_eq!(ProjectLanguage::from_name("cobol"), None);
+}
+
+#[test]
+fn scan_finds_languages_in_monorepo_subdirectories() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    for dir in [
+        "services/api",
+        "web",
+        "generated",
+        "legacy",
+        "web/node_modules/dep",
+        "a/b/c/d",
+    ] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    fs::write(root.join("services/api/go.mod"), "module api\n").unwrap();
+    fs::write(root.join("web/package.json"), "{}").unwrap();
+    fs::write(root.join("web/node_modules/dep/package.json"), "{}").unwrap();
+    fs::write(root.join(".gitignore"), "generated/\n").unwrap();
+    fs::write(root.join("generated/Cargo.toml"), "").unwrap();
+    fs::write(root.join("legacy/pom.xml"), "").unwrap();
+    fs::write(root.join("a/b/c/d/Gemfile"), "").unwrap();
+
+    let options = ScanOptions {
+        exclude: vec!["legacy".to_string()],
+        ..Default::default()
+    };
+    let detected = scan_project_languages(root, &options).unwrap();
+    assert_eq!(
+        detected,
+        vec![
+            DetectedLanguage {
+                language: ProjectLanguage::Go,
+                paths: vec![PathBuf::from("services/api")],
+            },
+            DetectedLanguage {
+                language: ProjectLanguage::NodeJs,
+                paths: vec![PathBuf::from("web")],
+            },
+        ]
+    );
+
+    let deeper = ScanOptions {
+        max_depth: 4,
+        exclude: vec!["legacy".to_string()],
+    };
+    let detected = scan_project_languages(root, &deeper).unwrap();
+    assert!(detected
+        .iter()
+        .any(|d| d.language == ProjectLanguage::Ruby && d.paths == vec![PathBuf::from("a/b/c/d")]));
+}
+
+#[test]
+fn scan_reports_root_as_empty_path() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("Cargo.toml"), "").unwrap();
+    fs::create_dir(tmp.path().join("crates")).unwrap();
+    fs::create_dir(tmp.path().join("crates/core")).unwrap();
+    fs::write(tmp.path().join("crates/core/Cargo.toml"), "").unwrap();
+
+    let detected = scan_project_languages(tmp.path(), &ScanOptions::default()).unwrap();
+    assert_eq!(
+        detected,
+        vec![DetectedLanguage {
+            language: ProjectLanguage::Rust,
+            paths: vec![PathBuf::new(), PathBuf::from("crates/core")],
+        }]
+    );
+}
+
+#[test]
+fn node_package_dirs_include_each_package() {
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("node_modules")).unwrap();
+    let detected = vec![DetectedLanguage {
+        language: ProjectLanguage::NodeJs,
+        paths: vec![PathBuf::from("apps/web"), PathBuf::from("packages/ui")],
+    }];
+    assert_eq!(
+        node_package_dirs(tmp.path(), &detected),
+        vec![
+            tmp.path().to_path_buf(),
+            tmp.path().join("apps/web"),
+            tmp.path().join("packages/ui"),
+        ]
+    );
+}
@@ -1,3 +1,6 @@
+#[path = "../src/agents.rs"]
+mod agents;
+
 #[path = "../src/config.rs"]
 mod config;
 
@@ -7,13 +10,25 @@ mod cli;
 #[path = "../src/settings.rs"]
 mod settings;
 
+#[path = "../src/engine.rs"]
+mod engine;
+
 #[path = "../src/language.rs"]
 mod language;
 
+#[path = "../src/toolchain.rs"]
+mod toolchain;
+
+#[path = "../src/proxy.rs"]
+mod proxy;
+
+#[path = "../src/secrets.rs"]
+mod secrets;
+
 #[path = "../src/container/mod.rs"]
 mod container;
 
-use cli::Agent;
+use agents::AgentRegistry;
 use std::fs;
 use tempfile::tempdir;
 
@@ -23,7 +38,10 @@ fn test_generate_container_name_sanitizes_directory() {
     let project_dir = tmp_dir.path().join("My Project");
     fs::create_dir(&project_dir).expect("create project dir");
 
-    let name = container::generate_container_name(&project_dir, &Agent::Claude);
+    let name = container::generate_container_name(
+        &project_dir,
+        &AgentRegistry::builtin().resolve(None).unwrap(),
+    );
 
     assert!(name.starts_with("csb-claude-my-project-"));
 }
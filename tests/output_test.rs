@@ -0,0 +1,38 @@
+use codesandbox::container::ContainerInfo;
+use codesandbox::output::{web_url, ContainerSummary};
+
+#[test]
+fn web_url_carries_session_token() {
+    assert_eq!(
+        web_url("http://example.com:6789", "csb-demo", "csb-demo.nonce.tag"),
+        "http://example.com:6789/container/csb-demo?token=csb-demo.nonce.tag"
+    );
+}
+
+#[test]
+fn container_summary_serializes_labels() {
+    let inspect = r#"[{
+        "Created": "2024-01-01T00:00:00Z",
+        "State": {"Status": "running"},
+        "Config": {"Labels": {
+            "codesandbox.project": "/work/demo",
+            "codesandbox.agent": "claude",
+            "codesandbox.isolated": "true"
+        }}
+    }]"#;
+    let info = ContainerInfo::from_inspect("csb-demo", inspect).unwrap();
+    let summary = ContainerSummary::new(&info, "http://localhost:6789", "csb-demo.nonce.tag");
+    let value = serde_json::to_value(&summary).unwrap();
+
+    assert_eq!(value["name"], "csb-demo");
+    assert_eq!(value["project_path"], "/work/demo");
+    assert_eq!(value["agent"], "claude");
+    assert_eq!(value["status"], "running");
+    assert_eq!(value["isolated"], true);
+    assert_eq!(
+        value["web_url"],
+        "http://localhost:6789/container/csb-demo?token=csb-demo.nonce.tag"
+    );
+    assert!(value["mounts"].as_array().unwrap().is_empty());
+    assert!(value.get("limits").is_some());
+}
@@ -0,0 +1,146 @@
+use codesandbox::proxy::{serve_proxy, Allowlist, Resolver};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Stand-in for a real upstream: answers every connection with a fixed body.
+async fn start_target() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let first_line = request.lines().next().unwrap_or("").to_string();
+                let body = format!("hello {}", first_line);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.ok();
+            });
+        }
+    });
+    addr
+}
+
+async fn start_proxy(allowed: &[&str]) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let allowlist = Allowlist::new(allowed.iter().copied());
+    let resolver: Arc<Resolver> = Arc::new(move |_| Some(allowlist.clone()));
+    tokio::spawn(serve_proxy(listener, resolver));
+    addr
+}
+
+async fn read_all(stream: &mut TcpStream) -> String {
+    let mut out = Vec::new();
+    stream.read_to_end(&mut out).await.unwrap();
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[test]
+fn allowlist_matches_hosts_and_subdomains() {
+    let allowlist = Allowlist::new(["api.anthropic.com", "*.githubusercontent.com"]);
+    assert!(allowlist.allows("api.anthropic.com"));
+    assert!(allowlist.allows("API.Anthropic.com."));
+    assert!(allowlist.allows("objects.githubusercontent.com"));
+    assert!(!allowlist.allows("githubusercontent.com"));
+    assert!(!allowlist.allows("evilgithubusercontent.com"));
+    assert!(!allowlist.allows("anthropic.com"));
+
+    let from_label = Allowlist::from_label(&allowlist.to_label());
+    assert_eq!(from_label, allowlist);
+}
+
+#[tokio::test]
+async fn connect_to_allowed_host_is_tunnelled() {
+    let target = start_target().await;
+    let proxy = start_proxy(&["localhost"]).await;
+
+    let mut client = TcpStream::connect(proxy).await.unwrap();
+    client
+        .write_all(
+            format!(
+                "CONNECT localhost:{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                target.port()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut buf = [0u8; 39];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf[..], b"HTTP/1.1 200 Connection Established\r\n\r\n");
+
+    client
+        .write_all(b"GET /inside HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_all(&mut client).await;
+    assert!(
+        response.ends_with("hello GET /inside HTTP/1.1"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn connect_to_other_host_is_denied() {
+    let proxy = start_proxy(&["localhost"]).await;
+
+    let mut client = TcpStream::connect(proxy).await.unwrap();
+    client
+        .write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_all(&mut client).await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{response}");
+}
+
+#[tokio::test]
+async fn plain_http_requests_are_forwarded_in_origin_form() {
+    let target = start_target().await;
+    let proxy = start_proxy(&["localhost"]).await;
+
+    let mut client = TcpStream::connect(proxy).await.unwrap();
+    let request = format!(
+        "GET http://localhost:{}/simple?x=1 HTTP/1.1\r\nHost: localhost\r\nProxy-Authorization: Basic eDp5\r\n\r\n",
+        target.port()
+    );
+    client.write_all(request.as_bytes()).await.unwrap();
+    let response = read_all(&mut client).await;
+    assert!(
+        response.ends_with("hello GET /simple?x=1 HTTP/1.1"),
+        "{response}"
+    );
+
+    let mut client = TcpStream::connect(proxy).await.unwrap();
+    client
+        .write_all(b"GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_all(&mut client).await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{response}");
+}
+
+#[tokio::test]
+async fn unknown_clients_are_denied() {
+    let target = start_target().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = listener.local_addr().unwrap();
+    let resolver: Arc<Resolver> = Arc::new(|_| None);
+    tokio::spawn(serve_proxy(listener, resolver));
+
+    let mut client = TcpStream::connect(proxy).await.unwrap();
+    client
+        .write_all(format!("CONNECT localhost:{} HTTP/1.1\r\n\r\n", target.port()).as_bytes())
+        .await
+        .unwrap();
+    let response = read_all(&mut client).await;
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{response}");
+}
@@ -1,38 +1,99 @@
 #[path = "../src/cli.rs"]
 mod cli;
 
+#[path = "../src/agents.rs"]
+mod agents;
+
 #[path = "../src/config.rs"]
 mod config;
 
+#[path = "../src/engine.rs"]
+mod engine;
+
 #[path = "../src/language.rs"]
 mod language;
 
+#[path = "../src/toolchain.rs"]
+mod toolchain;
+
+#[path = "../src/proxy.rs"]
+mod proxy;
+
+#[path = "../src/secrets.rs"]
+mod secrets;
+
 #[path = "../src/settings.rs"]
 mod settings;
 
 #[path = "../src/container/mod.rs"]
 mod container;
 
+use agents::{Agent, AgentRegistry};
 use std::path::Path;
 
+fn agent(name: &str) -> Agent {
+    AgentRegistry::builtin().resolve(Some(name)).unwrap()
+}
+
 #[test]
 fn build_command_includes_continue() {
-    let cmd = container::build_agent_command(
-        Path::new("/project"),
-        &cli::Agent::Claude,
-        true,
-        None,
-    );
+    let cmd =
+        container::build_agent_command(Path::new("/project"), &agent("claude"), true).unwrap();
     assert!(cmd.contains("claude --continue"));
 }
 
 #[test]
 fn build_command_without_continue() {
-    let cmd = container::build_agent_command(
-        Path::new("/project"),
-        &cli::Agent::Claude,
-        false,
-        None,
-    );
+    let cmd =
+        container::build_agent_command(Path::new("/project"), &agent("claude"), false).unwrap();
     assert!(!cmd.contains("--continue"));
 }
+
+#[test]
+fn build_command_uses_agent_binary_and_skip_flag() {
+    let cmd =
+        container::build_agent_command(Path::new("/project"), &agent("cursor"), false).unwrap();
+    assert!(cmd.ends_with("&& cursor-agent"));
+
+    let cmd =
+        container::build_agent_command(Path::new("/project"), &agent("gemini"), false).unwrap();
+    assert!(cmd.ends_with("&& gemini --yolo"));
+}
+
+#[test]
+fn build_command_uses_each_agents_resume_args() {
+    let cmd =
+        container::build_agent_command(Path::new("/project"), &agent("claude"), true).unwrap();
+    assert!(cmd.ends_with("claude --continue --dangerously-skip-permissions"));
+
+    let cmd = container::build_agent_command(Path::new("/project"), &agent("codex"), true).unwrap();
+    assert!(cmd.ends_with("&& codex resume --last"));
+
+    let cmd =
+        container::build_agent_command(Path::new("/project"), &agent("cursor"), true).unwrap();
+    assert!(cmd.ends_with("&& cursor-agent resume"));
+}
+
+#[test]
+fn build_command_rejects_continue_for_agents_without_resume() {
+    let err =
+        container::build_agent_command(Path::new("/project"), &agent("gemini"), true).unwrap_err();
+    assert!(err.to_string().contains("cannot resume"));
+}
+
+#[test]
+fn headless_command_substitutes_prompt_from_stdin() {
+    let cmd =
+        container::build_headless_command(Path::new("/project"), &agent("claude"), "-p {prompt}")
+            .unwrap();
+    assert!(cmd.starts_with("CODESANDBOX_PROMPT=\"$(cat)\" && cd '/project'"));
+    assert!(cmd.ends_with("claude --dangerously-skip-permissions -p \"$CODESANDBOX_PROMPT\" 2>&1"));
+}
+
+#[test]
+fn headless_command_without_placeholder_leaves_prompt_on_stdin() {
+    let cmd = container::build_headless_command(Path::new("/project"), &agent("codex"), "exec -")
+        .unwrap();
+    assert!(!cmd.contains("CODESANDBOX_PROMPT"));
+    assert!(cmd.ends_with("codex exec - 2>&1"));
+}
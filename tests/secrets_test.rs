@@ -0,0 +1,92 @@
+use codesandbox::secrets::{decrypt_store, encrypt_store, load_store, resolve_secrets, save_store};
+use codesandbox::settings::{SecretSettings, SecretTarget};
+use std::collections::BTreeMap;
+use std::env;
+use tempfile::tempdir;
+
+fn spec(env: Option<&str>, command: Option<&str>, store: Option<&str>) -> SecretSettings {
+    SecretSettings {
+        env: env.map(str::to_string),
+        command: command.map(str::to_string),
+        store: store.map(str::to_string),
+        inject: SecretTarget::Env,
+    }
+}
+
+#[test]
+fn store_round_trips_and_rejects_wrong_passphrase() {
+    let mut entries = BTreeMap::new();
+    entries.insert("github".to_string(), "ghp_secret".to_string());
+    entries.insert("npm".to_string(), "npm_token".to_string());
+
+    let data = encrypt_store(&entries, "correct horse").unwrap();
+    assert!(!String::from_utf8_lossy(&data).contains("ghp_secret"));
+    assert_eq!(decrypt_store(&data, "correct horse").unwrap(), entries);
+    assert!(decrypt_store(&data, "wrong").is_err());
+    assert!(decrypt_store(b"not a store", "correct horse").is_err());
+
+    // A fresh salt and nonce every time
+    assert_ne!(encrypt_store(&entries, "correct horse").unwrap(), data);
+}
+
+#[test]
+fn store_files_are_private() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("nested").join("secrets.enc");
+    assert!(load_store(&path, "pw").unwrap().is_empty());
+
+    let mut entries = BTreeMap::new();
+    entries.insert("key".to_string(), "value".to_string());
+    save_store(&path, &entries, "pw").unwrap();
+    assert_eq!(load_store(&path, "pw").unwrap(), entries);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn secrets_resolve_from_env_and_commands() {
+    env::set_var("CSB_TEST_SECRET_VALUE", "from-env");
+    let mut specs = BTreeMap::new();
+    specs.insert(
+        "API_KEY".to_string(),
+        spec(Some("CSB_TEST_SECRET_VALUE"), None, None),
+    );
+    specs.insert(
+        "TOKEN".to_string(),
+        SecretSettings {
+            inject: SecretTarget::File,
+            ..spec(None, Some("printf 'from-command\\n'"), None)
+        },
+    );
+
+    let resolved = resolve_secrets(&specs).unwrap();
+    assert_eq!(resolved.len(), 2);
+    assert_eq!(resolved[0].name, "API_KEY");
+    assert_eq!(resolved[0].value, "from-env");
+    assert_eq!(resolved[1].value, "from-command");
+    assert_eq!(resolved[1].target, SecretTarget::File);
+    assert!(!format!("{:?}", resolved).contains("from-env"));
+}
+
+#[test]
+fn invalid_secret_specs_are_errors() {
+    let check = |name: &str, spec: SecretSettings| {
+        let mut specs = BTreeMap::new();
+        specs.insert(name.to_string(), spec);
+        resolve_secrets(&specs).unwrap_err().to_string()
+    };
+    let err = check("A", spec(Some("X"), Some("true"), None));
+    assert!(err.contains("exactly one"), "{err}");
+    let err = check("A", spec(None, None, None));
+    assert!(err.contains("exactly one"), "{err}");
+    let err = check("A", spec(Some("CSB_TEST_SECRET_UNSET"), None, None));
+    assert!(err.contains("CSB_TEST_SECRET_UNSET is not set"), "{err}");
+    let err = check("A", spec(None, Some("exit 3"), None));
+    assert!(err.contains("command exited"), "{err}");
+    let err = check("../etc/passwd", spec(None, Some("true"), None));
+    assert!(err.contains("names may only contain"), "{err}");
+}
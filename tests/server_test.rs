@@ -7,7 +7,10 @@ use axum::{
 use tower::ServiceExt;
 use tower_http::services::{ServeDir, ServeFile};
 
-use codesandbox::server;
+use codesandbox::server::{
+    self, auth, auth::ServerAuth, browse::WorkspaceRoots, listener, registry::ContainerRegistry,
+};
+use codesandbox::settings::ServerSettings;
 
 #[tokio::test]
 async fn websocket_route_requires_upgrade() {
@@ -77,3 +80,599 @@ async fn serves_frontend_container_route() {
 
     assert_eq!(res.status(), StatusCode::OK);
 }
+
+const SECRET: &str = "test-secret";
+
+fn app(roots: WorkspaceRoots) -> Router {
+    let (shutdown_tx, _shutdown_rx) = tokio::sync::oneshot::channel();
+    server::app(
+        ServerAuth::new(SECRET),
+        roots,
+        ContainerRegistry::default(),
+        shutdown_tx,
+    )
+}
+
+async fn request(uri: &str, token: Option<&str>) -> axum::response::Response {
+    let app = app(WorkspaceRoots::new([std::env::temp_dir()]));
+    let mut req = Request::builder().uri(uri);
+    if let Some(token) = token {
+        req = req.header("Authorization", format!("Bearer {}", token));
+    }
+    app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+}
+
+#[tokio::test]
+async fn routes_reject_missing_and_invalid_tokens() {
+    for uri in [
+        "/api/list",
+        "/api/changed/csb-a",
+        "/shutdown",
+        "/terminal/csb-a",
+        "/",
+    ] {
+        assert_eq!(
+            request(uri, None).await.status(),
+            StatusCode::UNAUTHORIZED,
+            "{uri}"
+        );
+        assert_eq!(
+            request(uri, Some("wrong")).await.status(),
+            StatusCode::UNAUTHORIZED,
+            "{uri}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn container_name_is_no_longer_a_token() {
+    let res = request("/terminal/csb-a?token=csb-a", None).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn session_tokens_are_limited_to_their_container() {
+    let token = ServerAuth::new(SECRET).mint("csb-a").unwrap();
+
+    // Passes auth and only fails for lack of a websocket upgrade
+    let res = request("/terminal/csb-a", Some(&token)).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    for uri in [
+        "/terminal/csb-b",
+        "/api/changed/csb-b",
+        "/api/containers/csb-b",
+        "/api/containers",
+        "/api/list",
+        "/shutdown",
+    ] {
+        assert_eq!(
+            request(uri, Some(&token)).await.status(),
+            StatusCode::FORBIDDEN,
+            "{uri}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn tampered_or_foreign_session_tokens_are_rejected() {
+    let token = ServerAuth::new(SECRET).mint("csb-a").unwrap();
+    let renamed = token.replacen("csb-a", "csb-b", 1);
+    assert_eq!(
+        request("/terminal/csb-b", Some(&renamed)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let foreign = ServerAuth::new("other-secret").mint("csb-a").unwrap();
+    assert_eq!(
+        request("/terminal/csb-a", Some(&foreign)).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn install_secret_grants_every_route() {
+    let dir = tempfile::tempdir().unwrap();
+    let uri = format!("/api/list?path={}", dir.path().display());
+    assert_eq!(request(&uri, Some(SECRET)).await.status(), StatusCode::OK);
+    assert_eq!(
+        request("/shutdown", Some(SECRET)).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn query_token_is_kept_in_a_cookie() {
+    let token = ServerAuth::new(SECRET).mint("csb-a").unwrap();
+    let res = request(&format!("/container/csb-a?token={}", token), None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = res
+        .headers()
+        .get("set-cookie")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(cookie.starts_with(&format!("codesandbox_token={};", token)));
+    assert!(cookie.contains("HttpOnly"));
+
+    let res = app(WorkspaceRoots::new([std::env::temp_dir()]))
+        .oneshot(
+            Request::builder()
+                .uri("/terminal/csb-a?token=csb-a")
+                .header("Cookie", format!("codesandbox_token={}", token))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn secret_is_generated_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("codesandbox").join("server.secret");
+    let secret = auth::load_or_create_secret(&path).unwrap();
+    assert!(secret.len() >= 32);
+    assert_eq!(auth::load_or_create_secret(&path).unwrap(), secret);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn bind_addresses_default_to_loopback() {
+    assert_eq!(
+        listener::bind_addr(&ServerSettings::default()).unwrap(),
+        "127.0.0.1:6789".parse().unwrap()
+    );
+    assert_eq!(
+        listener::parse_bind("8080").unwrap(),
+        "127.0.0.1:8080".parse().unwrap()
+    );
+    assert_eq!(
+        listener::parse_bind(":8080").unwrap(),
+        "127.0.0.1:8080".parse().unwrap()
+    );
+    assert_eq!(
+        listener::parse_bind("0.0.0.0:443").unwrap(),
+        "0.0.0.0:443".parse().unwrap()
+    );
+    assert!(listener::parse_bind("nowhere").is_err());
+}
+
+#[test]
+fn wildcard_addresses_are_reached_over_loopback() {
+    assert_eq!(
+        listener::local_url("0.0.0.0:6789".parse().unwrap(), false),
+        "http://127.0.0.1:6789"
+    );
+    assert_eq!(
+        listener::local_url("[::]:443".parse().unwrap(), true),
+        "https://[::1]:443"
+    );
+}
+
+#[test]
+fn tls_certificate_and_key_go_together() {
+    let settings = ServerSettings {
+        tls: Some(true),
+        tls_cert: Some("cert.pem".to_string()),
+        ..Default::default()
+    };
+    assert!(listener::tls_files(&settings, &[]).is_err());
+
+    let settings = ServerSettings {
+        tls_cert: Some("cert.pem".to_string()),
+        tls_key: Some("key.pem".to_string()),
+        ..Default::default()
+    };
+    assert!(listener::tls_files(&settings, &[]).is_err());
+}
+
+#[test]
+fn self_signed_certificate_is_regenerated_for_new_hosts() {
+    let dir = tempfile::tempdir().unwrap();
+    let hosts = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    let (cert, _) = listener::self_signed_files(dir.path(), &hosts(&["box.local"])).unwrap();
+    let first = std::fs::read(&cert).unwrap();
+
+    listener::self_signed_files(dir.path(), &hosts(&["box.local", "localhost"])).unwrap();
+    assert_eq!(std::fs::read(&cert).unwrap(), first);
+
+    listener::self_signed_files(dir.path(), &hosts(&["10.0.0.5"])).unwrap();
+    assert_ne!(std::fs::read(&cert).unwrap(), first);
+    let names = std::fs::read_to_string(dir.path().join("names")).unwrap();
+    assert!(names.lines().any(|name| name == "10.0.0.5"));
+}
+
+#[tokio::test]
+async fn serve_records_its_address_and_stops_over_tls() {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_var("CODESANDBOX_CONFIG_HOME", dir.path());
+    let settings = ServerSettings {
+        bind: Some("127.0.0.1:0".to_string()),
+        tls: Some(true),
+        ..Default::default()
+    };
+    let serving = tokio::spawn(async move { server::serve(&settings, "localhost").await });
+
+    let mut state = None;
+    for _ in 0..50 {
+        state = server::running_server().await.unwrap();
+        if state.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let state = state.expect("server did not record its address");
+    assert!(state.url.starts_with("https://127.0.0.1:"));
+    assert_eq!(
+        state.tls_cert,
+        Some(dir.path().join("tls").join("cert.pem"))
+    );
+
+    // The pinned self-signed certificate is accepted and the secret required
+    let res = state
+        .client()
+        .unwrap()
+        .get(&state.url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    server::stop().await.unwrap();
+    serving.await.unwrap().unwrap();
+    assert!(!listener::ServerState::path().exists());
+}
+
+async fn list(roots: &WorkspaceRoots, query: &str) -> (StatusCode, serde_json::Value) {
+    let res = app(roots.clone())
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/list{}", query))
+                .header("Authorization", format!("Bearer {}", SECRET))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn list_is_confined_to_workspace_roots() {
+    let outside = tempfile::tempdir().unwrap();
+    let root = tempfile::tempdir().unwrap();
+    let roots = WorkspaceRoots::new([root.path()]);
+    std::fs::create_dir(root.path().join("project")).unwrap();
+    std::os::unix::fs::symlink(outside.path(), root.path().join("escape")).unwrap();
+
+    let (status, entries) = list(&roots, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        entries[0]["path"],
+        root.path().canonicalize().unwrap().display().to_string()
+    );
+
+    let (status, _) = list(&roots, &format!("?path={}", outside.path().display())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = list(&roots, &format!("?path={}/..", root.path().display())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = list(&roots, &format!("?path={}/escape", root.path().display())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = list(&roots, &format!("?path={}/missing", root.path().display())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The escaping link is not even listed
+    let (status, entries) = list(&roots, &format!("?path={}", root.path().display())).await;
+    assert_eq!(status, StatusCode::OK);
+    let names: Vec<_> = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["project"]);
+}
+
+#[tokio::test]
+async fn list_hides_dotfiles_and_describes_projects() {
+    let root = tempfile::tempdir().unwrap();
+    let roots = WorkspaceRoots::new([root.path()]);
+    let project = root.path().join("app");
+    std::fs::create_dir_all(project.join(".git")).unwrap();
+    std::fs::write(project.join("Cargo.toml"), "[package]").unwrap();
+    std::fs::write(root.path().join(".env"), "SECRET=1").unwrap();
+    std::fs::write(root.path().join("notes.txt"), "hello").unwrap();
+
+    let (_, entries) = list(&roots, &format!("?path={}", root.path().display())).await;
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["name"], "app");
+    assert_eq!(entries[0]["is_git_repo"], true);
+    assert_eq!(entries[0]["languages"], serde_json::json!(["Rust"]));
+    assert_eq!(entries[1]["name"], "notes.txt");
+    assert_eq!(entries[1]["size"], 5);
+    assert!(entries[1]["modified"].is_string());
+
+    let (_, entries) = list(
+        &roots,
+        &format!("?path={}&hidden=true", root.path().display()),
+    )
+    .await;
+    assert_eq!(entries.as_array().unwrap().len(), 3);
+}
+
+// Serializes tests that put a fake `docker` on PATH
+static DOCKER_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// A `docker` that knows one sandbox created outside the server, stopped.
+/// Lifecycle commands are recorded in `calls` next to it.
+fn fake_docker(bin_dir: &std::path::Path) {
+    let script = r#"#!/bin/bash
+cmd="$1"
+shift
+case "$cmd" in
+  ps)
+    [[ " $* " == *" -a "* ]] || exit 0
+    [[ " $* " == *" --filter label=codesandbox.project"* ]] || exit 1
+    echo "csb-cli-made"
+    ;;
+  start|stop|rm)
+    [ "${!#}" = "csb-cli-made" ] || exit 1
+    echo "$cmd $*" >> "$(dirname "$0")/calls"
+    ;;
+  logs)
+    [ "${!#}" = "csb-cli-made" ] || exit 1
+    echo "$cmd $*" >> "$(dirname "$0")/calls"
+    echo "agent output"
+    echo "agent warning" >&2
+    ;;
+  inspect)
+    [ "${!#}" = "csb-cli-made" ] || exit 1
+    cat <<'JSON'
+[{"Created": "2024-01-01T00:00:00Z",
+  "State": {"Status": "exited"},
+  "Config": {"Labels": {
+    "codesandbox.project": "/projects/demo",
+    "codesandbox.agent": "codex",
+    "codesandbox.branch": "main",
+    "codesandbox.limits.memory": "4g"
+  }}}]
+JSON
+    ;;
+  exec)
+    echo " M src/lib.rs"
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#;
+    let docker = bin_dir.join("docker");
+    std::fs::write(&docker, script).unwrap();
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+async fn send_json(
+    app: Router,
+    method: &str,
+    uri: &str,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {}", token));
+    let req = match body {
+        Some(body) => req
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => req.body(Body::empty()),
+    };
+    let res = app.oneshot(req.unwrap()).await.unwrap();
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn get_json(app: Router, uri: &str, token: &str) -> (StatusCode, serde_json::Value) {
+    send_json(app, "GET", uri, token, None).await
+}
+
+#[tokio::test]
+async fn containers_created_elsewhere_are_known() {
+    let _lock = DOCKER_LOCK.lock().await;
+    let bin = tempfile::tempdir().unwrap();
+    fake_docker(bin.path());
+    let original_path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var(
+        "PATH",
+        format!("{}:{}", bin.path().display(), original_path),
+    );
+
+    // A fresh registry, as after a server restart
+    let registry = ContainerRegistry::default();
+    let found = registry.scan().await;
+    let (shutdown_tx, _shutdown_rx) = tokio::sync::oneshot::channel();
+    let app = server::app(
+        ServerAuth::new(SECRET),
+        WorkspaceRoots::new([std::env::temp_dir()]),
+        registry,
+        shutdown_tx,
+    );
+    let listed = get_json(app.clone(), "/api/containers", SECRET).await;
+    let single = get_json(app.clone(), "/api/containers/csb-cli-made", SECRET).await;
+    let missing = get_json(app.clone(), "/api/containers/csb-gone", SECRET).await;
+    let token = ServerAuth::new(SECRET).mint("csb-cli-made").unwrap();
+    let own = get_json(app.clone(), "/api/containers/csb-cli-made", &token).await;
+    let changed = get_json(app.clone(), "/api/changed/csb-cli-made", &token).await;
+    let changed_missing = get_json(app.clone(), "/api/changed/csb-gone", SECRET).await;
+
+    std::env::set_var("PATH", original_path);
+
+    assert_eq!(found.unwrap(), 1);
+    assert_eq!(listed.0, StatusCode::OK);
+    let listed = listed.1.as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["name"], "csb-cli-made");
+
+    assert_eq!(single.0, StatusCode::OK);
+    assert_eq!(single.1["project_path"], "/projects/demo");
+    assert_eq!(single.1["agent"], "codex");
+    assert_eq!(single.1["branch"], "main");
+    assert_eq!(single.1["status"], "exited");
+    assert_eq!(single.1["limits"]["memory"], "4g");
+
+    assert_eq!(missing.0, StatusCode::NOT_FOUND);
+    assert_eq!(own.0, StatusCode::OK);
+    assert_eq!(changed.0, StatusCode::OK);
+    assert_eq!(changed.1["files"][0]["path"], "src/lib.rs");
+    assert_eq!(changed_missing.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn containers_can_be_started_over_the_api() {
+    let _lock = DOCKER_LOCK.lock().await;
+    let bin = tempfile::tempdir().unwrap();
+    let script = r#"#!/bin/bash
+case "$1" in
+  run) echo "$@" >> "$(dirname "$0")/calls" ;;
+  inspect)
+    [ "$2" = "-f" ] && exit 0
+    echo '[{"Created": "2024-01-01T00:00:00Z", "State": {"Status": "running"},
+      "Config": {"Labels": {"codesandbox.project": "/projects/demo"}}}]'
+    ;;
+  *) exit 0 ;;
+esac
+"#;
+    let docker = bin.path().join("docker");
+    std::fs::write(&docker, script).unwrap();
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let original_path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var(
+        "PATH",
+        format!("{}:{}", bin.path().display(), original_path),
+    );
+    std::env::set_var("CODESANDBOX_CONFIG_HOME", bin.path());
+
+    let app = app(WorkspaceRoots::new([std::env::temp_dir()]));
+    let started = send_json(
+        app.clone(),
+        "POST",
+        "/api/start",
+        SECRET,
+        Some(serde_json::json!({ "path": project.path(), "agent": "claude" })),
+    )
+    .await;
+
+    std::env::remove_var("CODESANDBOX_CONFIG_HOME");
+    std::env::set_var("PATH", original_path);
+
+    assert_eq!(started.0, StatusCode::OK, "{}", started.1);
+    let name = started.1["container"].as_str().unwrap();
+    let calls = std::fs::read_to_string(bin.path().join("calls")).unwrap();
+    assert!(calls.contains(&format!("--name {name}")), "{calls}");
+}
+
+#[tokio::test]
+async fn containers_can_be_managed_over_the_api() {
+    let _lock = DOCKER_LOCK.lock().await;
+    let bin = tempfile::tempdir().unwrap();
+    fake_docker(bin.path());
+    let project = tempfile::tempdir().unwrap();
+    let original_path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var(
+        "PATH",
+        format!("{}:{}", bin.path().display(), original_path),
+    );
+
+    let app = app(WorkspaceRoots::new([std::env::temp_dir()]));
+    let base = "/api/containers/csb-cli-made";
+    let stopped = send_json(app.clone(), "POST", &format!("{base}/stop"), SECRET, None).await;
+    let started = send_json(app.clone(), "POST", &format!("{base}/start"), SECRET, None).await;
+    let restarted = send_json(
+        app.clone(),
+        "POST",
+        &format!("{base}/restart"),
+        SECRET,
+        None,
+    )
+    .await;
+    let logs = get_json(app.clone(), &format!("{base}/logs?tail=10"), SECRET).await;
+    let bad_tail = get_json(app.clone(), &format!("{base}/logs?tail=ten"), SECRET).await;
+    let removed = send_json(app.clone(), "DELETE", base, SECRET, None).await;
+    let missing = send_json(
+        app.clone(),
+        "POST",
+        "/api/containers/csb-gone/stop",
+        SECRET,
+        None,
+    )
+    .await;
+    let cleaned = send_json(
+        app.clone(),
+        "POST",
+        "/api/cleanup",
+        SECRET,
+        Some(serde_json::json!({ "path": project.path() })),
+    )
+    .await;
+    let outside = send_json(
+        app.clone(),
+        "POST",
+        "/api/cleanup",
+        SECRET,
+        Some(serde_json::json!({ "path": "/" })),
+    )
+    .await;
+    let token = ServerAuth::new(SECRET).mint("csb-cli-made").unwrap();
+    let session = send_json(app.clone(), "POST", &format!("{base}/stop"), &token, None).await;
+    let session_delete = send_json(app.clone(), "DELETE", base, &token, None).await;
+
+    std::env::set_var("PATH", original_path);
+
+    assert_eq!(stopped.0, StatusCode::OK);
+    assert_eq!(stopped.1["name"], "csb-cli-made");
+    assert_eq!(started.0, StatusCode::OK);
+    assert_eq!(restarted.0, StatusCode::OK);
+    assert_eq!(logs.0, StatusCode::OK);
+    assert_eq!(logs.1["logs"], "agent output\nagent warning\n");
+    assert_eq!(bad_tail.0, StatusCode::BAD_REQUEST);
+    assert!(bad_tail.1["error"].is_string());
+    assert_eq!(removed.0, StatusCode::OK);
+    assert_eq!(removed.1["removed"], serde_json::json!(["csb-cli-made"]));
+    assert_eq!(missing.0, StatusCode::NOT_FOUND);
+    assert_eq!(missing.1["error"], "unknown container");
+    assert_eq!(cleaned.0, StatusCode::OK);
+    assert_eq!(cleaned.1["removed"], serde_json::json!(["csb-cli-made"]));
+    assert_eq!(outside.0, StatusCode::FORBIDDEN);
+    assert_eq!(session.0, StatusCode::FORBIDDEN);
+    assert_eq!(session_delete.0, StatusCode::FORBIDDEN);
+
+    let calls = std::fs::read_to_string(bin.path().join("calls")).unwrap();
+    assert_eq!(
+        calls.lines().collect::<Vec<_>>(),
+        [
+            "stop csb-cli-made",
+            "start csb-cli-made",
+            "stop csb-cli-made",
+            "start csb-cli-made",
+            "logs --tail 10 csb-cli-made",
+            "rm -f -v csb-cli-made",
+            "rm -f -v csb-cli-made",
+        ]
+    );
+}
@@ -1,9 +1,11 @@
 #[path = "../src/settings.rs"]
 mod settings;
 
-use settings::load_settings;
+use codesandbox::project_config::load_effective_settings;
+use settings::{parse_settings_json, parse_settings_layer, parse_settings_toml, MountSpec};
 use std::env;
 use std::fs;
+use std::path::PathBuf;
 use tempfile::tempdir;
 
 #[test]
@@ -12,7 +14,9 @@ fn default_when_missing() {
     let original = env::var("CODESANDBOX_CONFIG_HOME").ok();
     env::set_var("CODESANDBOX_CONFIG_HOME", tmp.path());
 
-    let settings = load_settings().unwrap();
+    let settings = load_effective_settings(tmp.path(), Default::default())
+        .unwrap()
+        .settings;
     assert_eq!(settings.auto_remove_minutes, Some(60));
     assert_eq!(
         settings
@@ -67,7 +71,9 @@ fn read_from_file() {
     let original = env::var("CODESANDBOX_CONFIG_HOME").ok();
     env::set_var("CODESANDBOX_CONFIG_HOME", config_dir);
 
-    let settings = load_settings().unwrap();
+    let settings = load_effective_settings(tmp.path(), Default::default())
+        .unwrap()
+        .settings;
     assert_eq!(settings.auto_remove_minutes, Some(30));
     assert_eq!(
         settings
@@ -85,3 +91,122 @@ fn read_from_file() {
     }
 }
 
+#[test]
+fn invalid_file_is_an_error_with_location() {
+    let tmp = tempdir().unwrap();
+    fs::write(
+        tmp.path().join("settings.json"),
+        "{\n  \"auto_remove_minutes\": \"soon\"\n}\n",
+    )
+    .unwrap();
+
+    let original = env::var("CODESANDBOX_CONFIG_HOME").ok();
+    env::set_var("CODESANDBOX_CONFIG_HOME", tmp.path());
+
+    let err = format!(
+        "{:#}",
+        load_effective_settings(tmp.path(), Default::default()).unwrap_err()
+    );
+    assert!(err.contains("settings.json:2:"), "{err}");
+    assert!(err.contains("invalid type"), "{err}");
+
+    if let Some(val) = original {
+        env::set_var("CODESANDBOX_CONFIG_HOME", val);
+    } else {
+        env::remove_var("CODESANDBOX_CONFIG_HOME");
+    }
+}
+
+#[test]
+fn unknown_keys_are_warnings_with_suggestions() {
+    let (settings, warnings) =
+        parse_settings_json(r#"{ "web_hots": "devbox", "colour": true, "web": true }"#).unwrap();
+    assert_eq!(settings.web, Some(true));
+    assert_eq!(
+        warnings,
+        vec![
+            "unknown key `web_hots` (did you mean `web_host`?)".to_string(),
+            "unknown key `colour`".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn toml_errors_report_line_and_column() {
+    let err = parse_settings_toml("agent = \"gemini\"\nmounts = 3\n")
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("2:10:"), "{err}");
+}
+
+#[test]
+fn layers_keep_only_the_keys_they_set() {
+    let path = PathBuf::from("/project/.codesandbox.toml");
+    let (layer, warnings) = parse_settings_layer(
+        &path,
+        "agent = \"gemini\"\ncolour = true\n\n[limits]\ncpus = 2\n",
+    )
+    .unwrap();
+    assert_eq!(
+        layer.keys().collect::<Vec<_>>(),
+        vec!["agent", "colour", "limits"]
+    );
+    assert_eq!(layer["limits"], serde_json::json!({ "cpus": 2 }));
+    assert_eq!(warnings, vec!["unknown key `colour`".to_string()]);
+
+    let err = parse_settings_layer(&path, "agent = \"gemini\"\nmounts = 3\n")
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("/project/.codesandbox.toml:2:10:"), "{err}");
+
+    let err = parse_settings_layer(&PathBuf::from("settings.json"), "{ \"web\": ")
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("settings.json:1:"), "{err}");
+}
+
+#[test]
+fn mount_specs_parse_paths_and_modes() {
+    let mount = MountSpec::parse("/src/api").unwrap();
+    assert_eq!(mount.host, PathBuf::from("/src/api"));
+    assert_eq!(mount.container, PathBuf::from("/src/api"));
+    assert!(mount.read_only);
+    assert_eq!(mount.volume_arg(), "/src/api:/src/api:ro");
+    assert_eq!(mount.to_string(), "/src/api:ro");
+
+    let mount = MountSpec::parse("/tmp/scratch:rw").unwrap();
+    assert_eq!(mount.container, PathBuf::from("/tmp/scratch"));
+    assert!(!mount.read_only);
+    assert_eq!(mount.volume_arg(), "/tmp/scratch:/tmp/scratch");
+
+    let mount = MountSpec::parse("../shared:/shared:rw").unwrap();
+    assert_eq!(mount.host, PathBuf::from("../shared"));
+    assert_eq!(mount.container, PathBuf::from("/shared"));
+    assert_eq!(mount.to_string(), "../shared:/shared:rw");
+
+    assert!(MountSpec::parse("/a:relative").is_err());
+    assert!(MountSpec::parse(":/b").is_err());
+    assert!(MountSpec::parse("/a:/b:/c").is_err());
+}
+
+#[test]
+fn mount_specs_canonicalize_the_host_path() {
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("shared")).unwrap();
+    let canonical = fs::canonicalize(tmp.path().join("shared")).unwrap();
+
+    let spec = format!("{}/./shared", tmp.path().display());
+    let mount = MountSpec::parse(&spec).unwrap().canonicalize().unwrap();
+    assert_eq!(mount.host, canonical);
+    assert_eq!(mount.container, canonical);
+
+    let spec = format!("{}/./shared:/shared:rw", tmp.path().display());
+    let mount = MountSpec::parse(&spec).unwrap().canonicalize().unwrap();
+    assert_eq!(mount.host, canonical);
+    assert_eq!(mount.container, PathBuf::from("/shared"));
+
+    assert!(MountSpec::parse("/does/not/exist")
+        .unwrap()
+        .canonicalize()
+        .is_err());
+}
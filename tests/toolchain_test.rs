@@ -0,0 +1,175 @@
+use codesandbox::language::{DetectedLanguage, ProjectLanguage};
+use codesandbox::toolchain::{
+    extract_version, install_cmd, parse_go_mod, parse_node_version, parse_rust_toolchain,
+    read_pinned_versions, version_satisfies, PinnedVersion,
+};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::tempdir;
+
+#[test]
+fn parses_version_files() {
+    assert_eq!(
+        parse_rust_toolchain("[toolchain]\nchannel = \"1.78.0\"\ncomponents = [\"clippy\"]\n"),
+        Some("1.78.0".to_string())
+    );
+    assert_eq!(
+        parse_rust_toolchain("nightly-2024-05-01\n"),
+        Some("nightly-2024-05-01".to_string())
+    );
+    assert_eq!(
+        parse_node_version("v20.11.0\n"),
+        Some("20.11.0".to_string())
+    );
+    assert_eq!(parse_node_version("lts/*\n"), Some("lts".to_string()));
+    assert_eq!(parse_node_version("lts/iron\n"), Some("iron".to_string()));
+    assert_eq!(
+        parse_go_mod("module example.com/api\n\ngo 1.22\n"),
+        Some("1.22.0".to_string())
+    );
+    assert_eq!(
+        parse_go_mod("module m\n\ngo 1.20\n"),
+        Some("1.20".to_string())
+    );
+    assert_eq!(
+        parse_go_mod("module m\n\ngo 1.22\n\ntoolchain go1.22.3\n"),
+        Some("1.22.3".to_string())
+    );
+}
+
+#[test]
+fn reads_pins_from_root_and_detected_packages() {
+    let tmp = tempdir().unwrap();
+    let root = tmp.path();
+    fs::create_dir_all(root.join("services/api")).unwrap();
+    fs::create_dir_all(root.join("web")).unwrap();
+    fs::write(
+        root.join(".tool-versions"),
+        "nodejs 18.19.0\npython 3.11.8 # comment\nerlang 26.2\n",
+    )
+    .unwrap();
+    fs::write(root.join(".nvmrc"), "20\n").unwrap();
+    fs::write(
+        root.join("services/api/go.mod"),
+        "module api\n\ngo 1.22.1\n",
+    )
+    .unwrap();
+    fs::write(root.join("web/.node-version"), "18\n").unwrap();
+
+    let detected = vec![
+        DetectedLanguage {
+            language: ProjectLanguage::Go,
+            paths: vec![PathBuf::from("services/api")],
+        },
+        DetectedLanguage {
+            language: ProjectLanguage::NodeJs,
+            paths: vec![PathBuf::from("web")],
+        },
+    ];
+    let pins = read_pinned_versions(root, &detected);
+    assert_eq!(
+        pins,
+        vec![
+            PinnedVersion {
+                language: ProjectLanguage::NodeJs,
+                version: "20".to_string(),
+                source: PathBuf::from(".nvmrc"),
+            },
+            PinnedVersion {
+                language: ProjectLanguage::Python,
+                version: "3.11.8".to_string(),
+                source: PathBuf::from(".tool-versions"),
+            },
+            PinnedVersion {
+                language: ProjectLanguage::Go,
+                version: "1.22.1".to_string(),
+                source: PathBuf::from("services/api/go.mod"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn compares_pinned_and_provided_versions() {
+    assert_eq!(
+        extract_version("rustc 1.79.0 (129f3b996 2024-06-10)"),
+        Some("1.79.0".to_string())
+    );
+    assert_eq!(
+        extract_version("go version go1.24.5 linux/amd64"),
+        Some("1.24.5".to_string())
+    );
+    assert_eq!(extract_version("v22.3.0\n"), Some("22.3.0".to_string()));
+
+    assert!(version_satisfies(ProjectLanguage::Python, "3.12", "3.12.4"));
+    assert!(!version_satisfies(
+        ProjectLanguage::Python,
+        "3.12",
+        "3.10.12"
+    ));
+    assert!(!version_satisfies(ProjectLanguage::NodeJs, "20", "22.3.0"));
+    assert!(version_satisfies(ProjectLanguage::Go, "1.22.1", "1.24.5"));
+    assert!(!version_satisfies(ProjectLanguage::Go, "1.25.0", "1.24.5"));
+    assert!(!version_satisfies(
+        ProjectLanguage::Rust,
+        "stable",
+        "1.79.0"
+    ));
+    // Channels compare with the channel recorded at install time
+    assert!(version_satisfies(ProjectLanguage::Rust, "stable", "stable"));
+    assert!(version_satisfies(ProjectLanguage::NodeJs, "lts", "lts"));
+    assert!(!version_satisfies(ProjectLanguage::NodeJs, "iron", "lts"));
+}
+
+#[test]
+fn only_root_pins_change_the_container_toolchain() {
+    let root = PinnedVersion {
+        language: ProjectLanguage::Rust,
+        version: "1.79.0".to_string(),
+        source: PathBuf::from("rust-toolchain.toml"),
+    };
+    assert!(install_cmd(&root)
+        .unwrap()
+        .ends_with("&& rustup default 1.79.0"));
+
+    let nested = PinnedVersion {
+        source: PathBuf::from("crates/legacy/rust-toolchain"),
+        ..root
+    };
+    let cmd = install_cmd(&nested).unwrap();
+    assert!(cmd.starts_with("rustup toolchain install 1.79.0 "));
+    assert!(!cmd.contains("rustup default"), "{cmd}");
+
+    let nested_python = PinnedVersion {
+        language: ProjectLanguage::Python,
+        version: "3.11".to_string(),
+        source: PathBuf::from("tools/.python-version"),
+    };
+    assert_eq!(
+        install_cmd(&nested_python).as_deref(),
+        Some("uv python install 3.11")
+    );
+    for (language, source) in [
+        (ProjectLanguage::NodeJs, "web/.nvmrc"),
+        (ProjectLanguage::Go, "services/api/go.mod"),
+    ] {
+        let pin = PinnedVersion {
+            language,
+            version: "20".to_string(),
+            source: PathBuf::from(source),
+        };
+        assert_eq!(install_cmd(&pin), None, "{source}");
+    }
+}
+
+#[test]
+fn installed_channels_are_recorded() {
+    let pin = PinnedVersion {
+        language: ProjectLanguage::NodeJs,
+        version: "lts".to_string(),
+        source: PathBuf::from(".nvmrc"),
+    };
+    let cmd = install_cmd(&pin).unwrap();
+    assert!(cmd.starts_with("sudo npm install -g n && sudo n lts && "));
+    assert!(cmd.ends_with("echo lts > \"$HOME/.cache/codesandbox/toolchains/npm\""));
+}
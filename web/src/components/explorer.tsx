@@ -8,23 +8,36 @@ interface DirEntry {
   name: string;
   path: string;
   is_dir: boolean;
+  size: number;
+  modified: string | null;
+  is_git_repo: boolean;
+  languages: string[];
 }
 
 export default function Explorer() {
-  const [path, setPath] = useState('/');
+  // An empty path lists the workspace roots the server allows
+  const [path, setPath] = useState('');
+  const [roots, setRoots] = useState<string[]>([]);
   const navigate = useNavigate();
 
   const { data, isLoading, error } = useQuery<DirEntry[]>({
     queryKey: ['list', path],
     queryFn: async () => {
-      const res = await fetch(`/api/list?path=${encodeURIComponent(path)}`);
+      const query = path ? `?path=${encodeURIComponent(path)}` : '';
+      const res = await fetch(`/api/list${query}`);
       if (!res.ok) throw new Error('failed');
-      return res.json();
+      const entries: DirEntry[] = await res.json();
+      if (!path) setRoots(entries.map(e => e.path));
+      return entries;
     },
   });
 
   const goUp = () => {
-    if (path === '/') return;
+    if (!path) return;
+    if (roots.includes(path)) {
+      setPath('');
+      return;
+    }
     const parent = path.replace(/\/?[^/]+$/, '') || '/';
     setPath(parent);
   };
@@ -51,9 +64,11 @@ export default function Explorer() {
           <Button variant="secondary" onClick={goUp}>
             Up
           </Button>
-          <Button onClick={startHere}>Start Here</Button>
+          <Button onClick={startHere} disabled={!path}>
+            Start Here
+          </Button>
         </div>
-        <span className="text-sm text-gray-600">{path}</span>
+        <span className="text-sm text-gray-600">{path || 'Workspace roots'}</span>
       </CardHeader>
       <CardContent>
         <ul className="space-y-1">
@@ -66,6 +81,14 @@ export default function Explorer() {
               >
                 {d.name}
               </Button>
+              {d.is_git_repo && (
+                <span className="ml-2 text-xs text-gray-500">git</span>
+              )}
+              {d.languages.length > 0 && (
+                <span className="ml-2 text-xs text-gray-500">
+                  {d.languages.join(', ')}
+                </span>
+              )}
             </li>
           ))}
         </ul>
@@ -87,9 +87,9 @@ export default function Terminal({ containerName }: TerminalProps) {
             }, 100);
 
             const protocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
-            const token =
-                new URLSearchParams(window.location.search).get('token') ||
-                activeContainer;
+            // Without a token in the URL the server falls back to the
+            // session cookie it set when the page was opened with one
+            const token = new URLSearchParams(window.location.search).get('token');
             // Forward auto-run params to the server so it can inject them immediately
             const pageParams = new URLSearchParams(window.location.search);
             const run = pageParams.get('run');
@@ -97,7 +97,7 @@ export default function Terminal({ containerName }: TerminalProps) {
             const cwd = pageParams.get('cwd');
             const cwdB64 = pageParams.get('cwd_b64');
             const wsParams = new URLSearchParams();
-            wsParams.set('token', token);
+            if (token) wsParams.set('token', token);
             if (runB64) wsParams.set('run_b64', runB64);
             else if (run) wsParams.set('run', run);
             if (cwdB64) wsParams.set('cwd_b64', cwdB64);